@@ -0,0 +1,203 @@
+//! `#[derive(IntoAttrs)]` for `dot_dsl`, re-exported there behind the `derive` feature.
+//!
+//! Written against `proc_macro` alone, so it only understands what it needs to: a
+//! non-generic struct with named fields, and `#[attr(...)]` on those fields.
+
+use proc_macro::{Delimiter, TokenStream, TokenTree};
+
+/// Implement `dot_dsl::graph::IntoAttrs` with one attribute per field, keyed by the
+/// field name and valued by its `Display` output, in field order. `Option` fields only
+/// give an attribute when they are `Some`.
+///
+/// Fields take `#[attr(skip)]` to be left out, `#[attr(rename = "key")]` for another
+/// key and `#[attr(format = "{:.2}")]` for a `format!` string to use instead of `"{}"`.
+#[proc_macro_derive(IntoAttrs, attributes(attr))]
+pub fn derive_into_attrs(input: TokenStream) -> TokenStream {
+    let generated = match parse_struct(input) {
+        Ok((name, fields)) => implementation(&name, &fields),
+        Err(message) => format!("::core::compile_error!({message:?});"),
+    };
+    generated.parse().expect("generated code parses")
+}
+
+struct Field {
+    name: String,
+    key: String,
+    format: String,
+    optional: bool,
+}
+
+fn parse_struct(input: TokenStream) -> Result<(String, Vec<Field>), String> {
+    let mut tokens = input.into_iter().peekable();
+    while let Some(token) = tokens.next() {
+        if !matches!(&token, TokenTree::Ident(ident) if ident.to_string() == "struct") {
+            if matches!(&token, TokenTree::Ident(ident)
+                if ident.to_string() == "enum" || ident.to_string() == "union")
+            {
+                return Err("IntoAttrs can only be derived for structs".to_string());
+            }
+            continue;
+        }
+        let Some(TokenTree::Ident(name)) = tokens.next() else {
+            return Err("IntoAttrs expected a struct name".to_string());
+        };
+        return match tokens.next() {
+            Some(TokenTree::Group(body)) if body.delimiter() == Delimiter::Brace => {
+                Ok((name.to_string(), parse_fields(body.stream())?))
+            }
+            Some(TokenTree::Punct(punct)) if punct.as_char() == '<' => {
+                Err("IntoAttrs doesn't support generic structs".to_string())
+            }
+            _ => Err("IntoAttrs needs a struct with named fields".to_string()),
+        };
+    }
+    Err("IntoAttrs can only be derived for structs".to_string())
+}
+
+/// Fields are split on the commas outside any `<...>`, since angle brackets don't
+/// group tokens the way other brackets do. The `>` of a `->` doesn't close one.
+fn parse_fields(body: TokenStream) -> Result<Vec<Field>, String> {
+    let mut fields = Vec::new();
+    let mut current: Vec<TokenTree> = Vec::new();
+    let mut depth = 0usize;
+    for token in body {
+        let after_dash =
+            matches!(current.last(), Some(TokenTree::Punct(punct)) if punct.as_char() == '-');
+        match &token {
+            TokenTree::Punct(punct) if punct.as_char() == '<' => depth += 1,
+            TokenTree::Punct(punct) if punct.as_char() == '>' && !after_dash => {
+                depth = depth.saturating_sub(1)
+            }
+            TokenTree::Punct(punct) if punct.as_char() == ',' && depth == 0 => {
+                fields.extend(parse_field(std::mem::take(&mut current))?);
+                continue;
+            }
+            _ => {}
+        }
+        current.push(token);
+    }
+    fields.extend(parse_field(current)?);
+    Ok(fields)
+}
+
+/// One field: its attributes, visibility, name, a colon and the type. `None` when the
+/// field is skipped, or when there was nothing after the last comma.
+fn parse_field(tokens: Vec<TokenTree>) -> Result<Option<Field>, String> {
+    let mut tokens = tokens.into_iter().peekable();
+    let (mut key, mut format, mut skip) = (None, None, false);
+    while matches!(tokens.peek(), Some(TokenTree::Punct(punct)) if punct.as_char() == '#') {
+        tokens.next();
+        let Some(TokenTree::Group(attribute)) = tokens.next() else {
+            return Err("IntoAttrs expected an attribute after `#`".to_string());
+        };
+        let mut inner = attribute.stream().into_iter();
+        let is_ours =
+            matches!(inner.next(), Some(TokenTree::Ident(ident)) if ident.to_string() == "attr");
+        if !is_ours {
+            continue;
+        }
+        let Some(TokenTree::Group(options)) = inner.next() else {
+            return Err("IntoAttrs expected `#[attr(...)]`".to_string());
+        };
+        for option in split_commas(options.stream()) {
+            match &option[..] {
+                [TokenTree::Ident(ident)] if ident.to_string() == "skip" => skip = true,
+                [TokenTree::Ident(ident), TokenTree::Punct(eq), TokenTree::Literal(value)]
+                    if eq.as_char() == '=' && value.to_string().starts_with('"') =>
+                {
+                    match ident.to_string().as_str() {
+                        "rename" => key = Some(value.to_string()),
+                        "format" => format = Some(value.to_string()),
+                        other => return Err(format!("IntoAttrs doesn't know `{other}`")),
+                    }
+                }
+                _ => {
+                    return Err(
+                        "IntoAttrs expected `skip`, `rename = \"...\"` or `format = \"...\"`"
+                            .to_string(),
+                    )
+                }
+            }
+        }
+    }
+    if matches!(tokens.peek(), Some(TokenTree::Ident(ident)) if ident.to_string() == "pub") {
+        tokens.next();
+        if matches!(tokens.peek(), Some(TokenTree::Group(group)) if group.delimiter() == Delimiter::Parenthesis)
+        {
+            tokens.next();
+        }
+    }
+    let name = match tokens.next() {
+        Some(TokenTree::Ident(name)) => name.to_string(),
+        None => return Ok(None),
+        Some(_) => return Err("IntoAttrs expected a field name".to_string()),
+    };
+    if !matches!(tokens.next(), Some(TokenTree::Punct(colon)) if colon.as_char() == ':') {
+        return Err("IntoAttrs needs a struct with named fields".to_string());
+    }
+    // `Option<T>`, also when written as a path such as `std::option::Option<T>`.
+    let mut optional = false;
+    for token in tokens {
+        match token {
+            TokenTree::Ident(ident) => optional = ident.to_string() == "Option",
+            TokenTree::Punct(punct) if punct.as_char() == ':' => {}
+            _ => break,
+        }
+    }
+    if skip {
+        return Ok(None);
+    }
+    let bare = name.strip_prefix("r#").unwrap_or(&name);
+    Ok(Some(Field {
+        key: key.unwrap_or_else(|| format!("{bare:?}")),
+        format: format.unwrap_or_else(|| "\"{}\"".to_string()),
+        optional,
+        name,
+    }))
+}
+
+fn split_commas(stream: TokenStream) -> Vec<Vec<TokenTree>> {
+    let mut parts = vec![Vec::new()];
+    for token in stream {
+        match &token {
+            TokenTree::Punct(punct) if punct.as_char() == ',' => parts.push(Vec::new()),
+            _ => parts.last_mut().expect("never empty").push(token),
+        }
+    }
+    parts.retain(|part| !part.is_empty());
+    parts
+}
+
+fn implementation(name: &str, fields: &[Field]) -> String {
+    if fields.is_empty() {
+        return format!(
+            "impl ::dot_dsl::graph::IntoAttrs for {name} {{ \
+             fn to_attrs(&self) -> ::std::vec::Vec<(&'static str, ::std::string::String)> {{ \
+             ::std::vec::Vec::new() }} }}"
+        );
+    }
+    let pushes = fields
+        .iter()
+        .map(|field| {
+            let Field {
+                name,
+                key,
+                format,
+                optional,
+            } = field;
+            if *optional {
+                format!(
+                    "if let ::core::option::Option::Some(value) = &self.{name} {{ \
+                     attrs.push(({key}, ::std::format!({format}, value))); }}"
+                )
+            } else {
+                format!("attrs.push(({key}, ::std::format!({format}, self.{name})));")
+            }
+        })
+        .collect::<String>();
+    format!(
+        "impl ::dot_dsl::graph::IntoAttrs for {name} {{ \
+         fn to_attrs(&self) -> ::std::vec::Vec<(&'static str, ::std::string::String)> {{ \
+         let mut attrs = ::std::vec::Vec::new(); {pushes} attrs }} }}"
+    )
+}
@@ -0,0 +1,46 @@
+/* C interface to the dot-dsl crate, built with `--features ffi`.
+ *
+ * Keep in sync with `graph::ffi` in src/lib.rs; the tests check that every
+ * exported function is declared here. */
+
+#ifndef DOT_DSL_H
+#define DOT_DSL_H
+
+#include <stdbool.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Status codes returned by the mutating calls. */
+#define DOT_OK 0
+#define DOT_EINVAL -1    /* null pointer or string not valid UTF-8 */
+#define DOT_EGRAPH -2    /* rejected by the graph, e.g. a duplicate */
+#define DOT_ENOTFOUND -3 /* no such node or edge */
+
+typedef struct DotGraph DotGraph;
+
+/* Create an empty graph; free it with dot_graph_free. */
+DotGraph *dot_graph_new(bool directed);
+/* Parse DOT source, returning NULL if it doesn't parse. */
+DotGraph *dot_graph_from_dot(const char *src);
+void dot_graph_free(DotGraph *graph);
+
+int dot_graph_add_node(DotGraph *graph, const char *name);
+int dot_graph_add_edge(DotGraph *graph, const char *node1, const char *node2);
+int dot_graph_set_attr(DotGraph *graph, const char *key, const char *value);
+int dot_graph_set_node_attr(DotGraph *graph, const char *name, const char *key,
+                            const char *value);
+int dot_graph_set_edge_attr(DotGraph *graph, const char *node1,
+                            const char *node2, const char *key,
+                            const char *value);
+
+/* Render as DOT; free the result with dot_string_free. */
+char *dot_graph_to_dot(const DotGraph *graph);
+void dot_string_free(char *s);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* DOT_DSL_H */
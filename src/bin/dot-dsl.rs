@@ -0,0 +1,245 @@
+//! `dot-dsl`: convert, validate and summarize graphs from the shell.
+//!
+//! Built with `cargo build --features cli`. Every command reads a file, or stdin
+//! when the path is `-`, and writes to stdout.
+
+use std::collections::HashSet;
+use std::fmt::Write as _;
+use std::io::{self, Read, Write};
+use std::process::ExitCode;
+use std::{env, fs};
+
+use dot_dsl::graph::Graph;
+
+const USAGE: &str = "\
+usage: dot-dsl convert <file> [--from dot|edge-list] [--to dot|canonical-dot|edge-list] [--directed]
+       dot-dsl validate <file>...
+       dot-dsl stats <file> [--from dot|edge-list] [--directed]
+
+Edge lists hold one edge per line as two names separated by a comma or
+whitespace; a line with a single name declares a node. Names holding either, or starting with #,
+are double-quoted. Without --from, .csv, .tsv and .txt files are read as edge lists and anything
+else as DOT.";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Dot,
+    CanonicalDot,
+    EdgeList,
+}
+
+impl Format {
+    fn parse(name: &str) -> Result<Self, String> {
+        match name {
+            "dot" => Ok(Format::Dot),
+            "canonical-dot" => Ok(Format::CanonicalDot),
+            "edge-list" => Ok(Format::EdgeList),
+            _ => Err(format!("unknown format `{name}`")),
+        }
+    }
+
+    fn guess(path: &str) -> Self {
+        match path.rsplit_once('.').map(|(_, ext)| ext) {
+            Some("csv" | "tsv" | "txt") => Format::EdgeList,
+            _ => Format::Dot,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Args {
+    paths: Vec<String>,
+    from: Option<Format>,
+    to: Option<Format>,
+    directed: bool,
+}
+
+impl Args {
+    fn parse(args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut parsed = Args::default();
+        let mut args = args.peekable();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--from" | "--to" => {
+                    let value = args.next().ok_or_else(|| format!("{arg} needs a format"))?;
+                    let format = Some(Format::parse(&value)?);
+                    if arg == "--from" {
+                        parsed.from = format;
+                    } else {
+                        parsed.to = format;
+                    }
+                }
+                "--directed" => parsed.directed = true,
+                "-" => parsed.paths.push(arg),
+                _ if arg.starts_with('-') => return Err(format!("unknown option `{arg}`")),
+                _ => parsed.paths.push(arg),
+            }
+        }
+        Ok(parsed)
+    }
+
+    fn single_path(&self) -> Result<&str, String> {
+        match self.paths.as_slice() {
+            [path] => Ok(path),
+            _ => Err("expected exactly one input file".to_string()),
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    let mut args = env::args().skip(1);
+    let command = args.next();
+    let result = Args::parse(args).and_then(|args| match command.as_deref() {
+        Some("convert") => convert(&args),
+        Some("validate") => validate(&args),
+        Some("stats") => stats(&args),
+        Some("-h" | "--help") => {
+            println!("{USAGE}");
+            Ok(ExitCode::SUCCESS)
+        }
+        Some(other) => Err(format!("unknown command `{other}`")),
+        None => Err("missing command".to_string()),
+    });
+    match result {
+        Ok(code) => code,
+        Err(message) => {
+            eprintln!("dot-dsl: {message}\n\n{USAGE}");
+            ExitCode::from(2)
+        }
+    }
+}
+
+fn convert(args: &Args) -> Result<ExitCode, String> {
+    let path = args.single_path()?;
+    let graph = match load(path, args) {
+        Ok(graph) => graph,
+        Err(message) => return Ok(failure(&message)),
+    };
+    let output = match args.to.unwrap_or(Format::Dot) {
+        Format::Dot => graph.to_dot(),
+        Format::CanonicalDot => graph.to_canonical_dot(),
+        Format::EdgeList => match to_edge_list(&graph) {
+            Ok(list) => list,
+            Err(message) => return Ok(failure(&format!("{path}: {message}"))),
+        },
+    };
+    Ok(write_out(&output))
+}
+
+fn validate(args: &Args) -> Result<ExitCode, String> {
+    if args.paths.is_empty() {
+        return Err("expected at least one input file".to_string());
+    }
+    let mut ok = true;
+    for path in &args.paths {
+        match load(path, args) {
+            Ok(_) => println!("{path}: ok"),
+            Err(message) => {
+                ok = false;
+                eprintln!("{message}");
+            }
+        }
+    }
+    Ok(if ok {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    })
+}
+
+fn stats(args: &Args) -> Result<ExitCode, String> {
+    let graph = match load(args.single_path()?, args) {
+        Ok(graph) => graph,
+        Err(message) => return Ok(failure(&message)),
+    };
+    let stats = graph.stats();
+    let output = format!(
+        "nodes: {}\nedges: {}\nself loops: {}\nisolated nodes: {}\ncomponents: {}\n\
+         max degree: {}\ndag: {}\n",
+        stats.node_count,
+        stats.edge_count,
+        stats.self_loops,
+        stats.isolated_nodes,
+        stats.components,
+        stats.max_degree,
+        if stats.is_dag { "yes" } else { "no" },
+    );
+    Ok(write_out(&output))
+}
+
+/// Read and parse `path`, describing any failure as `path: problem`.
+fn load(path: &str, args: &Args) -> Result<Graph, String> {
+    let reader: Box<dyn Read> = if path == "-" {
+        Box::new(io::stdin().lock())
+    } else {
+        Box::new(fs::File::open(path).map_err(|error| format!("{path}: {error}"))?)
+    };
+    match args.from.unwrap_or_else(|| Format::guess(path)) {
+        Format::EdgeList => {
+            Graph::from_edge_list(reader).map(|graph| graph.with_directed(args.directed))
+        }
+        _ => Graph::from_dot_reader(reader),
+    }
+    .map_err(|error| format!("{path}: {error}"))
+}
+
+/// Edges one per line, then nodes no edge touches, so reading it back loses only
+/// attributes, subgraphs and comments, and the direction unless read with
+/// `--directed`. Names that would split or read as a comment are quoted; names with
+/// line breaks can't be written at all.
+fn to_edge_list(graph: &Graph) -> Result<String, String> {
+    let mut out = String::new();
+    let mut touched = HashSet::new();
+    for edge in graph.edges() {
+        let (node1, node2) = (edge.node1(), edge.node2());
+        let _ = writeln!(out, "{},{}", edge_list_name(node1)?, edge_list_name(node2)?);
+        touched.extend([node1, node2]);
+    }
+    for node in graph.nodes() {
+        if !touched.contains(node.name()) {
+            let _ = writeln!(out, "{}", edge_list_name(node.name())?);
+        }
+    }
+    Ok(out)
+}
+
+/// `name` as `parse_edge_list` reads it back.
+fn edge_list_name(name: &str) -> Result<String, String> {
+    if name.contains(['\n', '\r']) {
+        return Err(format!(
+            "{name:?} has a line break, which edge lists can't hold"
+        ));
+    }
+    let plain = !name.is_empty()
+        && !name.starts_with('#')
+        && !name.contains(|c: char| c == ',' || c == '"' || c.is_whitespace());
+    if plain {
+        Ok(name.to_string())
+    } else {
+        Ok(format!(
+            "\"{}\"",
+            name.replace('\\', "\\\\").replace('"', "\\\"")
+        ))
+    }
+}
+
+fn write_out(output: &str) -> ExitCode {
+    let mut stdout = io::stdout().lock();
+    match stdout.write_all(output.as_bytes()).and_then(|()| {
+        if output.ends_with('\n') {
+            Ok(())
+        } else {
+            writeln!(stdout)
+        }
+    }) {
+        Ok(()) => ExitCode::SUCCESS,
+        // A closed pipe (e.g. `| head`) isn't worth reporting.
+        Err(error) if error.kind() == io::ErrorKind::BrokenPipe => ExitCode::SUCCESS,
+        Err(error) => failure(&error.to_string()),
+    }
+}
+
+fn failure(message: &str) -> ExitCode {
+    eprintln!("{message}");
+    ExitCode::FAILURE
+}
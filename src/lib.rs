@@ -2,139 +2,8282 @@
 
 /// The entire `graph` functionality in one module.
 pub mod graph {
-    use std::collections::HashMap;
+    use std::cmp::{Ordering, Reverse};
+    use std::collections::{BinaryHeap, HashMap, HashSet, VecDeque};
+    use std::sync::{Arc, Mutex, MutexGuard, OnceLock, PoisonError, RwLock};
 
     /// We place Node and Edge types in a nested `graph_items` module to match usage in the tests.
     pub mod graph_items {
         pub mod node {
-            // Import the helper from the parent `graph` module.
-            use crate::graph::merge_map_and_list;
+            // Import the helpers from the parent `graph` module.
+            use crate::graph::attrs::Attrs;
+            use crate::graph::graph_items::style::Style;
+            use crate::graph::parser::ParseError;
+            use crate::graph::{
+                format_non_negative, join_styles, parse_attr, split_styles, AttrDiff, Graph,
+                GraphError, IntoAttrs, Payload,
+            };
+            use std::any::Any;
+            use std::borrow::Cow;
             use std::collections::HashMap;
+            use std::str::FromStr;
+            use std::sync::Arc;
 
             #[derive(Debug, PartialEq, Eq, Clone)]
             pub struct Node {
-                name: String,
-                attrs: HashMap<String, String>,
+                name: Cow<'static, str>,
+                attrs: Attrs,
+                comments: Vec<String>,
+                data: Payload,
             }
 
             impl Node {
                 pub fn new(name: &str) -> Self {
+                    Node::named(name.to_string())
+                }
+
+                /// Like `new`, but a `&'static str` name is stored without being copied.
+                pub fn named(name: impl Into<Cow<'static, str>>) -> Self {
+                    Node {
+                        name: name.into(),
+                        attrs: Attrs::new(),
+                        comments: Vec::new(),
+                        data: Payload::default(),
+                    }
+                }
+
+                pub fn with_attrs(self, attrs: &[(&str, &str)]) -> Self {
+                    // Merge the new list into the attrs we own, without copying them
+                    let merged_attrs = self.attrs.merged(attrs);
+                    Node {
+                        attrs: merged_attrs,
+                        ..self
+                    }
+                }
+
+                /// Set one attribute; literals are stored without being copied.
+                pub fn with_attr(
+                    self,
+                    key: impl Into<Cow<'static, str>>,
+                    value: impl Into<Cow<'static, str>>,
+                ) -> Self {
+                    let attrs = self.attrs.inserted(key.into(), value.into());
+                    Node { attrs, ..self }
+                }
+
+                /// Unset one attribute; a no-op when it isn't set.
+                pub fn without_attr(self, key: &str) -> Self {
+                    let attrs = self.attrs.removed(key);
+                    Node { attrs, ..self }
+                }
+
+                pub fn clear_attrs(self) -> Self {
+                    Node {
+                        attrs: Attrs::new(),
+                        ..self
+                    }
+                }
+
+                /// Like `with_attrs`, but drops every attribute not in `attrs`.
+                pub fn replace_attrs(self, attrs: &[(&str, &str)]) -> Self {
+                    self.clear_attrs().with_attrs(attrs)
+                }
+
+                /// Set the attributes `value` gives, in its order.
+                pub fn with_attrs_from(self, value: &impl IntoAttrs) -> Self {
+                    value
+                        .to_attrs()
+                        .into_iter()
+                        .fold(self, |item, (key, value)| item.with_attr(key, value))
+                }
+
+                /// How the attributes would have to change to turn `self` into `other`.
+                pub fn attr_diff<'a>(&'a self, other: &'a Self) -> AttrDiff<'a> {
+                    AttrDiff::between(self.attrs(), other.attrs())
+                }
+
+                /// Attach a comment that is written just above the node statement.
+                pub fn with_comment(mut self, comment: &str) -> Self {
+                    self.comments.push(comment.to_string());
+                    self
+                }
+
+                /// Attach application data, replacing any already attached. It is never
+                /// rendered and clones share it; like the graph's observers, it takes no
+                /// part in comparisons.
+                pub fn with_data<T: Any + Send + Sync>(self, data: T) -> Self {
+                    Node {
+                        data: Payload(Some(Arc::new(data))),
+                        ..self
+                    }
+                }
+
+                /// The attached data, if there is some and it is a `T`.
+                pub fn data<T: Any>(&self) -> Option<&T> {
+                    self.data.0.as_deref()?.downcast_ref()
+                }
+
+                /// Add styles to the comma-separated `style` list, keeping the ones already set.
+                pub fn with_styles(self, styles: &[Style]) -> Self {
+                    let joined = join_styles(self.attr("style"), styles);
+                    self.with_attrs(&[("style", &joined)])
+                }
+
+                pub fn with_penwidth(self, penwidth: f64) -> Result<Self, GraphError> {
+                    self.with_number("penwidth", penwidth)
+                }
+
+                pub fn with_fontsize(self, fontsize: f64) -> Result<Self, GraphError> {
+                    self.with_number("fontsize", fontsize)
+                }
+
+                pub fn with_width(self, width: f64) -> Result<Self, GraphError> {
+                    self.with_number("width", width)
+                }
+
+                pub fn with_height(self, height: f64) -> Result<Self, GraphError> {
+                    self.with_number("height", height)
+                }
+
+                fn with_number(self, key: &str, value: f64) -> Result<Self, GraphError> {
+                    let formatted = format_non_negative(key, value)?;
+                    Ok(self.with_attrs(&[(key, &formatted)]))
+                }
+
+                pub fn attr(&self, key: &str) -> Option<&str> {
+                    self.attrs.get(key)
+                }
+
+                /// The attribute parsed as `T`, e.g. `attr_as::<f64>("fontsize")`.
+                pub fn attr_as<T: FromStr>(&self, key: &str) -> Result<Option<T>, ParseError> {
+                    parse_attr(key, self.attr(key))
+                }
+
+                pub fn styles(&self) -> Vec<Style> {
+                    split_styles(self.attr("style"))
+                }
+
+                pub fn attrs(&self) -> impl Iterator<Item = (&str, &str)> {
+                    self.attrs.iter()
+                }
+
+                /// The attributes Graphviz will draw the node with in `graph`: the graph's
+                /// node defaults, then those of the first subgraph with node defaults that
+                /// lists it, then the node's own.
+                pub fn effective_attrs<'a>(
+                    &'a self,
+                    graph: &'a Graph,
+                ) -> HashMap<&'a str, &'a str> {
+                    let mut attrs = graph
+                        .node_defaults()
+                        .iter()
+                        .map(|(k, v)| (k.as_str(), v.as_str()))
+                        .collect::<HashMap<_, _>>();
+                    if let Some(subgraph) = graph.defaults_owner(self.name()) {
+                        attrs.extend(subgraph.node_defaults());
+                    }
+                    attrs.extend(self.attrs());
+                    attrs
+                }
+
+                pub fn comments(&self) -> &[String] {
+                    &self.comments
+                }
+
+                pub fn name(&self) -> &str {
+                    &self.name
+                }
+
+                /// `other`'s attributes and data win, comments are kept from both.
+                pub(crate) fn merged(self, other: &Node) -> Self {
+                    let attrs = other.attrs().collect::<Vec<_>>();
+                    let mut merged = self.with_attrs(&attrs);
+                    merged.comments.extend(other.comments.iter().cloned());
+                    if other.data.0.is_some() {
+                        merged.data = other.data.clone();
+                    }
+                    merged
+                }
+
+                pub(crate) fn with_name(self, name: &str) -> Self {
                     Node {
-                        name: name.to_string(),
-                        attrs: HashMap::new(),
+                        name: Cow::Owned(name.to_string()),
+                        ..self
+                    }
+                }
+
+                /// Start a node whose name and attributes are checked by `build`.
+                pub fn builder(name: impl Into<Cow<'static, str>>) -> NodeBuilder {
+                    NodeBuilder::new(name)
+                }
+            }
+
+            impl From<&str> for Node {
+                fn from(name: &str) -> Self {
+                    Node::new(name)
+                }
+            }
+
+            /// Builds a `Node` from possibly untrusted input.
+            ///
+            /// Setters never fail; the first problem is kept and returned by `build`.
+            #[derive(Debug, Clone)]
+            pub struct NodeBuilder {
+                node: Result<Node, GraphError>,
+            }
+
+            impl NodeBuilder {
+                /// Rejects empty names and names containing control characters.
+                pub fn new(name: impl Into<Cow<'static, str>>) -> Self {
+                    let name = name.into();
+                    let node = if name.is_empty() || name.chars().any(char::is_control) {
+                        Err(GraphError::InvalidName(name.into_owned()))
+                    } else {
+                        Ok(Node::named(name))
+                    };
+                    NodeBuilder { node }
+                }
+
+                pub fn with_attr(
+                    self,
+                    key: impl Into<Cow<'static, str>>,
+                    value: impl Into<Cow<'static, str>>,
+                ) -> Self {
+                    self.map(|node| Ok(node.with_attr(key, value)))
+                }
+
+                pub fn with_attrs(self, attrs: &[(&str, &str)]) -> Self {
+                    self.map(|node| Ok(node.with_attrs(attrs)))
+                }
+
+                pub fn with_styles(self, styles: &[Style]) -> Self {
+                    self.map(|node| Ok(node.with_styles(styles)))
+                }
+
+                pub fn with_comment(self, comment: &str) -> Self {
+                    self.map(|node| Ok(node.with_comment(comment)))
+                }
+
+                pub fn with_penwidth(self, penwidth: f64) -> Self {
+                    self.map(|node| node.with_penwidth(penwidth))
+                }
+
+                pub fn with_fontsize(self, fontsize: f64) -> Self {
+                    self.map(|node| node.with_fontsize(fontsize))
+                }
+
+                pub fn with_width(self, width: f64) -> Self {
+                    self.map(|node| node.with_width(width))
+                }
+
+                pub fn with_height(self, height: f64) -> Self {
+                    self.map(|node| node.with_height(height))
+                }
+
+                fn map(self, f: impl FnOnce(Node) -> Result<Node, GraphError>) -> Self {
+                    NodeBuilder {
+                        node: self.node.and_then(f),
+                    }
+                }
+
+                pub fn build(self) -> Result<Node, GraphError> {
+                    self.node
+                }
+            }
+        }
+
+        pub mod edge {
+            // Import the helpers from the parent `graph` module.
+            use crate::graph::attrs::{Attrs, Key};
+            use crate::graph::graph_items::style::Style;
+            use crate::graph::parser::ParseError;
+            use crate::graph::{
+                format_non_negative, join_styles, parse_attr, split_styles, AttrDiff, Graph,
+                GraphError, IntoAttrs,
+            };
+            use std::borrow::Cow;
+            use std::collections::HashMap;
+            use std::str::FromStr;
+            use std::sync::Arc;
+
+            #[derive(Debug, PartialEq, Eq, Clone)]
+            pub struct Edge {
+                node1: Key,
+                node2: Key,
+                attrs: Attrs,
+                comments: Vec<String>,
+            }
+
+            impl Edge {
+                pub fn new(node1: &str, node2: &str) -> Self {
+                    Edge::between(node1.to_string(), node2.to_string())
+                }
+
+                /// Like `new`, but `&'static str` endpoints are stored without being copied.
+                pub fn between(
+                    node1: impl Into<Cow<'static, str>>,
+                    node2: impl Into<Cow<'static, str>>,
+                ) -> Self {
+                    Edge::shared(endpoint(node1.into()), endpoint(node2.into()))
+                }
+
+                /// Endpoints shared with other edges, as the edge-list reader does for
+                /// names that repeat.
+                pub(crate) fn shared(node1: Key, node2: Key) -> Self {
+                    Edge {
+                        node1,
+                        node2,
+                        attrs: Attrs::new(),
+                        comments: Vec::new(),
+                    }
+                }
+
+                pub fn with_attrs(self, attrs: &[(&str, &str)]) -> Self {
+                    let merged_attrs = self.attrs.merged(attrs);
+                    Edge {
+                        attrs: merged_attrs,
+                        ..self
+                    }
+                }
+
+                /// Set one attribute; literals are stored without being copied.
+                pub fn with_attr(
+                    self,
+                    key: impl Into<Cow<'static, str>>,
+                    value: impl Into<Cow<'static, str>>,
+                ) -> Self {
+                    let attrs = self.attrs.inserted(key.into(), value.into());
+                    Edge { attrs, ..self }
+                }
+
+                /// Unset one attribute; a no-op when it isn't set.
+                pub fn without_attr(self, key: &str) -> Self {
+                    let attrs = self.attrs.removed(key);
+                    Edge { attrs, ..self }
+                }
+
+                pub fn clear_attrs(self) -> Self {
+                    Edge {
+                        attrs: Attrs::new(),
+                        ..self
+                    }
+                }
+
+                /// Like `with_attrs`, but drops every attribute not in `attrs`.
+                pub fn replace_attrs(self, attrs: &[(&str, &str)]) -> Self {
+                    self.clear_attrs().with_attrs(attrs)
+                }
+
+                /// Set the attributes `value` gives, in its order.
+                pub fn with_attrs_from(self, value: &impl IntoAttrs) -> Self {
+                    value
+                        .to_attrs()
+                        .into_iter()
+                        .fold(self, |item, (key, value)| item.with_attr(key, value))
+                }
+
+                /// How the attributes would have to change to turn `self` into `other`.
+                pub fn attr_diff<'a>(&'a self, other: &'a Self) -> AttrDiff<'a> {
+                    AttrDiff::between(self.attrs(), other.attrs())
+                }
+
+                /// Attach a comment that is written just above the edge statement.
+                pub fn with_comment(mut self, comment: &str) -> Self {
+                    self.comments.push(comment.to_string());
+                    self
+                }
+
+                /// Add styles to the comma-separated `style` list, keeping the ones already set.
+                pub fn with_styles(self, styles: &[Style]) -> Self {
+                    let joined = join_styles(self.attr("style"), styles);
+                    self.with_attrs(&[("style", &joined)])
+                }
+
+                pub fn with_penwidth(self, penwidth: f64) -> Result<Self, GraphError> {
+                    self.with_number("penwidth", penwidth)
+                }
+
+                pub fn with_weight(self, weight: f64) -> Result<Self, GraphError> {
+                    self.with_number("weight", weight)
+                }
+
+                /// The `weight` attribute as a number; `None` when unset or not a number.
+                pub fn weight(&self) -> Option<f64> {
+                    self.attr("weight")?.trim().parse().ok()
+                }
+
+                /// Like [`Edge::with_weight`], in place.
+                pub fn set_weight(&mut self, weight: f64) -> Result<(), GraphError> {
+                    let formatted = format_non_negative("weight", weight)?;
+                    let attrs = std::mem::take(&mut self.attrs);
+                    self.attrs = attrs.inserted(Cow::Borrowed("weight"), Cow::Owned(formatted));
+                    Ok(())
+                }
+
+                pub fn with_capacity(self, capacity: f64) -> Result<Self, GraphError> {
+                    self.with_number("capacity", capacity)
+                }
+
+                /// The `capacity` attribute as a number; `None` when unset or not a number.
+                pub fn capacity(&self) -> Option<f64> {
+                    self.attr("capacity")?.trim().parse().ok()
+                }
+
+                pub fn with_fontsize(self, fontsize: f64) -> Result<Self, GraphError> {
+                    self.with_number("fontsize", fontsize)
+                }
+
+                pub fn with_arrowsize(self, arrowsize: f64) -> Result<Self, GraphError> {
+                    self.with_number("arrowsize", arrowsize)
+                }
+
+                fn with_number(self, key: &str, value: f64) -> Result<Self, GraphError> {
+                    let formatted = format_non_negative(key, value)?;
+                    Ok(self.with_attrs(&[(key, &formatted)]))
+                }
+
+                pub fn attr(&self, key: &str) -> Option<&str> {
+                    self.attrs.get(key)
+                }
+
+                /// The attribute parsed as `T`, e.g. `attr_as::<f64>("fontsize")`.
+                pub fn attr_as<T: FromStr>(&self, key: &str) -> Result<Option<T>, ParseError> {
+                    parse_attr(key, self.attr(key))
+                }
+
+                pub fn styles(&self) -> Vec<Style> {
+                    split_styles(self.attr("style"))
+                }
+
+                /// Identify the edge independently of its endpoints. The id is Graphviz's
+                /// `id` attribute, so it survives a trip through DOT.
+                pub fn with_id(self, id: &str) -> Self {
+                    self.with_attrs(&[("id", id)])
+                }
+
+                pub fn id(&self) -> Option<&str> {
+                    self.attr("id")
+                }
+
+                pub fn attrs(&self) -> impl Iterator<Item = (&str, &str)> {
+                    self.attrs.iter()
+                }
+
+                /// The attributes Graphviz will draw the edge with in `graph`: the graph's
+                /// edge defaults overridden by the edge's own.
+                pub fn effective_attrs<'a>(
+                    &'a self,
+                    graph: &'a Graph,
+                ) -> HashMap<&'a str, &'a str> {
+                    let mut attrs = graph
+                        .edge_defaults()
+                        .iter()
+                        .map(|(k, v)| (k.as_str(), v.as_str()))
+                        .collect::<HashMap<_, _>>();
+                    attrs.extend(self.attrs());
+                    attrs
+                }
+
+                pub fn comments(&self) -> &[String] {
+                    &self.comments
+                }
+
+                pub fn node1(&self) -> &str {
+                    &self.node1
+                }
+
+                pub fn node2(&self) -> &str {
+                    &self.node2
+                }
+
+                /// `other`'s attributes win, comments are kept from both.
+                pub(crate) fn merged(self, other: &Edge) -> Self {
+                    let attrs = other.attrs().collect::<Vec<_>>();
+                    let mut merged = self.with_attrs(&attrs);
+                    merged.comments.extend(other.comments.iter().cloned());
+                    merged
+                }
+
+                pub(crate) fn with_endpoints(self, node1: &str, node2: &str) -> Self {
+                    Edge {
+                        node1: Key::Shared(Arc::from(node1)),
+                        node2: Key::Shared(Arc::from(node2)),
+                        ..self
+                    }
+                }
+
+                /// Start a fluent edge: `Edge::from("a").to("b").build()`.
+                pub fn from(node1: impl Into<Cow<'static, str>>) -> EdgeFrom {
+                    EdgeFrom {
+                        node1: node1.into(),
+                    }
+                }
+            }
+
+            /// Unlike attribute keys, node names aren't interned process-wide: there are
+            /// too many of them.
+            fn endpoint(name: Cow<'static, str>) -> Key {
+                match name {
+                    Cow::Borrowed(name) => Key::Static(name),
+                    Cow::Owned(name) => Key::Shared(Arc::from(name)),
+                }
+            }
+
+            /// Convert with `.into()`: `Edge::from` is the builder above.
+            impl From<(&str, &str)> for Edge {
+                fn from((node1, node2): (&str, &str)) -> Self {
+                    Edge::new(node1, node2)
+                }
+            }
+
+            impl From<(&str, &str, &[(&str, &str)])> for Edge {
+                fn from((node1, node2, attrs): (&str, &str, &[(&str, &str)])) -> Self {
+                    Edge::new(node1, node2).with_attrs(attrs)
+                }
+            }
+
+            /// The start of a fluent edge, waiting for the `to` endpoint before there is
+            /// anything to build.
+            #[derive(Debug, Clone)]
+            pub struct EdgeFrom {
+                node1: Cow<'static, str>,
+            }
+
+            impl EdgeFrom {
+                pub fn to(self, node2: impl Into<Cow<'static, str>>) -> EdgeBuilder {
+                    EdgeBuilder {
+                        node1: self.node1,
+                        node2: node2.into(),
+                        attrs: Vec::new(),
+                        comments: Vec::new(),
+                    }
+                }
+            }
+
+            /// Builds an `Edge` step by step, once both endpoints are known.
+            #[derive(Debug, Clone)]
+            pub struct EdgeBuilder {
+                node1: Cow<'static, str>,
+                node2: Cow<'static, str>,
+                attrs: Vec<(Cow<'static, str>, Cow<'static, str>)>,
+                comments: Vec<String>,
+            }
+
+            impl EdgeBuilder {
+                /// Draw an arrowhead at `to`, whatever the kind of graph.
+                pub fn directed(self) -> Self {
+                    self.with_attr("dir", "forward")
+                }
+
+                /// Draw no arrowheads, whatever the kind of graph.
+                pub fn undirected(self) -> Self {
+                    self.with_attr("dir", "none")
+                }
+
+                pub fn with_attr(
+                    mut self,
+                    key: impl Into<Cow<'static, str>>,
+                    value: impl Into<Cow<'static, str>>,
+                ) -> Self {
+                    self.attrs.push((key.into(), value.into()));
+                    self
+                }
+
+                pub fn with_attrs(mut self, attrs: &[(&str, &str)]) -> Self {
+                    self.attrs.extend(
+                        attrs
+                            .iter()
+                            .map(|(k, v)| (Cow::Owned(k.to_string()), Cow::Owned(v.to_string()))),
+                    );
+                    self
+                }
+
+                /// Add styles to the ones already given to this builder.
+                pub fn with_styles(self, styles: &[Style]) -> Self {
+                    let existing = self.attrs.iter().rev().find(|(k, _)| k == "style");
+                    let joined = join_styles(existing.map(|(_, v)| v.as_ref()), styles);
+                    self.with_attr("style", joined)
+                }
+
+                pub fn with_comment(mut self, comment: &str) -> Self {
+                    self.comments.push(comment.to_string());
+                    self
+                }
+
+                pub fn build(self) -> Edge {
+                    // Later setters win, like repeated `with_attr` calls on an `Edge`.
+                    let mut edge = self
+                        .attrs
+                        .into_iter()
+                        .fold(Edge::between(self.node1, self.node2), |edge, (k, v)| {
+                            edge.with_attr(k, v)
+                        });
+                    edge.comments = self.comments;
+                    edge
+                }
+            }
+        }
+
+        pub mod subgraph {
+            use crate::graph::attrs::Attrs;
+            use crate::graph::Graph;
+            use std::borrow::Cow;
+            use std::collections::HashMap;
+
+            /// A named group of nodes, drawn boxed when its name starts with `cluster`.
+            #[derive(Debug, PartialEq, Eq, Clone)]
+            pub struct Subgraph {
+                name: Cow<'static, str>,
+                attrs: Attrs,
+                node_defaults: Attrs,
+                nodes: Vec<String>,
+            }
+
+            impl Subgraph {
+                pub fn new(name: &str) -> Self {
+                    Subgraph {
+                        name: Cow::Owned(name.to_string()),
+                        attrs: Attrs::new(),
+                        node_defaults: Attrs::new(),
+                        nodes: Vec::new(),
+                    }
+                }
+
+                pub fn with_attrs(self, attrs: &[(&str, &str)]) -> Self {
+                    let attrs = self.attrs.merged(attrs);
+                    Subgraph { attrs, ..self }
+                }
+
+                pub fn with_attr(
+                    self,
+                    key: impl Into<Cow<'static, str>>,
+                    value: impl Into<Cow<'static, str>>,
+                ) -> Self {
+                    let attrs = self.attrs.inserted(key.into(), value.into());
+                    Subgraph { attrs, ..self }
+                }
+
+                /// Defaults for member nodes, written as a `node [...]` statement. The graph
+                /// then declares those members inside the subgraph so the defaults reach them.
+                pub fn with_node_defaults(self, attrs: &[(&str, &str)]) -> Self {
+                    let node_defaults = self.node_defaults.merged(attrs);
+                    Subgraph {
+                        node_defaults,
+                        ..self
+                    }
+                }
+
+                /// Add member nodes by name, skipping ones already in the subgraph.
+                pub fn with_nodes(mut self, names: &[&str]) -> Self {
+                    for name in names {
+                        if !self.nodes.iter().any(|n| n == name) {
+                            self.nodes.push(name.to_string());
+                        }
+                    }
+                    self
+                }
+
+                pub fn name(&self) -> &str {
+                    &self.name
+                }
+
+                /// Whether Graphviz draws it as a box, which only `cluster...` names get.
+                pub fn is_cluster(&self) -> bool {
+                    self.name.starts_with("cluster")
+                }
+
+                pub fn attr(&self, key: &str) -> Option<&str> {
+                    self.attrs.get(key)
+                }
+
+                pub fn attrs(&self) -> impl Iterator<Item = (&str, &str)> {
+                    self.attrs.iter()
+                }
+
+                pub fn node_defaults(&self) -> impl Iterator<Item = (&str, &str)> {
+                    self.node_defaults.iter()
+                }
+
+                /// The attributes Graphviz will draw the subgraph with in `graph`: the
+                /// graph's own, unless its [`Cascade`] turns inheritance off, overridden by
+                /// the subgraph's.
+                ///
+                /// [`Cascade`]: crate::graph::Cascade
+                pub fn effective_attrs<'a>(
+                    &'a self,
+                    graph: &'a Graph,
+                ) -> HashMap<&'a str, &'a str> {
+                    let mut attrs = HashMap::new();
+                    if graph.cascade().subgraph_inheritance() {
+                        attrs.extend(graph.attrs.iter().map(|(k, v)| (k.as_str(), v.as_str())));
+                    }
+                    attrs.extend(self.attrs());
+                    attrs
+                }
+
+                pub fn nodes(&self) -> &[String] {
+                    &self.nodes
+                }
+
+                pub(crate) fn with_renamed_nodes(
+                    self,
+                    mut rename: impl FnMut(&str) -> String,
+                ) -> Self {
+                    let nodes = self.nodes.iter().map(|n| rename(n)).collect();
+                    Subgraph { nodes, ..self }
+                }
+
+                pub(crate) fn retaining_nodes(mut self, keep: impl Fn(&str) -> bool) -> Self {
+                    self.nodes.retain(|n| keep(n));
+                    self
+                }
+            }
+        }
+
+        pub mod style {
+            use std::convert::Infallible;
+            use std::fmt;
+            use std::str::FromStr;
+
+            /// One entry of the comma-separated `style` attribute.
+            #[derive(Debug, PartialEq, Eq, Clone)]
+            pub enum Style {
+                Solid,
+                Dashed,
+                Dotted,
+                Bold,
+                Invis,
+                Filled,
+                Rounded,
+                Diagonals,
+                Striped,
+                Wedged,
+                Radial,
+                Tapered,
+                /// Anything without a dedicated variant, e.g. `setlinewidth(2)`.
+                Other(String),
+            }
+
+            impl Style {
+                pub fn as_str(&self) -> &str {
+                    match self {
+                        Style::Solid => "solid",
+                        Style::Dashed => "dashed",
+                        Style::Dotted => "dotted",
+                        Style::Bold => "bold",
+                        Style::Invis => "invis",
+                        Style::Filled => "filled",
+                        Style::Rounded => "rounded",
+                        Style::Diagonals => "diagonals",
+                        Style::Striped => "striped",
+                        Style::Wedged => "wedged",
+                        Style::Radial => "radial",
+                        Style::Tapered => "tapered",
+                        Style::Other(other) => other,
+                    }
+                }
+            }
+
+            impl fmt::Display for Style {
+                fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                    f.write_str(self.as_str())
+                }
+            }
+
+            impl FromStr for Style {
+                type Err = Infallible;
+
+                fn from_str(s: &str) -> Result<Self, Self::Err> {
+                    Ok(match s.trim() {
+                        "solid" => Style::Solid,
+                        "dashed" => Style::Dashed,
+                        "dotted" => Style::Dotted,
+                        "bold" => Style::Bold,
+                        "invis" | "invisible" => Style::Invis,
+                        "filled" => Style::Filled,
+                        "rounded" => Style::Rounded,
+                        "diagonals" => Style::Diagonals,
+                        "striped" => Style::Striped,
+                        "wedged" => Style::Wedged,
+                        "radial" => Style::Radial,
+                        "tapered" => Style::Tapered,
+                        other => Style::Other(other.to_string()),
+                    })
+                }
+            }
+        }
+
+        pub mod esc_string {
+            use std::borrow::Cow;
+            use std::fmt;
+
+            /// Graphviz's escString, the text of labels and tooltips: `\N`, `\G`, `\E`,
+            /// `\T` and `\H` stand for the node, graph, edge, tail and head names, and
+            /// `\n`, `\l` and `\r` end a centered, left- or right-justified line.
+            ///
+            /// Text added with [`EscString::text`] is escaped to read literally, while the
+            /// escapes have methods of their own, so neither gets escaped twice. The value
+            /// goes into an attribute as it is, e.g. `node.with_attr("label", esc)`.
+            #[derive(Debug, PartialEq, Eq, Clone, Default)]
+            pub struct EscString(String);
+
+            /// Which way the line ended by [`EscString::end_line`] is justified.
+            #[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+            pub enum Justify {
+                #[default]
+                Center,
+                Left,
+                Right,
+            }
+
+            impl Justify {
+                /// `\n`, `\l` or `\r`.
+                pub fn escape(self) -> &'static str {
+                    match self {
+                        Justify::Center => "\\n",
+                        Justify::Left => "\\l",
+                        Justify::Right => "\\r",
+                    }
+                }
+            }
+
+            impl EscString {
+                pub fn new() -> Self {
+                    EscString::default()
+                }
+
+                /// Source that is escaped already, such as `"\N\l"`, kept as it is.
+                pub fn raw(source: impl Into<String>) -> Self {
+                    EscString(source.into())
+                }
+
+                /// Append `text` so that it reads literally: backslashes are doubled and
+                /// line breaks become centered `\n` ends.
+                pub fn text(mut self, text: &str) -> Self {
+                    for c in text.chars() {
+                        match c {
+                            '\\' => self.0.push_str("\\\\"),
+                            '\n' => self.0.push_str(Justify::Center.escape()),
+                            '\r' => {}
+                            c => self.0.push(c),
+                        }
+                    }
+                    self
+                }
+
+                /// `\N`
+                pub fn node_name(self) -> Self {
+                    self.escape("\\N")
+                }
+
+                /// `\G`
+                pub fn graph_name(self) -> Self {
+                    self.escape("\\G")
+                }
+
+                /// `\E`
+                pub fn edge_name(self) -> Self {
+                    self.escape("\\E")
+                }
+
+                /// `\T`
+                pub fn tail_name(self) -> Self {
+                    self.escape("\\T")
+                }
+
+                /// `\H`
+                pub fn head_name(self) -> Self {
+                    self.escape("\\H")
+                }
+
+                /// End the line so far, justified as `justify` says. A label's last line
+                /// needs one too to be justified other than centered.
+                pub fn end_line(self, justify: Justify) -> Self {
+                    self.escape(justify.escape())
+                }
+
+                pub fn as_str(&self) -> &str {
+                    &self.0
+                }
+
+                fn escape(mut self, escape: &str) -> Self {
+                    self.0.push_str(escape);
+                    self
+                }
+            }
+
+            impl fmt::Display for EscString {
+                fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                    f.write_str(&self.0)
+                }
+            }
+
+            impl From<EscString> for Cow<'static, str> {
+                fn from(esc: EscString) -> Self {
+                    Cow::Owned(esc.0)
+                }
+            }
+        }
+
+        pub mod label {
+            use super::esc_string::{EscString, Justify};
+            use std::borrow::Cow;
+            use std::fmt;
+
+            /// A multi-line label of literal text, all lines justified the same way.
+            /// Centered lines are joined with `\n`; left- and right-justified lines each
+            /// end in `\l` or `\r`, the last one too, as Graphviz needs.
+            #[derive(Debug, PartialEq, Eq, Clone)]
+            pub struct Label {
+                lines: Vec<String>,
+                justify: Justify,
+            }
+
+            impl Label {
+                /// Centered until told otherwise. Line breaks inside a line split it, and
+                /// empty lines are kept.
+                pub fn lines<S: AsRef<str>>(lines: &[S]) -> Self {
+                    Label {
+                        lines: lines
+                            .iter()
+                            .flat_map(|line| line.as_ref().split('\n'))
+                            .map(|line| line.strip_suffix('\r').unwrap_or(line).to_string())
+                            .collect(),
+                        justify: Justify::Center,
+                    }
+                }
+
+                pub fn centered(self) -> Self {
+                    self.justified(Justify::Center)
+                }
+
+                pub fn left_justified(self) -> Self {
+                    self.justified(Justify::Left)
+                }
+
+                pub fn right_justified(self) -> Self {
+                    self.justified(Justify::Right)
+                }
+
+                pub fn justified(self, justify: Justify) -> Self {
+                    Label { justify, ..self }
+                }
+
+                pub fn to_esc_string(&self) -> EscString {
+                    let last = self.lines.len().saturating_sub(1);
+                    self.lines
+                        .iter()
+                        .enumerate()
+                        .fold(EscString::new(), |esc, (i, line)| {
+                            let esc = esc.text(line);
+                            if i < last || self.justify != Justify::Center {
+                                esc.end_line(self.justify)
+                            } else {
+                                esc
+                            }
+                        })
+                }
+            }
+
+            impl fmt::Display for Label {
+                fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                    fmt::Display::fmt(&self.to_esc_string(), f)
+                }
+            }
+
+            impl From<Label> for EscString {
+                fn from(label: Label) -> Self {
+                    label.to_esc_string()
+                }
+            }
+
+            impl From<Label> for Cow<'static, str> {
+                fn from(label: Label) -> Self {
+                    label.to_esc_string().into()
+                }
+            }
+        }
+    }
+
+    /// Attribute storage shared by `Node` and `Edge`.
+    mod attrs {
+        use std::borrow::{Borrow, Cow};
+        use std::collections::{HashMap, HashSet};
+        use std::fmt;
+        use std::hash::{Hash, Hasher};
+        use std::ops::Deref;
+        use std::sync::{Arc, Mutex, OnceLock};
+
+        /// Past this many entries a linear scan stops being cheaper than hashing.
+        const INLINE_MAX: usize = 8;
+
+        /// Most nodes and edges carry a handful of attributes at most, so they live in a
+        /// plain vector of pairs, which only turns into a map once it outgrows `INLINE_MAX`.
+        #[derive(Clone)]
+        pub enum Attrs {
+            Inline(Vec<(Key, Cow<'static, str>)>),
+            Map(HashMap<Key, Cow<'static, str>>),
+        }
+
+        impl Attrs {
+            pub fn new() -> Self {
+                Attrs::Inline(Vec::new())
+            }
+
+            pub fn get(&self, key: &str) -> Option<&str> {
+                match self {
+                    Attrs::Inline(pairs) => {
+                        pairs.iter().find(|(k, _)| &**k == key).map(|(_, v)| &**v)
+                    }
+                    Attrs::Map(map) => map.get(key).map(|v| &**v),
+                }
+            }
+
+            pub fn len(&self) -> usize {
+                match self {
+                    Attrs::Inline(pairs) => pairs.len(),
+                    Attrs::Map(map) => map.len(),
+                }
+            }
+
+            pub fn iter(&self) -> Box<dyn Iterator<Item = (&str, &str)> + '_> {
+                match self {
+                    Attrs::Inline(pairs) => Box::new(pairs.iter().map(|(k, v)| (&**k, &**v))),
+                    Attrs::Map(map) => Box::new(map.iter().map(|(k, v)| (&**k, &**v))),
+                }
+            }
+
+            /// New keys override old ones. When a key is repeated within `kvs` the first
+            /// occurrence wins, hence the reversed walk.
+            pub fn merged(self, kvs: &[(&str, &str)]) -> Self {
+                kvs.iter().rev().fold(self, |attrs, (k, v)| {
+                    attrs.inserted(Cow::Owned(k.to_string()), Cow::Owned(v.to_string()))
+                })
+            }
+
+            pub fn inserted(self, key: Cow<'static, str>, value: Cow<'static, str>) -> Self {
+                match self {
+                    Attrs::Inline(mut pairs) => {
+                        match pairs.iter_mut().find(|(k, _)| **k == *key) {
+                            Some((_, v)) => *v = value,
+                            None => pairs.push((Key::from(key), value)),
+                        }
+                        if pairs.len() > INLINE_MAX {
+                            Attrs::Map(pairs.into_iter().collect())
+                        } else {
+                            Attrs::Inline(pairs)
+                        }
+                    }
+                    Attrs::Map(mut map) => {
+                        map.insert(Key::from(key), value);
+                        Attrs::Map(map)
+                    }
+                }
+            }
+
+            pub fn removed(self, key: &str) -> Self {
+                match self {
+                    Attrs::Inline(mut pairs) => {
+                        pairs.retain(|(k, _)| &**k != key);
+                        Attrs::Inline(pairs)
+                    }
+                    Attrs::Map(mut map) => {
+                        map.remove(key);
+                        Attrs::Map(map)
+                    }
+                }
+            }
+        }
+
+        impl Default for Attrs {
+            fn default() -> Self {
+                Attrs::new()
+            }
+        }
+
+        /// Equal when they hold the same pairs, whatever the order or representation.
+        impl PartialEq for Attrs {
+            fn eq(&self, other: &Self) -> bool {
+                self.len() == other.len() && self.iter().all(|(k, v)| other.get(k) == Some(v))
+            }
+        }
+
+        impl Eq for Attrs {}
+
+        impl fmt::Debug for Attrs {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.debug_map().entries(self.iter()).finish()
+            }
+        }
+
+        /// An attribute key: literals are kept as they are, anything else is interned.
+        /// Edge endpoints are stored the same way, shared but not interned.
+        #[derive(Clone)]
+        pub enum Key {
+            Static(&'static str),
+            Shared(Arc<str>),
+        }
+
+        impl From<Cow<'static, str>> for Key {
+            fn from(key: Cow<'static, str>) -> Self {
+                match key {
+                    Cow::Borrowed(key) => Key::Static(key),
+                    Cow::Owned(key) => Key::Shared(intern(&key)),
+                }
+            }
+        }
+
+        impl Deref for Key {
+            type Target = str;
+
+            fn deref(&self) -> &str {
+                match self {
+                    Key::Static(key) => key,
+                    Key::Shared(key) => key,
+                }
+            }
+        }
+
+        impl fmt::Debug for Key {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                fmt::Debug::fmt(&**self, f)
+            }
+        }
+
+        impl Borrow<str> for Key {
+            fn borrow(&self) -> &str {
+                self
+            }
+        }
+
+        impl PartialEq for Key {
+            fn eq(&self, other: &Self) -> bool {
+                **self == **other
+            }
+        }
+
+        impl Eq for Key {}
+
+        impl Hash for Key {
+            fn hash<H: Hasher>(&self, state: &mut H) {
+                (**self).hash(state)
+            }
+        }
+
+        /// The same handful of keys (`label`, `color`, `shape`, ...) appear on nearly every
+        /// node and edge, so each distinct key is allocated once per process and shared.
+        fn intern(key: &str) -> Arc<str> {
+            static KEYS: OnceLock<Mutex<HashSet<Arc<str>>>> = OnceLock::new();
+            let mut keys = KEYS
+                .get_or_init(Default::default)
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            match keys.get(key) {
+                Some(interned) => Arc::clone(interned),
+                None => {
+                    let interned = Arc::<str>::from(key);
+                    keys.insert(Arc::clone(&interned));
+                    interned
+                }
+            }
+        }
+    }
+
+    use graph_items::edge::Edge;
+    use graph_items::node::Node;
+    use graph_items::style::Style;
+    use graph_items::subgraph::Subgraph;
+    use parser::ParseError;
+    use std::any::Any;
+    use std::borrow::Cow;
+    use std::error::Error;
+    use std::fmt;
+    use std::io;
+    use std::str::FromStr;
+
+    /// The collections are shared between clones and copied on write, so cloning a graph
+    /// is O(1) and a clone only pays for the collections it actually modifies.
+    ///
+    /// Nodes, edges and subgraphs keep the order they were added in, and every writer
+    /// emits them in that order, since statement order changes Graphviz layouts. Only
+    /// attribute lists, which don't, are sorted by key. [`Graph::sort_nodes_by`] and
+    /// [`Graph::sort_edges_by`] choose another order.
+    #[derive(Debug, PartialEq, Eq, Clone)]
+    pub struct Graph {
+        nodes: Arc<Vec<Node>>,
+        /// Position of every node in `nodes` by name, kept in sync by `add_node`.
+        node_index: Arc<HashMap<String, usize>>,
+        edges: Arc<Vec<Edge>>,
+        /// Edge positions per endpoint, built on first use and kept up to date by `add_edge`.
+        adjacency: Cache<Arc<Adjacency>>,
+        pub attrs: HashMap<String, String>,
+        node_defaults: HashMap<String, String>,
+        edge_defaults: HashMap<String, String>,
+        subgraphs: Vec<Subgraph>,
+        /// What namespaced nodes were called before [`Graph::with_namespace`].
+        original_names: HashMap<String, String>,
+        observers: Observers,
+        snapshots: Snapshots,
+        comments: Vec<String>,
+        directed: bool,
+        duplicate_nodes: DuplicateNodes,
+        duplicate_edges: DuplicateEdges,
+        cascade: Cascade,
+    }
+
+    /// A value derived from the rest of the graph and computed lazily. It takes no part in
+    /// comparisons or debug output.
+    #[derive(Default)]
+    struct Cache<T>(OnceLock<T>);
+
+    impl<T> Cache<T> {
+        fn get_or_init(&self, f: impl FnOnce() -> T) -> &T {
+            self.0.get_or_init(f)
+        }
+
+        fn get_mut(&mut self) -> Option<&mut T> {
+            self.0.get_mut()
+        }
+    }
+
+    impl<T> PartialEq for Cache<T> {
+        fn eq(&self, _: &Self) -> bool {
+            true
+        }
+    }
+
+    impl<T> Eq for Cache<T> {}
+
+    impl<T: Clone> Clone for Cache<T> {
+        fn clone(&self) -> Self {
+            Cache(self.0.clone())
+        }
+    }
+
+    impl<T> fmt::Debug for Cache<T> {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str("Cache")
+        }
+    }
+
+    /// Callbacks registered with [`Graph::on_change`]. Like `Cache`, they take no part in
+    /// comparisons or debug output.
+    #[derive(Default, Clone)]
+    struct Observers(Vec<Observer>);
+
+    type Observer = Arc<dyn Fn(&Change<'_>) + Send + Sync>;
+
+    impl PartialEq for Observers {
+        fn eq(&self, _: &Self) -> bool {
+            true
+        }
+    }
+
+    impl Eq for Observers {}
+
+    impl fmt::Debug for Observers {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "Observers({})", self.0.len())
+        }
+    }
+
+    /// Data attached with [`Node::with_data`]. Like `Observers`, it takes no part in
+    /// comparisons, and debug output only says whether there is any.
+    #[derive(Default, Clone)]
+    struct Payload(Option<Arc<dyn Any + Send + Sync>>);
+
+    impl PartialEq for Payload {
+        fn eq(&self, _: &Self) -> bool {
+            true
+        }
+    }
+
+    impl Eq for Payload {}
+
+    impl fmt::Debug for Payload {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self.0 {
+                Some(_) => write!(f, "Payload(..)"),
+                None => write!(f, "Payload(None)"),
+            }
+        }
+    }
+
+    /// Named versions recorded with [`Graph::snapshot`]. They are history rather than
+    /// content, so they take no part in comparisons either.
+    #[derive(Default, Clone)]
+    struct Snapshots(Arc<Vec<(String, Graph)>>);
+
+    impl PartialEq for Snapshots {
+        fn eq(&self, _: &Self) -> bool {
+            true
+        }
+    }
+
+    impl Eq for Snapshots {}
+
+    impl fmt::Debug for Snapshots {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.debug_list()
+                .entries(self.0.iter().map(|(label, _)| label))
+                .finish()
+        }
+    }
+
+    /// What [`Graph::on_change`] observers are told about, after it happened.
+    #[derive(Debug, PartialEq, Eq, Clone, Copy)]
+    pub enum Change<'a> {
+        NodeAdded(&'a Node),
+        /// A duplicate node was merged into this one.
+        NodeUpdated(&'a Node),
+        NodeRemoved(&'a Node),
+        EdgeAdded(&'a Edge),
+        /// A duplicate edge was merged into this one.
+        EdgeUpdated(&'a Edge),
+        EdgeRemoved(&'a Edge),
+        /// A graph attribute now has this value.
+        AttrSet {
+            key: &'a str,
+            value: &'a str,
+        },
+        /// A graph attribute was unset.
+        AttrRemoved {
+            key: &'a str,
+        },
+    }
+
+    /// For every node name, the positions in `edges` of the edges leaving it (`node1`)
+    /// and entering it (`node2`).
+    #[derive(Default, Clone)]
+    struct Adjacency {
+        outgoing: HashMap<String, Vec<usize>>,
+        incoming: HashMap<String, Vec<usize>>,
+        /// Built by the first `has_path` and dropped by the next `insert`. The index
+        /// itself is rebuilt whenever direction changes, so it can be for either.
+        reachability: Cache<Arc<Reachability>>,
+    }
+
+    impl Adjacency {
+        fn new(edges: &[Edge]) -> Self {
+            edges
+                .iter()
+                .enumerate()
+                .fold(Adjacency::default(), |mut adjacency, (i, edge)| {
+                    adjacency.insert(i, edge);
+                    adjacency
+                })
+        }
+
+        fn insert(&mut self, i: usize, edge: &Edge) {
+            self.reachability = Cache::default();
+            self.outgoing
+                .entry(edge.node1().to_string())
+                .or_default()
+                .push(i);
+            self.incoming
+                .entry(edge.node2().to_string())
+                .or_default()
+                .push(i);
+        }
+
+        fn outgoing(&self, name: &str) -> &[usize] {
+            self.outgoing.get(name).map_or(&[], |edges| edges)
+        }
+
+        fn incoming(&self, name: &str) -> &[usize] {
+            self.incoming.get(name).map_or(&[], |edges| edges)
+        }
+    }
+
+    /// The graph's strongly connected components, or plain components when undirected,
+    /// and which components lead to which. Nodes in one component all reach each other,
+    /// so only walks between components are needed, and those are remembered.
+    #[derive(Default)]
+    struct Reachability {
+        component: HashMap<String, usize>,
+        /// The components each component has an edge to.
+        next: Vec<Vec<usize>>,
+        /// The components reachable from each component asked about so far.
+        reached: Mutex<HashMap<usize, Arc<HashSet<usize>>>>,
+    }
+
+    impl Reachability {
+        fn new(edges: &[Edge], directed: bool) -> Self {
+            let mut ids = HashMap::new();
+            let mut successors: Vec<Vec<usize>> = Vec::new();
+            for edge in edges {
+                let [from, to] = [edge.node1(), edge.node2()].map(|name| {
+                    *ids.entry(name).or_insert_with(|| {
+                        successors.push(Vec::new());
+                        successors.len() - 1
+                    })
+                });
+                successors[from].push(to);
+                if !directed {
+                    successors[to].push(from);
+                }
+            }
+            let (component, count) = strong_components(&successors);
+            let mut next = vec![Vec::new(); count];
+            for (from, targets) in successors.iter().enumerate() {
+                for &to in targets {
+                    if component[from] != component[to] {
+                        next[component[from]].push(component[to]);
+                    }
+                }
+            }
+            Reachability {
+                component: ids
+                    .into_iter()
+                    .map(|(name, id)| (name.to_string(), component[id]))
+                    .collect(),
+                next,
+                reached: Mutex::default(),
+            }
+        }
+
+        fn has_path(&self, from: &str, to: &str) -> bool {
+            let (Some(&from), Some(&to)) = (self.component.get(from), self.component.get(to))
+            else {
+                return false;
+            };
+            if from == to {
+                return true;
+            }
+            let known = self.lock().get(&from).cloned();
+            let reached = known.unwrap_or_else(|| {
+                let reached = Arc::new(self.walk(from));
+                self.lock().insert(from, Arc::clone(&reached));
+                reached
+            });
+            reached.contains(&to)
+        }
+
+        fn walk(&self, start: usize) -> HashSet<usize> {
+            let mut seen = HashSet::new();
+            let mut stack = vec![start];
+            while let Some(component) = stack.pop() {
+                stack.extend(self.next[component].iter().filter(|&&c| seen.insert(c)));
+            }
+            seen
+        }
+
+        fn lock(&self) -> MutexGuard<'_, HashMap<usize, Arc<HashSet<usize>>>> {
+            self.reached.lock().unwrap_or_else(PoisonError::into_inner)
+        }
+    }
+
+    /// Tarjan's algorithm without recursion: the component of every vertex, numbered
+    /// from 0, and how many there are.
+    fn strong_components(successors: &[Vec<usize>]) -> (Vec<usize>, usize) {
+        const UNSEEN: usize = usize::MAX;
+        let n = successors.len();
+        let (mut index, mut low) = (vec![UNSEEN; n], vec![0; n]);
+        let mut component = vec![UNSEEN; n];
+        let mut stack = Vec::new();
+        let (mut next_index, mut count) = (0, 0);
+        for root in 0..n {
+            if index[root] != UNSEEN {
+                continue;
+            }
+            // Each call is a vertex and how many of its successors have been tried.
+            let mut calls = vec![(root, 0)];
+            index[root] = next_index;
+            low[root] = next_index;
+            next_index += 1;
+            stack.push(root);
+            while let Some(&(v, tried)) = calls.last() {
+                if let Some(&w) = successors[v].get(tried) {
+                    calls.last_mut().expect("just looked at it").1 += 1;
+                    if index[w] == UNSEEN {
+                        index[w] = next_index;
+                        low[w] = next_index;
+                        next_index += 1;
+                        stack.push(w);
+                        calls.push((w, 0));
+                    } else if component[w] == UNSEEN {
+                        // Still on the stack.
+                        low[v] = low[v].min(index[w]);
+                    }
+                    continue;
+                }
+                calls.pop();
+                if let Some(&(parent, _)) = calls.last() {
+                    low[parent] = low[parent].min(low[v]);
+                }
+                if low[v] == index[v] {
+                    while let Some(w) = stack.pop() {
+                        component[w] = count;
+                        if w == v {
+                            break;
+                        }
+                    }
+                    count += 1;
+                }
+            }
+        }
+        (component, count)
+    }
+
+    /// What to do when a node is added under a name that is already present.
+    #[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+    pub enum DuplicateNodes {
+        /// Fail with [`GraphError::DuplicateNode`].
+        Error,
+        /// Ignore the new node.
+        KeepFirst,
+        /// Merge the new attributes into the existing node, as Graphviz does.
+        #[default]
+        Merge,
+    }
+
+    /// What to do when an edge joins the same two nodes as an existing one. In an
+    /// undirected graph `a -- b` and `b -- a` count as the same edge.
+    #[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+    pub enum DuplicateEdges {
+        /// Keep both, producing parallel edges.
+        #[default]
+        Keep,
+        /// Merge the new attributes into the existing edge.
+        Merge,
+        /// Fail with [`GraphError::DuplicateEdge`].
+        Error,
+    }
+
+    /// How attributes flow from the graph into its subgraphs, and what a later setting
+    /// of a list-valued attribute such as `style` does to the earlier one.
+    #[derive(Debug, PartialEq, Eq, Clone, Copy)]
+    pub struct Cascade {
+        subgraph_inheritance: bool,
+        combined_lists: bool,
+    }
+
+    impl Default for Cascade {
+        fn default() -> Self {
+            Cascade {
+                subgraph_inheritance: true,
+                combined_lists: false,
+            }
+        }
+    }
+
+    impl Cascade {
+        /// Graphviz's own behavior: subgraphs inherit, later settings replace.
+        pub fn new() -> Self {
+            Cascade::default()
+        }
+
+        /// Whether subgraphs inherit the graph attributes; a root `label` then shows up
+        /// on every cluster too. Without it `to_dot` writes them after the subgraphs.
+        pub fn with_subgraph_inheritance(self, subgraph_inheritance: bool) -> Self {
+            Cascade {
+                subgraph_inheritance,
+                ..self
+            }
+        }
+
+        /// Whether a later `style` on the graph, its defaults or a merged duplicate adds
+        /// to the existing list instead of replacing it.
+        pub fn with_combined_lists(self, combined_lists: bool) -> Self {
+            Cascade {
+                combined_lists,
+                ..self
+            }
+        }
+
+        pub fn subgraph_inheritance(&self) -> bool {
+            self.subgraph_inheritance
+        }
+
+        pub fn combined_lists(&self) -> bool {
+            self.combined_lists
+        }
+
+        /// `merge_into`, with `style` joined rather than replaced if the cascade says so.
+        fn merge_into(
+            &self,
+            map: HashMap<String, String>,
+            attrs: &[(&str, &str)],
+        ) -> HashMap<String, String> {
+            let new = attrs
+                .iter()
+                .find(|(key, _)| *key == "style")
+                .map(|(_, v)| *v);
+            match self.joined_style(map.get("style").map(String::as_str), new) {
+                Some(style) => {
+                    let mut map = merge_into(map, attrs);
+                    map.insert("style".to_string(), style);
+                    map
+                }
+                None => merge_into(map, attrs),
+            }
+        }
+
+        /// The `style` to store when `new` is set over `old`, if it isn't simply `new`.
+        fn joined_style(&self, old: Option<&str>, new: Option<&str>) -> Option<String> {
+            match (old, new) {
+                (Some(old), Some(new)) if self.combined_lists => {
+                    Some(join_styles(Some(old), &split_styles(Some(new))))
+                }
+                _ => None,
+            }
+        }
+    }
+
+    /// How [`Graph::to_dot_with`] lays out its output. The default is what
+    /// [`Graph::to_dot`] writes.
+    #[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+    pub struct DotOptions {
+        implicit_nodes: ImplicitNodes,
+        compact: bool,
+        graph_attrs: GraphAttrStyle,
+    }
+
+    /// Which nodes get a statement of their own, given that Graphviz creates any node an
+    /// edge mentions.
+    #[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+    pub enum ImplicitNodes {
+        /// The nodes that were declared, and only those.
+        #[default]
+        AsDeclared,
+        /// Not the declared nodes without attributes or comments that an edge mentions
+        /// anyway, for the smallest output.
+        Omit,
+        /// The declared nodes and then every edge endpoint that never was, so each node
+        /// is spelled out.
+        Declare,
+    }
+
+    /// How the graph's own attributes are written. The parser reads both forms into
+    /// [`Graph::attrs`].
+    #[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+    pub enum GraphAttrStyle {
+        /// One `graph [rankdir="LR", ...]` statement.
+        #[default]
+        List,
+        /// A `rankdir="LR"` statement per attribute, sorted by key.
+        Assignments,
+    }
+
+    impl DotOptions {
+        pub fn new() -> Self {
+            DotOptions::default()
+        }
+
+        pub fn with_implicit_nodes(self, implicit_nodes: ImplicitNodes) -> Self {
+            DotOptions {
+                implicit_nodes,
+                ..self
+            }
+        }
+
+        /// Write the whole graph on one line for embedding in URLs and payloads:
+        /// `;` between statements, no comments or indents, quotes only where an id or
+        /// value needs them, and runs of edges with the same attributes chained as in
+        /// `a -> b -> c`.
+        pub fn with_compact(self, compact: bool) -> Self {
+            DotOptions { compact, ..self }
+        }
+
+        pub fn with_graph_attrs(self, graph_attrs: GraphAttrStyle) -> Self {
+            DotOptions {
+                graph_attrs,
+                ..self
+            }
+        }
+
+        pub fn implicit_nodes(&self) -> ImplicitNodes {
+            self.implicit_nodes
+        }
+
+        pub fn is_compact(&self) -> bool {
+            self.compact
+        }
+
+        pub fn graph_attrs(&self) -> GraphAttrStyle {
+            self.graph_attrs
+        }
+    }
+
+    impl Graph {
+        pub fn new() -> Self {
+            Graph {
+                nodes: Arc::default(),
+                node_index: Arc::default(),
+                edges: Arc::default(),
+                adjacency: Cache::default(),
+                attrs: HashMap::new(),
+                node_defaults: HashMap::new(),
+                edge_defaults: HashMap::new(),
+                subgraphs: Vec::new(),
+                original_names: HashMap::new(),
+                observers: Observers::default(),
+                snapshots: Snapshots::default(),
+                comments: Vec::new(),
+                directed: false,
+                duplicate_nodes: DuplicateNodes::default(),
+                duplicate_edges: DuplicateEdges::default(),
+                cascade: Cascade::default(),
+            }
+        }
+
+        /// An empty graph with room for `nodes` nodes and `edges` edges, for when the size
+        /// is known upfront.
+        pub fn with_capacity(nodes: usize, edges: usize) -> Self {
+            let mut graph = Graph::new();
+            graph.reserve(nodes, edges);
+            graph
+        }
+
+        /// A `digraph` of the tree under `root`, with an edge from each parent to each child.
+        ///
+        /// Nodes are named `n0`, `n1`, ... in pre-order and `label` gives their `label`
+        /// attribute, so equal labels in different branches stay separate nodes.
+        pub fn from_tree<T>(
+            root: &T,
+            children: impl Fn(&T) -> &[T],
+            label: impl Fn(&T) -> String,
+        ) -> Self {
+            let mut nodes: Vec<Node> = Vec::new();
+            let mut edges = Vec::new();
+            let mut stack: Vec<(&T, Option<usize>)> = vec![(root, None)];
+            while let Some((item, parent)) = stack.pop() {
+                let index = nodes.len();
+                let name = format!("n{index}");
+                if let Some(parent) = parent {
+                    edges.push(Edge::new(nodes[parent].name(), &name));
+                }
+                nodes.push(Node::named(name).with_attr("label", label(item)));
+                // Reversed so the first child is popped, and named, first.
+                stack.extend(
+                    children(item)
+                        .iter()
+                        .rev()
+                        .map(|child| (child, Some(index))),
+                );
+            }
+            Graph::with_capacity(nodes.len(), edges.len())
+                .with_directed(true)
+                .with_nodes(&nodes)
+                .with_edges(&edges)
+        }
+
+        /// Make room for `nodes` more nodes and `edges` more edges.
+        pub fn reserve(&mut self, nodes: usize, edges: usize) {
+            // Skip untouched collections so they stay shared with any clones.
+            if nodes > 0 {
+                Arc::make_mut(&mut self.nodes).reserve(nodes);
+                Arc::make_mut(&mut self.node_index).reserve(nodes);
+            }
+            if edges > 0 {
+                Arc::make_mut(&mut self.edges).reserve(edges);
+            }
+        }
+
+        /// Make the graph a `digraph`, whose edges go from `node1` to `node2`.
+        pub fn with_directed(self, directed: bool) -> Self {
+            Graph {
+                directed,
+                adjacency: Cache::default(),
+                ..self
+            }
+        }
+
+        pub fn is_directed(&self) -> bool {
+            self.directed
+        }
+
+        /// Choose how `with_nodes` and `add_node` treat a node whose name is taken.
+        pub fn with_duplicate_nodes(self, duplicate_nodes: DuplicateNodes) -> Self {
+            Graph {
+                duplicate_nodes,
+                ..self
+            }
+        }
+
+        /// Add nodes, or anything that converts into them such as plain names.
+        ///
+        /// # Panics
+        ///
+        /// Panics on a duplicate name under [`DuplicateNodes::Error`]; use
+        /// [`Graph::try_with_nodes`] to get the error instead.
+        pub fn with_nodes<N: Clone + Into<Node>>(self, nodes: &[N]) -> Self {
+            self.try_with_nodes(nodes)
+                .unwrap_or_else(|error| panic!("{error}"))
+        }
+
+        pub fn try_with_nodes<N: Clone + Into<Node>>(
+            mut self,
+            nodes: &[N],
+        ) -> Result<Self, GraphError> {
+            self.reserve(nodes.len(), 0);
+            nodes.iter().try_fold(self, |mut graph, node| {
+                graph.add_node(node.clone().into())?;
+                Ok(graph)
+            })
+        }
+
+        /// Add a node, applying the graph's [`DuplicateNodes`] policy.
+        pub fn add_node(&mut self, node: Node) -> Result<(), GraphError> {
+            let existing = self.node_index.get(node.name()).copied();
+            match (existing, self.duplicate_nodes) {
+                (None, _) => {
+                    Arc::make_mut(&mut self.node_index)
+                        .insert(node.name().to_string(), self.nodes.len());
+                    Arc::make_mut(&mut self.nodes).push(node);
+                    self.notify(Change::NodeAdded(&self.nodes[self.nodes.len() - 1]));
+                }
+                (Some(_), DuplicateNodes::KeepFirst) => {}
+                (Some(i), DuplicateNodes::Merge) => {
+                    let joined = self
+                        .cascade
+                        .joined_style(self.nodes[i].attr("style"), node.attr("style"));
+                    let node = match joined {
+                        Some(style) => node.with_attrs(&[("style", &style)]),
+                        None => node,
+                    };
+                    let nodes = Arc::make_mut(&mut self.nodes);
+                    let existing = std::mem::replace(&mut nodes[i], Node::new(""));
+                    nodes[i] = existing.merged(&node);
+                    self.notify(Change::NodeUpdated(&self.nodes[i]));
+                }
+                (Some(_), DuplicateNodes::Error) => {
+                    return Err(GraphError::DuplicateNode(node.name().to_string()));
+                }
+            }
+            Ok(())
+        }
+
+        /// Choose how attributes cascade; see [`Cascade`].
+        pub fn with_cascade(self, cascade: Cascade) -> Self {
+            Graph { cascade, ..self }
+        }
+
+        pub fn cascade(&self) -> Cascade {
+            self.cascade
+        }
+
+        /// Choose how `with_edges` and `add_edge` treat an edge between nodes that are
+        /// already connected.
+        pub fn with_duplicate_edges(self, duplicate_edges: DuplicateEdges) -> Self {
+            Graph {
+                duplicate_edges,
+                ..self
+            }
+        }
+
+        /// Add edges, or anything that converts into them such as `(node1, node2)` pairs.
+        ///
+        /// # Panics
+        ///
+        /// Panics on a duplicate edge under [`DuplicateEdges::Error`]; use
+        /// [`Graph::try_with_edges`] to get the error instead.
+        pub fn with_edges<E: Clone + Into<Edge>>(self, edges: &[E]) -> Self {
+            self.try_with_edges(edges)
+                .unwrap_or_else(|error| panic!("{error}"))
+        }
+
+        pub fn try_with_edges<E: Clone + Into<Edge>>(
+            mut self,
+            edges: &[E],
+        ) -> Result<Self, GraphError> {
+            self.reserve(0, edges.len());
+            edges.iter().try_fold(self, |mut graph, edge| {
+                graph.add_edge(edge.clone().into())?;
+                Ok(graph)
+            })
+        }
+
+        /// Add an edge, applying the graph's [`DuplicateEdges`] policy. Duplicates are
+        /// looked up through the adjacency index, so this is O(degree), not O(edges).
+        pub fn add_edge(&mut self, edge: Edge) -> Result<(), GraphError> {
+            let existing = match self.duplicate_edges {
+                DuplicateEdges::Keep => None,
+                _ => self.find_edge(edge.node1(), edge.node2()),
+            };
+            match (existing, self.duplicate_edges) {
+                (Some(i), DuplicateEdges::Merge) => {
+                    let joined = self
+                        .cascade
+                        .joined_style(self.edges[i].attr("style"), edge.attr("style"));
+                    let edge = match joined {
+                        Some(style) => edge.with_attrs(&[("style", &style)]),
+                        None => edge,
+                    };
+                    let edges = Arc::make_mut(&mut self.edges);
+                    let existing = std::mem::replace(&mut edges[i], Edge::new("", ""));
+                    edges[i] = existing.merged(&edge);
+                    self.notify(Change::EdgeUpdated(&self.edges[i]));
+                }
+                (Some(_), DuplicateEdges::Error) => {
+                    return Err(GraphError::DuplicateEdge(
+                        edge.node1().to_string(),
+                        edge.node2().to_string(),
+                    ));
+                }
+                _ => {
+                    // Keep an already built index up to date instead of dropping it.
+                    if let Some(adjacency) = self.adjacency.get_mut() {
+                        Arc::make_mut(adjacency).insert(self.edges.len(), &edge);
+                    }
+                    Arc::make_mut(&mut self.edges).push(edge);
+                    self.notify(Change::EdgeAdded(&self.edges[self.edges.len() - 1]));
+                }
+            }
+            Ok(())
+        }
+
+        /// Remove node `name` and every edge touching it, returning the node.
+        pub fn remove_node(&mut self, name: &str) -> Option<Node> {
+            let i = self.node_index.get(name).copied()?;
+            let touches = |edge: &Edge| edge.node1() == name || edge.node2() == name;
+            if self.edges.iter().any(touches) {
+                let edges = Arc::make_mut(&mut self.edges);
+                let (removed, kept) = std::mem::take(edges)
+                    .into_iter()
+                    .partition::<Vec<_>, _>(touches);
+                *edges = kept;
+                self.adjacency = Cache::default();
+                // Edges go first, so observers never see an edge to a removed node.
+                for edge in &removed {
+                    self.notify(Change::EdgeRemoved(edge));
+                }
+            }
+            Some(self.remove_node_at(i))
+        }
+
+        /// Remove just the node, and its place in any subgraph, leaving its edges to an
+        /// implicit node of the same name.
+        fn remove_node_at(&mut self, i: usize) -> Node {
+            let node = Arc::make_mut(&mut self.nodes).remove(i);
+            self.node_index = Arc::new(index_by_name(&self.nodes));
+            self.subgraphs = std::mem::take(&mut self.subgraphs)
+                .into_iter()
+                .map(|subgraph| subgraph.retaining_nodes(|member| member != node.name()))
+                .collect();
+            self.notify(Change::NodeRemoved(&node));
+            node
+        }
+
+        /// Remove the first edge joining `node1` and `node2` (in either order unless
+        /// directed), returning it.
+        pub fn remove_edge(&mut self, node1: &str, node2: &str) -> Option<Edge> {
+            let i = self.find_edge(node1, node2)?;
+            Some(self.remove_edge_at(i))
+        }
+
+        /// Remove the edge with id `id`, returning it.
+        pub fn remove_edge_by_id(&mut self, id: &str) -> Option<Edge> {
+            let i = self.edges.iter().position(|edge| edge.id() == Some(id))?;
+            Some(self.remove_edge_at(i))
+        }
+
+        fn remove_edge_at(&mut self, i: usize) -> Edge {
+            let edge = Arc::make_mut(&mut self.edges).remove(i);
+            self.adjacency = Cache::default();
+            self.notify(Change::EdgeRemoved(&edge));
+            edge
+        }
+
+        /// Swap in `edge`, whose endpoints may differ, at position `i`.
+        fn replace_edge_at(&mut self, i: usize, edge: Edge) {
+            Arc::make_mut(&mut self.edges)[i] = edge;
+            self.adjacency = Cache::default();
+            self.notify(Change::EdgeUpdated(&self.edges[i]));
+        }
+
+        /// Call `observer` after every change to this graph's nodes, edges and graph
+        /// attributes. Clones made afterwards share the observer.
+        pub fn on_change(&mut self, observer: impl Fn(&Change<'_>) + Send + Sync + 'static) {
+            self.observers.0.push(Arc::new(observer));
+        }
+
+        fn notify(&self, change: Change<'_>) {
+            for observer in &self.observers.0 {
+                observer(&change);
+            }
+        }
+
+        /// The first edge joining `node1` and `node2` (in either order unless directed).
+        fn find_edge(&self, node1: &str, node2: &str) -> Option<usize> {
+            self.joining(node1, node2).min()
+        }
+
+        /// Positions of the edges joining `node1` and `node2` (in either order unless
+        /// directed), unordered; an undirected self loop comes up twice.
+        fn joining<'a>(
+            &'a self,
+            node1: &'a str,
+            node2: &'a str,
+        ) -> impl Iterator<Item = usize> + 'a {
+            let adjacency = self.adjacency();
+            let reversed = if self.directed {
+                &[][..]
+            } else {
+                adjacency.incoming(node1)
+            };
+            adjacency
+                .outgoing(node1)
+                .iter()
+                .chain(reversed)
+                .copied()
+                .filter(move |&i| {
+                    let e = &self.edges[i];
+                    (e.node1() == node1 && e.node2() == node2)
+                        || (!self.directed && e.node1() == node2 && e.node2() == node1)
+                })
+        }
+
+        pub fn with_attrs(mut self, attrs: &[(&str, &str)]) -> Self {
+            self.set_attrs(attrs);
+            self
+        }
+
+        /// Set one graph attribute, like `with_attrs` with a single pair.
+        pub fn with_attr(
+            mut self,
+            key: impl Into<Cow<'static, str>>,
+            value: impl Into<Cow<'static, str>>,
+        ) -> Self {
+            let (key, value) = (key.into(), value.into());
+            self.set_attrs(&[(&key, &value)]);
+            self
+        }
+
+        pub fn without_attr(mut self, key: &str) -> Self {
+            self.remove_attr(key);
+            self
+        }
+
+        pub fn clear_attrs(mut self) -> Self {
+            let mut keys = self.attrs.keys().cloned().collect::<Vec<_>>();
+            keys.sort();
+            for key in keys {
+                self.remove_attr(&key);
+            }
+            self
+        }
+
+        /// Like `with_attrs`, but drops every graph attribute not in `attrs`.
+        pub fn replace_attrs(self, attrs: &[(&str, &str)]) -> Self {
+            self.clear_attrs().with_attrs(attrs)
+        }
+
+        /// Unset a graph attribute, returning its old value.
+        pub fn remove_attr(&mut self, key: &str) -> Option<String> {
+            let value = self.attrs.remove(key)?;
+            self.notify(Change::AttrRemoved { key });
+            Some(value)
+        }
+
+        fn set_attrs(&mut self, attrs: &[(&str, &str)]) {
+            self.attrs = self
+                .cascade
+                .merge_into(std::mem::take(&mut self.attrs), attrs);
+            if !self.observers.0.is_empty() {
+                let mut seen = HashSet::new();
+                for (key, _) in attrs.iter().filter(|(key, _)| seen.insert(*key)) {
+                    let value = &self.attrs[*key];
+                    self.notify(Change::AttrSet { key, value });
+                }
+            }
+        }
+
+        /// Attributes every node gets unless it sets them itself, written as `node [...]`.
+        pub fn with_node_defaults(mut self, attrs: &[(&str, &str)]) -> Self {
+            self.node_defaults = self
+                .cascade
+                .merge_into(std::mem::take(&mut self.node_defaults), attrs);
+            self
+        }
+
+        /// Attributes every edge gets unless it sets them itself, written as `edge [...]`.
+        pub fn with_edge_defaults(mut self, attrs: &[(&str, &str)]) -> Self {
+            self.edge_defaults = self
+                .cascade
+                .merge_into(std::mem::take(&mut self.edge_defaults), attrs);
+            self
+        }
+
+        pub fn node_defaults(&self) -> &HashMap<String, String> {
+            &self.node_defaults
+        }
+
+        pub fn edge_defaults(&self) -> &HashMap<String, String> {
+            &self.edge_defaults
+        }
+
+        /// The subgraph whose node defaults reach `name`: the first one with node
+        /// defaults that lists it, which is also where `to_dot` declares the node.
+        pub(crate) fn defaults_owner(&self, name: &str) -> Option<&Subgraph> {
+            self.subgraphs.iter().find(|subgraph| {
+                subgraph.node_defaults().next().is_some()
+                    && subgraph.nodes().iter().any(|member| member == name)
+            })
+        }
+
+        /// Attach a comment that is written above the `graph { ... }` block, e.g. the
+        /// tool version or the id of the record the graph was generated from.
+        pub fn with_comment(mut self, comment: &str) -> Self {
+            self.comments.push(comment.to_string());
+            self
+        }
+
+        /// Add subgraphs, written after the nodes. Their members should also be added as nodes.
+        pub fn with_subgraphs(mut self, subgraphs: &[Subgraph]) -> Self {
+            self.subgraphs.extend_from_slice(subgraphs);
+            self
+        }
+
+        pub fn subgraphs(&self) -> &[Subgraph] {
+            &self.subgraphs
+        }
+
+        /// Put the nodes into one `cluster_<value>` subgraph per value of `key`, labelled
+        /// with the value, in order of first appearance. Nodes without `key` are left out.
+        ///
+        /// Values that already have a cluster labelled with them are skipped, so calling
+        /// this again adds nothing. When two values sanitize to the same name, or a
+        /// subgraph has it already, the later one gets a `_2`, `_3`, ... suffix.
+        pub fn cluster_by_attr(mut self, key: &str) -> Self {
+            let clustered = |value: &str| {
+                self.subgraphs
+                    .iter()
+                    .any(|subgraph| subgraph.is_cluster() && subgraph.attr("label") == Some(value))
+            };
+            let mut clusters: Vec<(&str, Vec<&str>)> = Vec::new();
+            for node in self.nodes.iter() {
+                let Some(value) = node.attr(key).filter(|value| !clustered(value)) else {
+                    continue;
+                };
+                match clusters.iter_mut().find(|(v, _)| *v == value) {
+                    Some((_, members)) => members.push(node.name()),
+                    None => clusters.push((value, vec![node.name()])),
+                }
+            }
+            let mut taken = self
+                .subgraphs
+                .iter()
+                .map(|subgraph| subgraph.name().to_string())
+                .collect::<HashSet<_>>();
+            let clusters = clusters
+                .into_iter()
+                .map(|(value, members)| {
+                    let base = format!("cluster_{}", sanitize_id(value));
+                    let mut name = base.clone();
+                    for suffix in 2.. {
+                        if !taken.contains(&name) {
+                            break;
+                        }
+                        name = format!("{base}_{suffix}");
+                    }
+                    taken.insert(name.clone());
+                    Subgraph::new(&name)
+                        .with_attrs(&[("label", value)])
+                        .with_nodes(&members)
+                })
+                .collect::<Vec<_>>();
+            self.subgraphs.extend(clusters);
+            self
+        }
+
+        /// Declare a plain node for every edge endpoint that isn't declared yet, in the
+        /// order edges first name them, as Graphviz does implicitly. Afterwards
+        /// [`Graph::node`] finds every node an edge mentions.
+        pub fn ensure_nodes(mut self) -> Self {
+            let implicit = self.node_names()[self.nodes.len()..]
+                .iter()
+                .map(|name| name.to_string())
+                .collect::<Vec<_>>();
+            self.reserve(implicit.len(), 0);
+            for name in implicit {
+                self.update_node(name, |node| node);
+            }
+            self
+        }
+
+        /// Reorder the declared nodes, and so their statements in the output. The sort is
+        /// stable, so nodes that compare equal keep their insertion order.
+        pub fn sort_nodes_by(mut self, compare: impl FnMut(&Node, &Node) -> Ordering) -> Self {
+            Arc::make_mut(&mut self.nodes).sort_by(compare);
+            self.node_index = Arc::new(index_by_name(&self.nodes));
+            self
+        }
+
+        /// Reorder the edges, and so their statements in the output. The sort is stable,
+        /// so edges that compare equal keep their insertion order.
+        pub fn sort_edges_by(mut self, compare: impl FnMut(&Edge, &Edge) -> Ordering) -> Self {
+            Arc::make_mut(&mut self.edges).sort_by(compare);
+            self.adjacency = Cache::default();
+            self
+        }
+
+        pub fn with_fontsize(self, fontsize: f64) -> Result<Self, GraphError> {
+            self.with_number("fontsize", fontsize)
+        }
+
+        pub fn with_nodesep(self, nodesep: f64) -> Result<Self, GraphError> {
+            self.with_number("nodesep", nodesep)
+        }
+
+        pub fn with_ranksep(self, ranksep: f64) -> Result<Self, GraphError> {
+            self.with_number("ranksep", ranksep)
+        }
+
+        fn with_number(self, key: &str, value: f64) -> Result<Self, GraphError> {
+            let formatted = format_non_negative(key, value)?;
+            Ok(self.with_attrs(&[(key, &formatted)]))
+        }
+
+        pub fn attr(&self, key: &str) -> Option<&str> {
+            self.attrs.get(key).map(String::as_str)
+        }
+
+        /// The graph attribute parsed as `T`, e.g. `attr_as::<f64>("nodesep")`.
+        pub fn attr_as<T: FromStr>(&self, key: &str) -> Result<Option<T>, ParseError> {
+            parse_attr(key, self.attr(key))
+        }
+
+        pub fn node(&self, name: &str) -> Option<&Node> {
+            self.node_index.get(name).map(|&i| &self.nodes[i])
+        }
+
+        pub fn contains_node(&self, name: &str) -> bool {
+            self.node_index.contains_key(name)
+        }
+
+        /// Whether an edge joins `node1` and `node2`, in either order unless directed.
+        /// Uses the adjacency index, so it is O(degree).
+        pub fn contains_edge(&self, node1: &str, node2: &str) -> bool {
+            self.find_edge(node1, node2).is_some()
+        }
+
+        /// Every edge joining `node1` and `node2`, parallel ones included, in insertion
+        /// order. Undirected graphs match either order.
+        pub fn edges_between<'a>(
+            &'a self,
+            node1: &'a str,
+            node2: &'a str,
+        ) -> impl Iterator<Item = &'a Edge> + 'a {
+            let mut found = self.joining(node1, node2).collect::<Vec<_>>();
+            found.sort_unstable();
+            found.dedup();
+            found.into_iter().map(|i| &self.edges[i])
+        }
+
+        /// The first edge with id `id`; a linear scan, as ids aren't indexed.
+        pub fn edge_by_id(&self, id: &str) -> Option<&Edge> {
+            self.edges.iter().find(|edge| edge.id() == Some(id))
+        }
+
+        /// Give every edge without an id one of its own: `e0`, `e1` and so on, skipping
+        /// ids already in use.
+        pub fn with_edge_ids(mut self) -> Self {
+            let taken = self
+                .edges
+                .iter()
+                .filter_map(|edge| edge.id().map(str::to_string))
+                .collect::<HashSet<_>>();
+            let mut ids = (0..)
+                .map(|n| format!("e{n}"))
+                .filter(|id| !taken.contains(id));
+            if self.edges.iter().any(|edge| edge.id().is_none()) {
+                for edge in Arc::make_mut(&mut self.edges) {
+                    if edge.id().is_none() {
+                        let id = ids.next().expect("the ids never run out");
+                        *edge = std::mem::replace(edge, Edge::new("", "")).with_id(&id);
+                    }
+                }
+            }
+            self
+        }
+
+        /// The declared nodes, in insertion order.
+        pub fn nodes(&self) -> &[Node] {
+            &self.nodes
+        }
+
+        /// How many nodes and edges fit before the storage has to grow.
+        pub fn capacity(&self) -> (usize, usize) {
+            (self.nodes.capacity(), self.edges.capacity())
+        }
+
+        /// The edges, in insertion order.
+        pub fn edges(&self) -> &[Edge] {
+            &self.edges
+        }
+
+        /// How many nodes are declared; endpoints that never were aren't counted, as
+        /// with [`Graph::nodes`]. [`Graph::stats`] counts those too.
+        pub fn node_count(&self) -> usize {
+            self.nodes.len()
+        }
+
+        pub fn edge_count(&self) -> usize {
+            self.edges.len()
+        }
+
+        /// Whether there are no nodes and no edges; graph attributes don't count.
+        pub fn is_empty(&self) -> bool {
+            self.nodes.is_empty() && self.edges.is_empty()
+        }
+
+        /// The sum of the edge weights, counting edges without a numeric `weight` as 1,
+        /// the Graphviz default.
+        pub fn total_weight(&self) -> f64 {
+            self.edges
+                .iter()
+                .map(|edge| edge.weight().unwrap_or(1.0))
+                .sum()
+        }
+
+        pub fn comments(&self) -> &[String] {
+            &self.comments
+        }
+
+        /// Rename every node to a plain `[A-Za-z_][A-Za-z0-9_]*` identifier, so the output
+        /// needs no quoting at all. The returned [`IdMap`] maps the new names back.
+        pub fn sanitize_ids(self) -> (Self, IdMap) {
+            let names = self.nodes.iter().map(|node| node.name()).chain(
+                self.edges
+                    .iter()
+                    .flat_map(|edge| [edge.node1(), edge.node2()]),
+            );
+            let ids = names.fold(IdMap::default(), |ids, name| ids.with_original(name));
+            let graph = self.renamed(|name| ids.sanitized(name).unwrap_or(name).to_string());
+            (graph, ids)
+        }
+
+        /// Prefix every node name with `prefix.`, so graphs from different sources can be
+        /// combined with [`Graph::with_graph`] without their names colliding.
+        /// [`Graph::original_name`] still knows what each node was called.
+        pub fn with_namespace(self, prefix: &str) -> Self {
+            let mut original_names = HashMap::new();
+            let graph = self.renamed(|name| {
+                let namespaced = format!("{prefix}.{name}");
+                original_names.insert(namespaced.clone(), name.to_string());
+                namespaced
+            });
+            let original_names = original_names
+                .into_iter()
+                .map(|(namespaced, name)| {
+                    let original = graph.original_name(&name).to_string();
+                    (namespaced, original)
+                })
+                .collect();
+            Graph {
+                original_names,
+                ..graph
+            }
+        }
+
+        /// The name a node had before any [`Graph::with_namespace`], or `name` itself if
+        /// it was never namespaced.
+        pub fn original_name<'a>(&'a self, name: &'a str) -> &'a str {
+            self.original_names.get(name).map_or(name, String::as_str)
+        }
+
+        /// Add `other`'s nodes, subgraphs and edges, under this graph's duplicate
+        /// policies, and its graph attributes and defaults, which win over ours.
+        ///
+        /// # Panics
+        ///
+        /// Panics on a duplicate under the `Error` policies, like `with_nodes` and
+        /// `with_edges`.
+        pub fn with_graph(self, other: &Graph) -> Self {
+            let mut graph = self
+                .with_attrs(&pairs_of(&other.attrs))
+                .with_node_defaults(&pairs_of(&other.node_defaults))
+                .with_edge_defaults(&pairs_of(&other.edge_defaults))
+                .with_nodes(&other.nodes)
+                .with_subgraphs(&other.subgraphs)
+                .with_edges(&other.edges);
+            graph.original_names.extend(
+                other
+                    .original_names
+                    .iter()
+                    .map(|(k, v)| (k.clone(), v.clone())),
+            );
+            graph
+        }
+
+        /// The graph with every node, edge endpoint and subgraph member renamed.
+        fn renamed(self, mut rename: impl FnMut(&str) -> String) -> Self {
+            let mut names = HashMap::new();
+            let mut rename = |name: &str| -> String {
+                names
+                    .entry(name.to_string())
+                    .or_insert_with(|| rename(name))
+                    .clone()
+            };
+            let nodes = self
+                .nodes
+                .iter()
+                .map(|node| {
+                    let renamed = rename(node.name());
+                    node.clone().with_name(&renamed)
+                })
+                .collect::<Vec<_>>();
+            let node_index = Arc::new(index_by_name(&nodes));
+            let edges = self
+                .edges
+                .iter()
+                .map(|edge| {
+                    let node1 = rename(edge.node1());
+                    let node2 = rename(edge.node2());
+                    edge.clone().with_endpoints(&node1, &node2)
+                })
+                .collect::<Vec<_>>();
+            let subgraphs = self
+                .subgraphs
+                .iter()
+                .map(|subgraph| subgraph.clone().with_renamed_nodes(&mut rename))
+                .collect();
+
+            Graph {
+                nodes: Arc::new(nodes),
+                node_index,
+                edges: Arc::new(edges),
+                adjacency: Cache::default(),
+                subgraphs,
+                ..self
+            }
+        }
+
+        /// Parse DOT source produced by [`Graph::to_dot`] or written by hand.
+        pub fn from_dot(src: &str) -> Result<Self, ParseError> {
+            parser::parse(src)
+        }
+
+        /// Like `from_dot`, reading the source incrementally, e.g. from `dot`'s stdout.
+        pub fn from_dot_reader<R: io::Read>(reader: R) -> Result<Self, parser::ReadError> {
+            parser::parse_reader(reader)
+        }
+
+        /// Read an edge list a line at a time; see [`parser::parse_edge_list`].
+        pub fn from_edge_list<R: io::Read>(reader: R) -> Result<Self, parser::ReadError> {
+            parser::parse_edge_list(reader)
+        }
+
+        /// Encode the graph in the compact [`binary`] format.
+        pub fn to_bytes(&self) -> Vec<u8> {
+            binary::encode(self)
+        }
+
+        /// Decode a graph written by [`Graph::to_bytes`].
+        pub fn from_bytes(bytes: &[u8]) -> Result<Self, binary::DecodeError> {
+            binary::decode(bytes)
+        }
+
+        /// Render the graph as DOT source: graph attrs and defaults first, then nodes and
+        /// edges in insertion order. Attribute lists are sorted by key so the output is
+        /// stable.
+        ///
+        /// Graphviz only applies a subgraph's node defaults to nodes created inside it,
+        /// so members of a subgraph with node defaults are declared there instead.
+        pub fn to_dot(&self) -> String {
+            self.to_dot_with(DotOptions::default())
+        }
+
+        /// Like [`Graph::to_dot`], laid out as `options` say.
+        pub fn to_dot_with(&self, options: DotOptions) -> String {
+            let header = render_comments(&self.comments, "");
+            let graph_line = match options.graph_attrs {
+                _ if self.attrs.is_empty() => String::new(),
+                GraphAttrStyle::List => {
+                    format!("    graph{}\n", render_attr_list(self.attrs.iter()))
+                }
+                GraphAttrStyle::Assignments => {
+                    let mut pairs = pairs_of(&self.attrs);
+                    pairs.sort();
+                    pairs
+                        .into_iter()
+                        .map(|(k, v)| format!("    {}={}\n", quote_id(k), quote_string(v)))
+                        .collect()
+                }
+            };
+            let (graph_attrs, late_graph_attrs) = if self.cascade.subgraph_inheritance {
+                (graph_line, String::new())
+            } else {
+                (String::new(), graph_line)
+            };
+            let defaults = [("node", &self.node_defaults), ("edge", &self.edge_defaults)]
+                .iter()
+                .filter(|(_, attrs)| !attrs.is_empty())
+                .map(|(keyword, attrs)| {
+                    format!("    {keyword}{}\n", render_attr_list(attrs.iter()))
+                })
+                .collect::<String>();
+            let subgraphs = self
+                .subgraphs
+                .iter()
+                .map(|subgraph| {
+                    let declared = self
+                        .nodes
+                        .iter()
+                        .filter(|node| {
+                            self.defaults_owner(node.name())
+                                .is_some_and(|owner| std::ptr::eq(owner, subgraph))
+                        })
+                        .collect::<Vec<_>>();
+                    render_subgraph(subgraph, &declared)
+                })
+                .collect::<String>();
+            let implicit = match options.implicit_nodes {
+                ImplicitNodes::Omit => self
+                    .edges
+                    .iter()
+                    .flat_map(|edge| [edge.node1(), edge.node2()])
+                    .collect::<HashSet<_>>(),
+                _ => HashSet::new(),
+            };
+            let declared = self
+                .nodes
+                .iter()
+                .filter(|node| self.defaults_owner(node.name()).is_none())
+                .filter(|node| {
+                    let plain = node.attrs().next().is_none() && node.comments().is_empty();
+                    !(plain && implicit.contains(node.name()))
+                })
+                .collect::<Vec<_>>();
+            let undeclared = match options.implicit_nodes {
+                ImplicitNodes::Declare => self.node_names()[self.nodes.len()..]
+                    .iter()
+                    .copied()
+                    .filter(|name| self.defaults_owner(name).is_none())
+                    .collect(),
+                _ => Vec::new(),
+            };
+            if options.compact {
+                return self.compact_dot(&declared, &undeclared, options.graph_attrs);
+            }
+            let nodes = declared
+                .iter()
+                .map(|node| render_node(node))
+                .chain(
+                    undeclared
+                        .iter()
+                        .map(|name| format!("    {}\n", quote_id(name))),
+                )
+                .collect::<String>();
+            let keyword = if self.directed { "digraph" } else { "graph" };
+            let edges = self
+                .edges
+                .iter()
+                .map(|edge| render_edge(edge, self.directed))
+                .collect::<String>();
+
+            format!(
+                "{header}{keyword} {{\n{graph_attrs}{defaults}{nodes}{subgraphs}{late_graph_attrs}{edges}}}\n"
+            )
+        }
+
+        /// The statements of [`DotOptions::with_compact`] output, given the top-level
+        /// node statements `to_dot_with` settled on.
+        fn compact_dot(
+            &self,
+            declared: &[&Node],
+            undeclared: &[&str],
+            graph_attrs: GraphAttrStyle,
+        ) -> String {
+            let node = |node: &Node| {
+                format!(
+                    "{}{}",
+                    quote_id(node.name()),
+                    compact_attr_list(node.attrs())
+                )
+            };
+            let defaults = |keyword: &str, attrs: &HashMap<String, String>| {
+                (!attrs.is_empty()).then(|| {
+                    format!(
+                        "{keyword}{}",
+                        compact_attr_list(pairs_of(attrs).into_iter())
+                    )
+                })
+            };
+
+            let mut statements = Vec::new();
+            let graph_attrs = match graph_attrs {
+                GraphAttrStyle::List => defaults("graph", &self.attrs).into_iter().collect(),
+                GraphAttrStyle::Assignments => {
+                    let mut pairs = pairs_of(&self.attrs);
+                    pairs.sort();
+                    pairs
+                        .into_iter()
+                        .map(|(k, v)| format!("{}={}", quote_id(k), quote_id(v)))
+                        .collect::<Vec<_>>()
+                }
+            };
+            if self.cascade.subgraph_inheritance {
+                statements.extend(graph_attrs.clone());
+            }
+            statements.extend(defaults("node", &self.node_defaults));
+            statements.extend(defaults("edge", &self.edge_defaults));
+            statements.extend(declared.iter().map(|declared| node(declared)));
+            statements.extend(undeclared.iter().map(|name| quote_id(name)));
+            for subgraph in &self.subgraphs {
+                let mut inner = Vec::new();
+                if subgraph.attrs().next().is_some() {
+                    inner.push(format!("graph{}", compact_attr_list(subgraph.attrs())));
+                }
+                if subgraph.node_defaults().next().is_some() {
+                    inner.push(format!(
+                        "node{}",
+                        compact_attr_list(subgraph.node_defaults())
+                    ));
+                }
+                for member in subgraph.nodes() {
+                    let owned = self
+                        .defaults_owner(member)
+                        .is_some_and(|owner| std::ptr::eq(owner, subgraph));
+                    match self.node(member) {
+                        Some(declared) if owned => inner.push(node(declared)),
+                        _ => inner.push(quote_id(member)),
+                    }
+                }
+                statements.push(format!(
+                    "subgraph {}{{{}}}",
+                    quote_id(subgraph.name()),
+                    inner.join(";")
+                ));
+            }
+            if !self.cascade.subgraph_inheritance {
+                statements.extend(graph_attrs);
+            }
+            let edge_op = if self.directed { "->" } else { "--" };
+            let mut chain: Option<(String, String, &str)> = None;
+            for edge in self.edges.iter() {
+                let attrs = compact_attr_list(edge.attrs());
+                match &mut chain {
+                    Some((path, chain_attrs, last))
+                        if *last == edge.node1() && *chain_attrs == attrs =>
+                    {
+                        *path += &format!("{edge_op}{}", quote_id(edge.node2()));
+                        *last = edge.node2();
+                    }
+                    _ => {
+                        if let Some((path, attrs, _)) = chain.take() {
+                            statements.push(path + &attrs);
+                        }
+                        let path = format!(
+                            "{}{edge_op}{}",
+                            quote_id(edge.node1()),
+                            quote_id(edge.node2())
+                        );
+                        chain = Some((path, attrs, edge.node2()));
+                    }
+                }
+            }
+            if let Some((path, attrs, _)) = chain {
+                statements.push(path + &attrs);
+            }
+
+            let keyword = if self.directed { "digraph" } else { "graph" };
+            format!("{keyword}{{{}}}", statements.join(";"))
+        }
+
+        /// DOT in one fixed form, in the spirit of `dot -Tcanon`, so equal graphs give equal
+        /// bytes: every id and key quoted, tab indents, one `;`-terminated statement per
+        /// line, sorted attributes, no comments, and every node declared, including
+        /// edge endpoints that never were, before the subgraphs and edges.
+        pub fn to_canonical_dot(&self) -> String {
+            let attr_list = |attrs: &mut dyn Iterator<Item = (&str, &str)>| {
+                let mut pairs = attrs
+                    .map(|(k, v)| format!("{}={}", quote_string(k), quote_string(v)))
+                    .collect::<Vec<_>>();
+                pairs.sort();
+                if pairs.is_empty() {
+                    String::new()
+                } else {
+                    format!(" [{}]", pairs.join(", "))
+                }
+            };
+            let node = |indent: &str, name: &str| {
+                let attrs = self
+                    .node(name)
+                    .map(|node| attr_list(&mut node.attrs()))
+                    .unwrap_or_default();
+                format!("{indent}{}{attrs};\n", quote_string(name))
+            };
+            let statement = |indent: &str, keyword: &str, attrs: &HashMap<String, String>| {
+                if attrs.is_empty() {
+                    String::new()
+                } else {
+                    let mut attrs = attrs.iter().map(|(k, v)| (k.as_str(), v.as_str()));
+                    format!("{indent}{keyword}{};\n", attr_list(&mut attrs))
+                }
+            };
+
+            let keyword = if self.directed { "digraph" } else { "graph" };
+            let mut out = format!("{keyword} {{\n");
+            let graph_attrs = statement("\t", "graph", &self.attrs);
+            if self.cascade.subgraph_inheritance {
+                out += &graph_attrs;
+            }
+            out += &statement("\t", "node", &self.node_defaults);
+            out += &statement("\t", "edge", &self.edge_defaults);
+            for name in self.node_names() {
+                // As in `to_dot`, nodes Graphviz must create inside a subgraph for its
+                // defaults to apply are declared there.
+                if self.defaults_owner(name).is_none() {
+                    out += &node("\t", name);
+                }
+            }
+            for subgraph in &self.subgraphs {
+                out += &format!("\tsubgraph {} {{\n", quote_string(subgraph.name()));
+                if subgraph.attrs().next().is_some() {
+                    out += &format!("\t\tgraph{};\n", attr_list(&mut subgraph.attrs()));
+                }
+                if subgraph.node_defaults().next().is_some() {
+                    out += &format!("\t\tnode{};\n", attr_list(&mut subgraph.node_defaults()));
+                }
+                for member in subgraph.nodes() {
+                    let owned = self
+                        .defaults_owner(member)
+                        .is_some_and(|owner| std::ptr::eq(owner, subgraph));
+                    if owned {
+                        out += &node("\t\t", member);
+                    } else {
+                        out += &format!("\t\t{};\n", quote_string(member));
+                    }
+                }
+                out += "\t}\n";
+            }
+            if !self.cascade.subgraph_inheritance {
+                out += &graph_attrs;
+            }
+            let edge_op = if self.directed { "->" } else { "--" };
+            for edge in self.edges.iter() {
+                out += &format!(
+                    "\t{} {edge_op} {}{};\n",
+                    quote_string(edge.node1()),
+                    quote_string(edge.node2()),
+                    attr_list(&mut edge.attrs())
+                );
+            }
+            out += "}\n";
+            out
+        }
+    }
+
+    // -------------------------------------------------------------------------
+    // TRAVERSAL (backed by the lazily built adjacency index)
+    // -------------------------------------------------------------------------
+
+    impl Graph {
+        /// Every node sharing an edge with `name`, in either direction, without repeats.
+        pub fn neighbors<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+            let adjacency = self.adjacency();
+            let outgoing = adjacency
+                .outgoing(name)
+                .iter()
+                .map(|&i| self.edges[i].node2());
+            let incoming = adjacency
+                .incoming(name)
+                .iter()
+                .map(|&i| self.edges[i].node1());
+            let mut seen = HashSet::new();
+            outgoing.chain(incoming).filter(move |n| seen.insert(*n))
+        }
+
+        /// The nodes `name` has an edge to, without repeats. Undirected edges go both
+        /// ways, so there this is the same as `neighbors`.
+        pub fn successors<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+            let mut seen = HashSet::new();
+            self.next_hops(name).filter(move |n| seen.insert(*n))
+        }
+
+        /// The nodes with an edge to `name`, without repeats; `neighbors` when undirected.
+        pub fn predecessors<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+            let previous: Box<dyn Iterator<Item = &'a str>> = if self.directed {
+                Box::new(
+                    self.adjacency()
+                        .incoming(name)
+                        .iter()
+                        .map(|&i| self.edges[i].node1()),
+                )
+            } else {
+                Box::new(self.neighbors(name))
+            };
+            let mut seen = HashSet::new();
+            previous.filter(move |n| seen.insert(*n))
+        }
+
+        /// The nodes no edge points at, declared nodes first as in `to_dot`. In an
+        /// undirected graph only isolated nodes qualify.
+        pub fn roots(&self) -> Vec<&str> {
+            self.node_names()
+                .into_iter()
+                .filter(|name| self.predecessors(name).next().is_none())
+                .collect()
+        }
+
+        /// The nodes no edge leaves; the counterpart of [`Graph::roots`].
+        pub fn leaves(&self) -> Vec<&str> {
+            self.node_names()
+                .into_iter()
+                .filter(|name| self.successors(name).next().is_none())
+                .collect()
+        }
+
+        /// Every edge touching `name`, in insertion order, a self loop once. Narrow it
+        /// down with [`IncidentEdges::incoming`] or [`IncidentEdges::outgoing`].
+        pub fn edges_of<'a>(&'a self, name: &str) -> IncidentEdges<'a> {
+            let adjacency = self.adjacency();
+            IncidentEdges {
+                edges: &self.edges,
+                outgoing: adjacency.outgoing(name),
+                incoming: adjacency.incoming(name),
+            }
+        }
+
+        /// Nodes reachable from `start` in breadth-first order, `start` included.
+        /// Directed graphs are followed along edge direction only.
+        pub fn bfs<'a>(&'a self, start: &'a str) -> Vec<&'a str> {
+            let mut seen = HashSet::from([start]);
+            let mut queue = VecDeque::from([start]);
+            let mut order = Vec::new();
+            while let Some(name) = queue.pop_front() {
+                order.push(name);
+                queue.extend(self.next_hops(name).filter(|n| seen.insert(n)));
+            }
+            order
+        }
+
+        /// Nodes reachable from `start` in depth-first preorder, `start` included.
+        pub fn dfs<'a>(&'a self, start: &'a str) -> Vec<&'a str> {
+            let mut seen = HashSet::new();
+            let mut stack = vec![start];
+            let mut order = Vec::new();
+            while let Some(name) = stack.pop() {
+                if !seen.insert(name) {
+                    continue;
+                }
+                order.push(name);
+                // Reversed so the first neighbor is visited first.
+                let next = self.next_hops(name).collect::<Vec<_>>();
+                stack.extend(next.into_iter().rev().filter(|n| !seen.contains(n)));
+            }
+            order
+        }
+
+        /// How many edges it takes to get from `start` to each node it reaches, itself at 0.
+        fn hop_distances<'a>(&'a self, start: &'a str) -> HashMap<&'a str, usize> {
+            let mut distances = HashMap::from([(start, 0)]);
+            let mut queue = VecDeque::from([start]);
+            while let Some(name) = queue.pop_front() {
+                let distance = distances[name] + 1;
+                for next in self.next_hops(name) {
+                    distances.entry(next).or_insert_with(|| {
+                        queue.push_back(next);
+                        distance
+                    });
+                }
+            }
+            distances
+        }
+
+        /// The path with the fewest edges from `from` to `to`, both included.
+        pub fn shortest_path<'a>(&'a self, from: &'a str, to: &'a str) -> Option<Vec<&'a str>> {
+            let mut parents = HashMap::new();
+            let mut queue = VecDeque::from([from]);
+            while let Some(name) = queue.pop_front() {
+                if name == to {
+                    let mut path = vec![to];
+                    let mut current = to;
+                    while current != from {
+                        current = parents[current];
+                        path.push(current);
+                    }
+                    path.reverse();
+                    return Some(path);
+                }
+                for next in self.next_hops(name) {
+                    if next != from && !parents.contains_key(next) {
+                        parents.insert(next, name);
+                        queue.push_back(next);
+                    }
+                }
+            }
+            None
+        }
+
+        /// Up to `k` cheapest loopless paths from `from` to `to`, cheapest first, each with
+        /// its cost: the sum of its edge weights (see [`Edge::weight`]), where edges
+        /// without one cost 1 and negative ones cost 0. Uses Yen's algorithm; parallel
+        /// edges don't make for different paths.
+        pub fn k_shortest_paths<'a>(
+            &'a self,
+            from: &'a str,
+            to: &'a str,
+            k: usize,
+        ) -> Vec<(Vec<&'a str>, f64)> {
+            let mut found = Vec::new();
+            match self.cheapest_path(from, to, &HashSet::new(), &HashSet::new()) {
+                Some(first) if k > 0 => found.push(first),
+                _ => return found,
+            }
+            let mut candidates: Vec<(Vec<&str>, f64)> = Vec::new();
+            while found.len() < k {
+                let last = found[found.len() - 1].0.clone();
+                // Branch off the last path at each of its nodes in turn, avoiding the
+                // steps already taken from there by paths sharing the same start.
+                for i in 0..last.len() - 1 {
+                    let root = &last[..=i];
+                    let taken = found
+                        .iter()
+                        .filter(|(path, _)| path.len() > i + 1 && path[..=i] == *root)
+                        .map(|(path, _)| (path[i], path[i + 1]))
+                        .collect();
+                    let visited = root[..i].iter().copied().collect();
+                    let Some((spur, spur_cost)) = self.cheapest_path(last[i], to, &visited, &taken)
+                    else {
+                        continue;
+                    };
+                    let root_cost = root
+                        .windows(2)
+                        .map(|step| self.step_cost(step[0], step[1]))
+                        .sum::<f64>();
+                    let path = [&root[..i], &spur[..]].concat();
+                    let known = |(other, _): &(Vec<&str>, f64)| *other == path;
+                    if !found.iter().any(known) && !candidates.iter().any(known) {
+                        candidates.push((path, root_cost + spur_cost));
+                    }
+                }
+                let cheapest = candidates
+                    .iter()
+                    .enumerate()
+                    .min_by(|(_, a), (_, b)| a.1.total_cmp(&b.1))
+                    .map(|(i, _)| i);
+                match cheapest {
+                    Some(i) => found.push(candidates.remove(i)),
+                    None => break,
+                }
+            }
+            found
+        }
+
+        /// Dijkstra's algorithm, never entering `avoid` nor taking a step in `banned`.
+        fn cheapest_path<'a>(
+            &'a self,
+            from: &'a str,
+            to: &'a str,
+            avoid: &HashSet<&str>,
+            banned: &HashSet<(&str, &str)>,
+        ) -> Option<(Vec<&'a str>, f64)> {
+            let mut best = HashMap::from([(from, 0.0)]);
+            let mut parents = HashMap::new();
+            let mut heap = BinaryHeap::from([Reverse(Cost(0.0, from))]);
+            while let Some(Reverse(Cost(cost, name))) = heap.pop() {
+                if name == to {
+                    let mut path = vec![to];
+                    while let Some(&parent) = parents.get(path[path.len() - 1]) {
+                        path.push(parent);
+                    }
+                    path.reverse();
+                    return Some((path, cost));
+                }
+                if cost > best[name] {
+                    continue;
+                }
+                for (next, weight) in self.weighted_steps(name) {
+                    if avoid.contains(next) || banned.contains(&(name, next)) {
+                        continue;
+                    }
+                    let total = cost + weight;
+                    if best.get(next).is_none_or(|&known| total < known) {
+                        best.insert(next, total);
+                        parents.insert(next, name);
+                        heap.push(Reverse(Cost(total, next)));
+                    }
+                }
+            }
+            None
+        }
+
+        /// The cheapest single edge from `from` to `to`.
+        fn step_cost(&self, from: &str, to: &str) -> f64 {
+            self.weighted_steps(from)
+                .filter(|(next, _)| *next == to)
+                .map(|(_, weight)| weight)
+                .fold(f64::INFINITY, f64::min)
+        }
+
+        /// Where each edge from `name` leads and what it costs, as `next_hops` walks.
+        fn weighted_steps<'a>(
+            &'a self,
+            name: &'a str,
+        ) -> impl Iterator<Item = (&'a str, f64)> + 'a {
+            let adjacency = self.adjacency();
+            let backward = if self.directed {
+                &[][..]
+            } else {
+                adjacency.incoming(name)
+            };
+            let forward = adjacency
+                .outgoing(name)
+                .iter()
+                .map(|&i| (i, self.edges[i].node2()));
+            let backward = backward.iter().map(|&i| (i, self.edges[i].node1()));
+            forward
+                .chain(backward)
+                .map(|(i, next)| (next, self.edges[i].weight().unwrap_or(1.0).max(0.0)))
+        }
+
+        /// Every path from `from` to `to` that visits no node twice and has at most
+        /// `max_len` edges, found lazily depth first. Parallel edges don't make for
+        /// different paths, and there are none from a node to itself.
+        pub fn all_simple_paths<'a>(
+            &'a self,
+            from: &'a str,
+            to: &'a str,
+            max_len: usize,
+        ) -> SimplePaths<'a> {
+            let start = from != to && max_len > 0;
+            SimplePaths {
+                graph: self,
+                to,
+                max_len,
+                path: vec![from],
+                stack: if start {
+                    vec![self.successors(from).collect::<Vec<_>>().into_iter()]
+                } else {
+                    Vec::new()
+                },
+            }
+        }
+
+        /// Whether `to` can be reached from `from` along edges, following direction in
+        /// a directed graph. A node present in the graph always reaches itself.
+        ///
+        /// The components the answer is worked out from are built on the first call
+        /// and kept, along with every walk, until the edges change.
+        pub fn has_path(&self, from: &str, to: &str) -> bool {
+            if from == to && self.contains_node(from) {
+                return true;
+            }
+            self.reachability().has_path(from, to)
+        }
+
+        /// Whether the graph is directed and has no cycles, self loops included. Shares
+        /// [`Graph::has_path`]'s components, so asking again is cheap until the edges change.
+        pub fn is_dag(&self) -> bool {
+            let reachability = self.reachability();
+            self.directed
+                && reachability.next.len() == reachability.component.len()
+                && self.edges.iter().all(|edge| edge.node1() != edge.node2())
+        }
+
+        fn reachability(&self) -> &Reachability {
+            self.adjacency()
+                .reachability
+                .get_or_init(|| Arc::new(Reachability::new(&self.edges, self.directed)))
+        }
+
+        /// Where a walk can go from `name`: along outgoing edges in a directed graph,
+        /// along any incident edge otherwise.
+        fn next_hops<'a>(&'a self, name: &'a str) -> Box<dyn Iterator<Item = &'a str> + 'a> {
+            if self.directed {
+                Box::new(
+                    self.adjacency()
+                        .outgoing(name)
+                        .iter()
+                        .map(|&i| self.edges[i].node2()),
+                )
+            } else {
+                Box::new(self.neighbors(name))
+            }
+        }
+
+        fn adjacency(&self) -> &Adjacency {
+            self.adjacency
+                .get_or_init(|| Arc::new(Adjacency::new(&self.edges)))
+        }
+    }
+
+    /// The edges touching one node, from [`Graph::edges_of`].
+    #[derive(Debug, Clone)]
+    pub struct IncidentEdges<'a> {
+        edges: &'a [Edge],
+        /// Ascending positions in `edges`, as the adjacency index keeps them.
+        outgoing: &'a [usize],
+        incoming: &'a [usize],
+    }
+
+    impl<'a> IncidentEdges<'a> {
+        /// Only the edges pointing at the node: those naming it second. Undirected
+        /// graphs go by how each edge was written.
+        pub fn incoming(self) -> impl Iterator<Item = &'a Edge> {
+            self.incoming.iter().map(|&i| &self.edges[i])
+        }
+
+        /// Only the edges leaving the node: those naming it first.
+        pub fn outgoing(self) -> impl Iterator<Item = &'a Edge> {
+            self.outgoing.iter().map(|&i| &self.edges[i])
+        }
+    }
+
+    impl<'a> Iterator for IncidentEdges<'a> {
+        type Item = &'a Edge;
+
+        fn next(&mut self) -> Option<&'a Edge> {
+            let i = match (self.outgoing.first(), self.incoming.first()) {
+                (Some(&out), Some(&inc)) if out == inc => {
+                    self.outgoing = &self.outgoing[1..];
+                    self.incoming = &self.incoming[1..];
+                    out
+                }
+                (Some(&out), Some(&inc)) if out < inc => {
+                    self.outgoing = &self.outgoing[1..];
+                    out
+                }
+                (_, Some(&inc)) => {
+                    self.incoming = &self.incoming[1..];
+                    inc
+                }
+                (Some(&out), None) => {
+                    self.outgoing = &self.outgoing[1..];
+                    out
+                }
+                (None, None) => return None,
+            };
+            Some(&self.edges[i])
+        }
+    }
+
+    /// A path cost and where it ends, ordered by cost for [`Graph::k_shortest_paths`].
+    struct Cost<'a>(f64, &'a str);
+
+    impl PartialEq for Cost<'_> {
+        fn eq(&self, other: &Self) -> bool {
+            self.cmp(other) == Ordering::Equal
+        }
+    }
+
+    impl Eq for Cost<'_> {}
+
+    impl PartialOrd for Cost<'_> {
+        fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+            Some(self.cmp(other))
+        }
+    }
+
+    impl Ord for Cost<'_> {
+        fn cmp(&self, other: &Self) -> Ordering {
+            self.0.total_cmp(&other.0).then_with(|| self.1.cmp(other.1))
+        }
+    }
+
+    /// The paths from [`Graph::all_simple_paths`], each listing its nodes from start
+    /// to end.
+    #[derive(Debug, Clone)]
+    pub struct SimplePaths<'a> {
+        graph: &'a Graph,
+        to: &'a str,
+        max_len: usize,
+        /// The walk so far, which `stack` has one list of untried next steps for.
+        path: Vec<&'a str>,
+        stack: Vec<std::vec::IntoIter<&'a str>>,
+    }
+
+    impl<'a> Iterator for SimplePaths<'a> {
+        type Item = Vec<&'a str>;
+
+        fn next(&mut self) -> Option<Vec<&'a str>> {
+            while let Some(steps) = self.stack.last_mut() {
+                match steps.next() {
+                    Some(next) if next == self.to => {
+                        let mut path = self.path.clone();
+                        path.push(next);
+                        return Some(path);
+                    }
+                    Some(next) => {
+                        // Going on through `next` takes at least two more edges.
+                        if self.path.len() < self.max_len && !self.path.contains(&next) {
+                            self.path.push(next);
+                            let steps = self.graph.successors(next).collect::<Vec<_>>();
+                            self.stack.push(steps.into_iter());
+                        }
+                    }
+                    None => {
+                        self.stack.pop();
+                        self.path.pop();
+                    }
+                }
+            }
+            None
+        }
+    }
+
+    // -------------------------------------------------------------------------
+    // STATISTICS
+    // -------------------------------------------------------------------------
+
+    /// A summary of a graph's shape, from [`Graph::stats`]. Nodes include edge endpoints
+    /// that were never declared.
+    #[derive(Debug, PartialEq, Eq, Clone, Default)]
+    pub struct GraphStats {
+        pub node_count: usize,
+        pub edge_count: usize,
+        /// Edges from a node to itself.
+        pub self_loops: usize,
+        /// Nodes no edge touches.
+        pub isolated_nodes: usize,
+        /// Connected components, ignoring edge direction.
+        pub components: usize,
+        /// The most edges touching one node; a self loop counts twice.
+        pub max_degree: usize,
+        /// Whether the graph is a digraph without cycles. Undirected graphs never are.
+        pub is_dag: bool,
+    }
+
+    impl Graph {
+        pub fn stats(&self) -> GraphStats {
+            let names = self.node_names();
+            let position = names
+                .iter()
+                .enumerate()
+                .map(|(i, name)| (*name, i))
+                .collect::<HashMap<_, _>>();
+            let mut degree = vec![0; names.len()];
+            let mut in_degree = vec![0; names.len()];
+            let mut successors = vec![Vec::new(); names.len()];
+            let mut parent = (0..names.len()).collect::<Vec<_>>();
+            let mut self_loops = 0;
+            for edge in self.edges.iter() {
+                let (from, to) = (position[edge.node1()], position[edge.node2()]);
+                degree[from] += 1;
+                degree[to] += 1;
+                in_degree[to] += 1;
+                successors[from].push(to);
+                if from == to {
+                    self_loops += 1;
+                }
+                let (root_from, root_to) =
+                    (find_root(&mut parent, from), find_root(&mut parent, to));
+                parent[root_from] = root_to;
+            }
+            let components = (0..names.len())
+                .filter(|&i| find_root(&mut parent, i) == i)
+                .count();
+
+            // Kahn's algorithm: the graph is acyclic iff every node can be taken off.
+            let mut ready = (0..names.len())
+                .filter(|&i| in_degree[i] == 0)
+                .collect::<Vec<_>>();
+            let mut taken = 0;
+            while let Some(i) = ready.pop() {
+                taken += 1;
+                for &next in &successors[i] {
+                    in_degree[next] -= 1;
+                    if in_degree[next] == 0 {
+                        ready.push(next);
+                    }
+                }
+            }
+
+            GraphStats {
+                node_count: names.len(),
+                edge_count: self.edges.len(),
+                self_loops,
+                isolated_nodes: degree.iter().filter(|&&d| d == 0).count(),
+                components,
+                max_degree: degree.iter().copied().max().unwrap_or(0),
+                is_dag: self.directed && taken == names.len(),
+            }
+        }
+
+        /// Newman's degree assortativity: the correlation, from -1 to 1, between the
+        /// degrees at the two ends of each edge. Positive when nodes tend to join nodes of
+        /// similar degree. A directed graph compares the out-degree of where each edge
+        /// starts with the in-degree of where it ends. `None` when there are no edges or
+        /// every end has the same degree.
+        pub fn assortativity(&self) -> Option<f64> {
+            let mut out_degree: HashMap<&str, f64> = HashMap::new();
+            let mut in_degree: HashMap<&str, f64> = HashMap::new();
+            for edge in self.edges.iter() {
+                *out_degree.entry(edge.node1()).or_default() += 1.0;
+                *in_degree.entry(edge.node2()).or_default() += 1.0;
+            }
+            let ends = |name| {
+                let out = out_degree.get(name).copied().unwrap_or(0.0);
+                (out, in_degree.get(name).copied().unwrap_or(0.0))
+            };
+            let mut pairs = Vec::new();
+            for edge in self.edges.iter() {
+                let (from, to) = (ends(edge.node1()), ends(edge.node2()));
+                if self.directed {
+                    pairs.push((from.0, to.1));
+                } else {
+                    let (x, y) = (from.0 + from.1, to.0 + to.1);
+                    pairs.extend([(x, y), (y, x)]);
+                }
+            }
+            if pairs.is_empty() {
+                return None;
+            }
+            let n = pairs.len() as f64;
+            let mean_x = pairs.iter().map(|(x, _)| x).sum::<f64>() / n;
+            let mean_y = pairs.iter().map(|(_, y)| y).sum::<f64>() / n;
+            let (mut covariance, mut var_x, mut var_y) = (0.0, 0.0, 0.0);
+            for (x, y) in pairs {
+                covariance += (x - mean_x) * (y - mean_y);
+                var_x += (x - mean_x) * (x - mean_x);
+                var_y += (y - mean_y) * (y - mean_y);
+            }
+            let spread = (var_x * var_y).sqrt();
+            (spread > 0.0).then(|| covariance / spread)
+        }
+    }
+
+    /// Union-find lookup with path halving.
+    fn find_root(parent: &mut [usize], mut i: usize) -> usize {
+        while parent[i] != i {
+            parent[i] = parent[parent[i]];
+            i = parent[i];
+        }
+        i
+    }
+
+    // -------------------------------------------------------------------------
+    // ALGORITHMS
+    // -------------------------------------------------------------------------
+
+    impl Graph {
+        /// The most that can flow from `source` to `sink` with each edge carrying at most
+        /// its capacity (see [`Edge::capacity`]), where edges without one carry 1 and
+        /// negative ones nothing. Undirected edges carry flow either way. Alongside comes
+        /// a minimum cut: the edges, in graph order, leading from the nodes the source
+        /// could still push more flow to on to the rest. Their capacities add up to the flow.
+        ///
+        /// Uses the Edmonds–Karp algorithm. A sink that is the source gets nothing.
+        pub fn max_flow(&self, source: &str, sink: &str) -> (f64, Vec<&Edge>) {
+            let names = self.node_names();
+            let position = names
+                .iter()
+                .enumerate()
+                .map(|(i, name)| (*name, i))
+                .collect::<HashMap<_, _>>();
+            let (Some(&s), Some(&t)) = (position.get(source), position.get(sink)) else {
+                return (0.0, Vec::new());
+            };
+            if s == t {
+                return (0.0, Vec::new());
+            }
+
+            // Residual arcs come in pairs, arc `a ^ 1` running back along arc `a`.
+            let mut head = Vec::new();
+            let mut residual = Vec::new();
+            let mut arcs = vec![Vec::new(); names.len()];
+            for edge in self.edges.iter() {
+                let (from, to) = (position[edge.node1()], position[edge.node2()]);
+                if from == to {
+                    continue;
+                }
+                let capacity = edge.capacity().unwrap_or(1.0).max(0.0);
+                arcs[from].push(head.len());
+                head.push(to);
+                residual.push(capacity);
+                arcs[to].push(head.len());
+                head.push(from);
+                residual.push(if self.directed { 0.0 } else { capacity });
+            }
+
+            let mut flow = 0.0;
+            loop {
+                // The shortest path with room left, by the arc each node was reached along.
+                let mut reached = vec![false; names.len()];
+                let mut via = vec![None; names.len()];
+                reached[s] = true;
+                let mut queue = VecDeque::from([s]);
+                while let Some(u) = queue.pop_front() {
+                    for &a in &arcs[u] {
+                        let v = head[a];
+                        if !reached[v] && residual[a] > 0.0 {
+                            reached[v] = true;
+                            via[v] = Some(a);
+                            queue.push_back(v);
+                        }
+                    }
+                }
+                if !reached[t] {
+                    let cut = self
+                        .edges
+                        .iter()
+                        .filter(|edge| {
+                            let from = reached[position[edge.node1()]];
+                            let to = reached[position[edge.node2()]];
+                            from != to && (from || !self.directed)
+                        })
+                        .collect();
+                    return (flow, cut);
+                }
+                let mut path = Vec::new();
+                let mut v = t;
+                while let Some(a) = via[v] {
+                    path.push(a);
+                    v = head[a ^ 1];
+                }
+                let room = path
+                    .iter()
+                    .map(|&a| residual[a])
+                    .fold(f64::INFINITY, f64::min);
+                for &a in &path {
+                    residual[a] -= room;
+                    residual[a ^ 1] += room;
+                }
+                flow += room;
+            }
+        }
+
+        /// A largest set of edges no two of which share a node, in graph order. Direction
+        /// is ignored and self loops never take part; of parallel edges only the first can.
+        /// Works on any graph, bipartite or not, by Edmonds' blossom algorithm.
+        pub fn maximum_matching(&self) -> Vec<&Edge> {
+            let names = self.node_names();
+            let position = names
+                .iter()
+                .enumerate()
+                .map(|(i, name)| (*name, i))
+                .collect::<HashMap<_, _>>();
+            let mut neighbours = vec![Vec::new(); names.len()];
+            let mut first_edge = HashMap::new();
+            for (i, edge) in self.edges.iter().enumerate() {
+                let (u, v) = (position[edge.node1()], position[edge.node2()]);
+                if u != v && first_edge.insert((u.min(v), u.max(v)), i).is_none() {
+                    neighbours[u].push(v);
+                    neighbours[v].push(u);
+                }
+            }
+            let mate = blossom_matching(&neighbours);
+            let mut matched = (0..names.len())
+                .filter_map(|u| mate[u].filter(|&v| u < v).map(|v| first_edge[&(u, v)]))
+                .collect::<Vec<_>>();
+            matched.sort_unstable();
+            matched.into_iter().map(|i| &self.edges[i]).collect()
+        }
+
+        /// A color index for every node such that nodes joined by an edge differ: declared
+        /// nodes first, then edge endpoints that were never declared. Each node in turn
+        /// takes the lowest index its already colored neighbors left free, so few colors
+        /// are used but not always the fewest. Direction is ignored, as are self loops.
+        pub fn greedy_coloring(&self) -> Vec<(&str, usize)> {
+            let mut colors: HashMap<&str, usize> = HashMap::new();
+            let mut coloring = Vec::new();
+            for name in self.node_names() {
+                let taken = self
+                    .edges_of(name)
+                    .map(|edge| {
+                        if edge.node1() == name {
+                            edge.node2()
+                        } else {
+                            edge.node1()
+                        }
+                    })
+                    .filter_map(|neighbor| colors.get(neighbor).copied())
+                    .collect::<HashSet<_>>();
+                let color = (0..).find(|color| !taken.contains(color)).unwrap_or(0);
+                colors.insert(name, color);
+                coloring.push((name, color));
+            }
+            coloring
+        }
+
+        /// Fill every node with `palette[i]` for its [`Graph::greedy_coloring`] index `i`,
+        /// cycling through the palette if it runs out. Edge endpoints that were never
+        /// declared are added as nodes to carry the color; an empty palette changes nothing.
+        pub fn with_coloring(mut self, palette: &[&str]) -> Self {
+            if palette.is_empty() {
+                return self;
+            }
+            let coloring = self
+                .greedy_coloring()
+                .into_iter()
+                .map(|(name, color)| (name.to_string(), palette[color % palette.len()]))
+                .collect::<Vec<_>>();
+            for (name, fillcolor) in coloring {
+                self.update_node(name, |node| {
+                    node.with_attrs(&[("fillcolor", fillcolor)])
+                        .with_styles(&[Style::Filled])
+                });
+            }
+            self
+        }
+
+        /// Replace node `name` with `update` of it, declaring it first if it was only an
+        /// edge endpoint.
+        fn update_node(&mut self, name: String, update: impl FnOnce(Node) -> Node) {
+            match self.node_index.get(name.as_str()).copied() {
+                Some(i) => {
+                    let nodes = Arc::make_mut(&mut self.nodes);
+                    let node = std::mem::replace(&mut nodes[i], Node::new(""));
+                    nodes[i] = update(node);
+                    self.notify(Change::NodeUpdated(&self.nodes[i]));
+                }
+                None => {
+                    let node = update(Node::named(name));
+                    Arc::make_mut(&mut self.node_index)
+                        .insert(node.name().to_string(), self.nodes.len());
+                    Arc::make_mut(&mut self.nodes).push(node);
+                    self.notify(Change::NodeAdded(&self.nodes[self.nodes.len() - 1]));
+                }
+            }
+        }
+
+        /// The nodes whose removal would split their component, declared nodes first.
+        /// Direction is ignored.
+        pub fn articulation_points(&self) -> Vec<&str> {
+            self.cut_points().0
+        }
+
+        /// The edges whose removal would split their component, in graph order. Direction
+        /// is ignored; parallel edges are never bridges, and neither are self loops.
+        pub fn bridges(&self) -> Vec<&Edge> {
+            self.cut_points()
+                .1
+                .into_iter()
+                .map(|i| &self.edges[i])
+                .collect()
+        }
+
+        /// Articulation points and the positions of bridges, by Tarjan's lowpoints.
+        fn cut_points(&self) -> (Vec<&str>, Vec<usize>) {
+            const UNSEEN: usize = usize::MAX;
+            let names = self.node_names();
+            let position = names
+                .iter()
+                .enumerate()
+                .map(|(i, name)| (*name, i))
+                .collect::<HashMap<_, _>>();
+            let n = names.len();
+            let mut incident = vec![Vec::new(); n];
+            for (i, edge) in self.edges.iter().enumerate() {
+                let (u, v) = (position[edge.node1()], position[edge.node2()]);
+                if u != v {
+                    incident[u].push((v, i));
+                    incident[v].push((u, i));
+                }
+            }
+            let (mut discovered, mut low) = (vec![UNSEEN; n], vec![0; n]);
+            let mut is_cut = vec![false; n];
+            let mut bridges = Vec::new();
+            let mut time = 0;
+            for root in 0..n {
+                if discovered[root] != UNSEEN {
+                    continue;
+                }
+                discovered[root] = time;
+                low[root] = time;
+                time += 1;
+                let mut children = 0;
+                // Each call is a vertex, the edge it was reached along, and how many of its
+                // edges have been tried.
+                let mut calls = vec![(root, UNSEEN, 0)];
+                while let Some(&(v, via, tried)) = calls.last() {
+                    if let Some(&(w, i)) = incident[v].get(tried) {
+                        calls.last_mut().expect("just looked at it").2 += 1;
+                        if i == via {
+                            continue;
+                        }
+                        if discovered[w] == UNSEEN {
+                            discovered[w] = time;
+                            low[w] = time;
+                            time += 1;
+                            calls.push((w, i, 0));
+                            if v == root {
+                                children += 1;
+                            }
+                        } else {
+                            low[v] = low[v].min(discovered[w]);
+                        }
+                        continue;
+                    }
+                    calls.pop();
+                    if let Some(&(parent, _, _)) = calls.last() {
+                        low[parent] = low[parent].min(low[v]);
+                        if low[v] > discovered[parent] {
+                            bridges.push(via);
+                        }
+                        if parent != root && low[v] >= discovered[parent] {
+                            is_cut[parent] = true;
+                        }
+                    }
+                }
+                is_cut[root] = children > 1;
+            }
+            bridges.sort_unstable();
+            let points = names
+                .into_iter()
+                .zip(is_cut)
+                .filter_map(|(name, cut)| cut.then_some(name))
+                .collect();
+            (points, bridges)
+        }
+
+        /// [`Graph::condensation_with`], keeping a component of one node as it is and
+        /// naming a larger one after its first member, labelled with all their names.
+        pub fn condensation(&self) -> Graph {
+            self.condensation_with(|members| match members {
+                [name] => self.node(name).cloned().unwrap_or_else(|| Node::new(name)),
+                _ => Node::new(members[0]).with_attr("label", members.join(", ")),
+            })
+        }
+
+        /// The graph with each strongly connected component, or each component when
+        /// undirected, collapsed into the node `merge` makes of its members. One plain
+        /// edge joins two components wherever any edge did, so a directed graph becomes
+        /// a DAG. Components come in order of their first member, and members in the
+        /// graph's order: declared nodes first.
+        pub fn condensation_with(&self, mut merge: impl FnMut(&[&str]) -> Node) -> Graph {
+            let names = self.node_names();
+            let position = names
+                .iter()
+                .enumerate()
+                .map(|(i, name)| (*name, i))
+                .collect::<HashMap<_, _>>();
+            let mut successors = vec![Vec::new(); names.len()];
+            for edge in self.edges.iter() {
+                let (from, to) = (position[edge.node1()], position[edge.node2()]);
+                successors[from].push(to);
+                if !self.directed {
+                    successors[to].push(from);
+                }
+            }
+            let (component, count) = strong_components(&successors);
+            // Renumber the components by first appearance.
+            let mut order = vec![None; count];
+            let mut members: Vec<Vec<&str>> = Vec::new();
+            for (i, name) in names.iter().enumerate() {
+                let c = *order[component[i]].get_or_insert(members.len());
+                match members.get_mut(c) {
+                    Some(group) => group.push(name),
+                    None => members.push(vec![name]),
+                }
+            }
+            let nodes = members.iter().map(|group| merge(group)).collect::<Vec<_>>();
+            let mut joined = HashSet::new();
+            let edges = self
+                .edges
+                .iter()
+                .filter_map(|edge| {
+                    let [from, to] = [edge.node1(), edge.node2()]
+                        .map(|name| order[component[position[name]]].expect("numbered"));
+                    (from != to && joined.insert((from, to)))
+                        .then(|| Edge::new(nodes[from].name(), nodes[to].name()))
+                })
+                .collect::<Vec<_>>();
+            Graph::with_capacity(nodes.len(), edges.len())
+                .with_directed(self.directed)
+                .with_nodes(&nodes)
+                .with_edges(&edges)
+        }
+
+        /// Each node's layer: the most edges on any path reaching it, so every edge leads
+        /// to a later layer and nodes nothing leads to are in layer 0. Declared nodes come
+        /// first. `None` unless the graph is a DAG.
+        pub fn longest_path_layers(&self) -> Option<Vec<(&str, usize)>> {
+            if !self.directed {
+                return None;
+            }
+            let names = self.node_names();
+            let position = names
+                .iter()
+                .enumerate()
+                .map(|(i, name)| (*name, i))
+                .collect::<HashMap<_, _>>();
+            let mut in_degree = vec![0; names.len()];
+            let mut successors = vec![Vec::new(); names.len()];
+            for edge in self.edges.iter() {
+                let (from, to) = (position[edge.node1()], position[edge.node2()]);
+                in_degree[to] += 1;
+                successors[from].push(to);
+            }
+            // Kahn's algorithm, pushing each node's layer past those of its predecessors.
+            let mut layer = vec![0; names.len()];
+            let mut ready = (0..names.len())
+                .filter(|&i| in_degree[i] == 0)
+                .collect::<Vec<_>>();
+            let mut taken = 0;
+            while let Some(i) = ready.pop() {
+                taken += 1;
+                for &next in &successors[i] {
+                    layer[next] = layer[next].max(layer[i] + 1);
+                    in_degree[next] -= 1;
+                    if in_degree[next] == 0 {
+                        ready.push(next);
+                    }
+                }
+            }
+            (taken == names.len()).then(|| names.into_iter().zip(layer).collect())
+        }
+
+        /// The nodes in waves, each of which only has edges from earlier waves, so a wave
+        /// can run once those before it are done: the [`Graph::longest_path_layers`] in
+        /// turn. `None` unless the graph is a DAG.
+        pub fn topological_generations(&self) -> Option<Vec<Vec<&str>>> {
+            let mut generations: Vec<Vec<&str>> = Vec::new();
+            for (name, layer) in self.longest_path_layers()? {
+                if generations.len() <= layer {
+                    generations.resize_with(layer + 1, Vec::new);
+                }
+                generations[layer].push(name);
+            }
+            Some(generations)
+        }
+
+        /// Add a `rank=same` subgraph `layer<i>` for each of the
+        /// [`Graph::topological_generations`], so Graphviz lines them up the same way. A
+        /// graph that isn't a DAG is returned as it is.
+        pub fn with_layer_ranks(self) -> Self {
+            let Some(generations) = self.topological_generations() else {
+                return self;
+            };
+            let subgraphs = generations
+                .iter()
+                .enumerate()
+                .map(|(layer, names)| {
+                    Subgraph::new(&format!("layer{layer}"))
+                        .with_attrs(&[("rank", "same")])
+                        .with_nodes(names)
+                })
+                .collect::<Vec<_>>();
+            self.with_subgraphs(&subgraphs)
+        }
+
+        /// The graph with only enough edges to keep each component connected: going in
+        /// graph order, an edge stays if it joins nodes no earlier edge already connected.
+        /// Direction is ignored when deciding but kept on the edges. Nodes, attributes and
+        /// subgraphs stay as they are; observers and history don't carry over.
+        pub fn spanning_forest(&self) -> Graph {
+            let names = self.node_names();
+            let position = names
+                .iter()
+                .enumerate()
+                .map(|(i, name)| (*name, i))
+                .collect::<HashMap<_, _>>();
+            let mut parent = (0..names.len()).collect::<Vec<_>>();
+            let edges = self
+                .edges
+                .iter()
+                .filter(|edge| {
+                    let (from, to) = (position[edge.node1()], position[edge.node2()]);
+                    let (root_from, root_to) =
+                        (find_root(&mut parent, from), find_root(&mut parent, to));
+                    parent[root_from] = root_to;
+                    root_from != root_to
+                })
+                .cloned()
+                .collect();
+            Graph {
+                edges: Arc::new(edges),
+                adjacency: Cache::default(),
+                observers: Observers::default(),
+                snapshots: Snapshots::default(),
+                ..self.clone()
+            }
+        }
+
+        /// A walk taking every edge exactly once, as the edges in the order taken, or
+        /// `None` if there is none. Undirected edges may be walked either way. When every
+        /// node has as many edges in as out (an even number, undirected) the walk is a
+        /// circuit, ending where it started. Uses Hierholzer's algorithm.
+        pub fn eulerian_path(&self) -> Option<Vec<&Edge>> {
+            let names = self.node_names();
+            let position = names
+                .iter()
+                .enumerate()
+                .map(|(i, name)| (*name, i))
+                .collect::<HashMap<_, _>>();
+            // Out minus in degree when directed, plain degree otherwise.
+            let mut balance = vec![0isize; names.len()];
+            let mut incident = vec![Vec::new(); names.len()];
+            for (i, edge) in self.edges.iter().enumerate() {
+                let (from, to) = (position[edge.node1()], position[edge.node2()]);
+                incident[from].push((i, to));
+                balance[from] += 1;
+                if self.directed {
+                    balance[to] -= 1;
+                } else {
+                    incident[to].push((i, from));
+                    balance[to] += 1;
+                }
+            }
+            let odd = |b: isize| if self.directed { b != 0 } else { b % 2 != 0 };
+            let unbalanced = (0..names.len())
+                .filter(|&i| odd(balance[i]))
+                .collect::<Vec<_>>();
+            let start = match unbalanced[..] {
+                [] => (0..names.len()).find(|&i| !incident[i].is_empty()),
+                [a, b] if !self.directed => Some(a.min(b)),
+                [a, b] if balance[a] == 1 && balance[b] == -1 => Some(a),
+                [a, b] if balance[a] == -1 && balance[b] == 1 => Some(b),
+                _ => return None,
+            };
+            let Some(start) = start else {
+                return Some(Vec::new());
+            };
+
+            let mut used = vec![false; self.edges.len()];
+            let mut tried = vec![0; names.len()];
+            let mut stack = vec![(start, None)];
+            let mut walk = Vec::with_capacity(self.edges.len());
+            while let Some(&(v, _)) = stack.last() {
+                while tried[v] < incident[v].len() && used[incident[v][tried[v]].0] {
+                    tried[v] += 1;
+                }
+                match incident[v].get(tried[v]) {
+                    Some(&(i, next)) => {
+                        used[i] = true;
+                        stack.push((next, Some(i)));
+                    }
+                    None => walk.extend(stack.pop().and_then(|(_, via)| via)),
+                }
+            }
+            // Edges left over sit in a part of the graph the walk never got to.
+            if walk.len() < self.edges.len() {
+                return None;
+            }
+            walk.reverse();
+            Some(walk.into_iter().map(|i| &self.edges[i]).collect())
+        }
+
+        /// The most edges it takes to get from `name` to any other node, following
+        /// direction in a directed graph. `None` if `name` isn't in the graph or can't
+        /// reach every node.
+        pub fn eccentricity(&self, name: &str) -> Option<usize> {
+            let names = self.node_names();
+            let name = names.iter().copied().find(|n| *n == name)?;
+            let distances = self.hop_distances(name);
+            (distances.len() == names.len()).then(|| distances.into_values().max().unwrap_or(0))
+        }
+
+        /// The largest [`Graph::eccentricity`]. `None` for an empty graph or one where
+        /// some node can't reach every other.
+        pub fn diameter(&self) -> Option<usize> {
+            self.eccentricities()?.into_iter().max()
+        }
+
+        /// The smallest [`Graph::eccentricity`], under the same conditions as
+        /// [`Graph::diameter`].
+        pub fn radius(&self) -> Option<usize> {
+            self.eccentricities()?.into_iter().min()
+        }
+
+        fn eccentricities(&self) -> Option<Vec<usize>> {
+            let names = self.node_names();
+            names
+                .iter()
+                .map(|name| {
+                    let distances = self.hop_distances(name);
+                    (distances.len() == names.len()).then(|| distances.into_values().max())?
+                })
+                .collect()
+        }
+
+        /// Communities found by label propagation: every node starts in its own, then
+        /// keeps joining whichever community most of its neighbors are in until none wants
+        /// to move. Fast, and the same `seed` always gives the same answer, but different
+        /// seeds can draw the lines differently. Direction is ignored; parallel edges pull
+        /// harder. Communities are numbered from 0 in order of first member, declared
+        /// nodes first.
+        pub fn label_propagation(&self, seed: u64) -> Vec<(&str, usize)> {
+            const MAX_ROUNDS: usize = 100;
+            let names = self.node_names();
+            let position = names
+                .iter()
+                .enumerate()
+                .map(|(i, name)| (*name, i))
+                .collect::<HashMap<_, _>>();
+            let mut neighbours = vec![Vec::new(); names.len()];
+            for edge in self.edges.iter() {
+                let (u, v) = (position[edge.node1()], position[edge.node2()]);
+                if u != v {
+                    neighbours[u].push(v);
+                    neighbours[v].push(u);
+                }
+            }
+            let mut rng = SplitMix64(seed);
+            let mut labels = (0..names.len()).collect::<Vec<_>>();
+            let mut order = labels.clone();
+            for _ in 0..MAX_ROUNDS {
+                rng.shuffle(&mut order);
+                let mut moved = false;
+                for &v in &order {
+                    let mut counts: HashMap<usize, usize> = HashMap::new();
+                    for &w in &neighbours[v] {
+                        *counts.entry(labels[w]).or_default() += 1;
+                    }
+                    let Some(&most) = counts.values().max() else {
+                        continue;
+                    };
+                    let mut favourites = counts
+                        .into_iter()
+                        .filter_map(|(label, count)| (count == most).then_some(label))
+                        .collect::<Vec<_>>();
+                    if favourites.contains(&labels[v]) {
+                        continue;
+                    }
+                    // Sorted first so ties are broken the same way for the same seed.
+                    favourites.sort_unstable();
+                    labels[v] = favourites[rng.below(favourites.len())];
+                    moved = true;
+                }
+                if !moved {
+                    break;
+                }
+            }
+            let mut numbers = HashMap::new();
+            names
+                .into_iter()
+                .zip(labels)
+                .map(|(name, label)| {
+                    let next = numbers.len();
+                    (name, *numbers.entry(label).or_insert(next))
+                })
+                .collect()
+        }
+
+        /// Put the nodes into one `cluster_<i>` subgraph per
+        /// [`Graph::label_propagation`] community `i`.
+        pub fn cluster_by_community(mut self, seed: u64) -> Self {
+            let mut members: Vec<Vec<&str>> = Vec::new();
+            for (name, community) in self.label_propagation(seed) {
+                match members.get_mut(community) {
+                    Some(group) => group.push(name),
+                    None => members.push(vec![name]),
+                }
+            }
+            let clusters = members
+                .iter()
+                .enumerate()
+                .map(|(i, group)| Subgraph::new(&format!("cluster_{i}")).with_nodes(group))
+                .collect::<Vec<_>>();
+            self.subgraphs.extend(clusters);
+            self
+        }
+
+        /// Split the nodes into `k` parts of near equal size with few edges between
+        /// them, as each node's part number, declared nodes first. Each part is grown
+        /// breadth first from the first node left over, then nodes move to the part most
+        /// of their neighbors are in while that cuts fewer edges and no part grows past
+        /// its share. Direction is ignored. A `k` of 0 counts as 1, and there are never
+        /// more parts than nodes.
+        pub fn partition(&self, k: usize) -> Vec<(&str, usize)> {
+            const NONE: usize = usize::MAX;
+            const MAX_PASSES: usize = 10;
+            let names = self.node_names();
+            let n = names.len();
+            let position = names
+                .iter()
+                .enumerate()
+                .map(|(i, name)| (*name, i))
+                .collect::<HashMap<_, _>>();
+            let mut neighbours = vec![Vec::new(); n];
+            for edge in self.edges.iter() {
+                let (u, v) = (position[edge.node1()], position[edge.node2()]);
+                if u != v {
+                    neighbours[u].push(v);
+                    neighbours[v].push(u);
+                }
+            }
+            let k = k.clamp(1, n.max(1));
+            let share = n.div_ceil(k);
+            let mut part = vec![NONE; n];
+            let mut sizes = vec![0; k];
+            let (mut assigned, mut next_start) = (0, 0);
+            for (p, size) in sizes.iter_mut().enumerate() {
+                let target = (n - assigned).div_ceil(k - p);
+                let mut queue = VecDeque::new();
+                while *size < target {
+                    let v = queue.pop_front().unwrap_or_else(|| {
+                        while part[next_start] != NONE {
+                            next_start += 1;
+                        }
+                        next_start
+                    });
+                    if part[v] != NONE {
+                        continue;
+                    }
+                    part[v] = p;
+                    *size += 1;
+                    assigned += 1;
+                    queue.extend(neighbours[v].iter().filter(|&&w| part[w] == NONE));
+                }
+            }
+            for _ in 0..MAX_PASSES {
+                let mut moved = false;
+                for v in 0..n {
+                    let mut links = vec![0; k];
+                    for &w in &neighbours[v] {
+                        links[part[w]] += 1;
+                    }
+                    let here = part[v];
+                    let best = (0..k)
+                        .filter(|&q| q != here && sizes[q] < share)
+                        .max_by_key(|&q| (links[q], Reverse(q)));
+                    if let Some(q) = best.filter(|&q| links[q] > links[here] && sizes[here] > 1) {
+                        part[v] = q;
+                        sizes[here] -= 1;
+                        sizes[q] += 1;
+                        moved = true;
+                    }
+                }
+                if !moved {
+                    break;
+                }
+            }
+            names.into_iter().zip(part).collect()
+        }
+
+        /// Give every node a `partition` attribute holding its [`Graph::partition`]
+        /// number, so [`Graph::cluster_by_attr`] can box the parts or
+        /// [`Query`](query::Query) pick one out. Edge endpoints that were never declared
+        /// are added as nodes to carry it.
+        pub fn with_partition(mut self, k: usize) -> Self {
+            let parts = self
+                .partition(k)
+                .into_iter()
+                .map(|(name, part)| (name.to_string(), part.to_string()))
+                .collect::<Vec<_>>();
+            for (name, part) in parts {
+                self.update_node(name, |node| node.with_attrs(&[("partition", &part)]));
+            }
+            self
+        }
+    }
+
+    /// Edmonds' blossom algorithm: each vertex's partner in a maximum matching.
+    fn blossom_matching(neighbours: &[Vec<usize>]) -> Vec<Option<usize>> {
+        const NONE: usize = usize::MAX;
+        let n = neighbours.len();
+        let mut mate = vec![NONE; n];
+        // Per search: the tree parent of each odd vertex, the base of the blossom each
+        // vertex has been shrunk into, and which vertices are on the queue's even side.
+        let mut parent = vec![NONE; n];
+        let mut base = (0..n).collect::<Vec<_>>();
+        let mut even = vec![false; n];
+
+        // The base of the first blossom the tree paths from `a` and `b` share.
+        let common_base = |mate: &[usize], parent: &[usize], base: &[usize], a, b| {
+            let mut on_path = vec![false; n];
+            let mut a = a;
+            loop {
+                a = base[a];
+                on_path[a] = true;
+                if mate[a] == NONE {
+                    break;
+                }
+                a = parent[mate[a]];
+            }
+            let mut b = b;
+            loop {
+                b = base[b];
+                if on_path[b] {
+                    return b;
+                }
+                b = parent[mate[b]];
+            }
+        };
+        // Flag the blossoms from `v` down to `stem`, pointing odd vertices back along
+        // the cycle so an augmenting path can later run through it either way.
+        let mark = |mate: &[usize],
+                    parent: &mut [usize],
+                    base: &[usize],
+                    in_blossom: &mut [bool],
+                    mut v: usize,
+                    stem: usize,
+                    mut child: usize| {
+            while base[v] != stem {
+                in_blossom[base[v]] = true;
+                in_blossom[base[mate[v]]] = true;
+                parent[v] = child;
+                child = mate[v];
+                v = parent[mate[v]];
+            }
+        };
+
+        for root in 0..n {
+            if mate[root] != NONE {
+                continue;
+            }
+            parent.fill(NONE);
+            even.fill(false);
+            base.iter_mut().enumerate().for_each(|(i, b)| *b = i);
+            even[root] = true;
+            let mut queue = VecDeque::from([root]);
+            let mut free = NONE;
+            'search: while let Some(v) = queue.pop_front() {
+                for &w in &neighbours[v] {
+                    if base[v] == base[w] || mate[v] == w {
+                        continue;
+                    }
+                    if w == root || (mate[w] != NONE && parent[mate[w]] != NONE) {
+                        // An odd cycle: shrink it into a blossom based at `stem`.
+                        let stem = common_base(&mate, &parent, &base, v, w);
+                        let mut in_blossom = vec![false; n];
+                        mark(&mate, &mut parent, &base, &mut in_blossom, v, stem, w);
+                        mark(&mate, &mut parent, &base, &mut in_blossom, w, stem, v);
+                        for i in 0..n {
+                            if in_blossom[base[i]] {
+                                base[i] = stem;
+                                if !even[i] {
+                                    even[i] = true;
+                                    queue.push_back(i);
+                                }
+                            }
+                        }
+                    } else if parent[w] == NONE {
+                        parent[w] = v;
+                        if mate[w] == NONE {
+                            free = w;
+                            break 'search;
+                        }
+                        even[mate[w]] = true;
+                        queue.push_back(mate[w]);
+                    }
+                }
+            }
+            // Flip the augmenting path from the free vertex back to the root.
+            while free != NONE {
+                let v = parent[free];
+                let next = mate[v];
+                mate[free] = v;
+                mate[v] = free;
+                free = next;
+            }
+        }
+        mate.into_iter().map(|v| (v != NONE).then_some(v)).collect()
+    }
+
+    // -------------------------------------------------------------------------
+    // SAMPLING
+    // -------------------------------------------------------------------------
+
+    impl Graph {
+        /// Up to `n` nodes picked at random and the edges between them. Declarations,
+        /// attributes and subgraphs carry over for what is kept. The same `seed` always
+        /// picks the same nodes.
+        pub fn sample_nodes(&self, n: usize, seed: u64) -> Graph {
+            let mut names = self.node_names();
+            SplitMix64(seed).shuffle(&mut names);
+            names.truncate(n);
+            self.restricted(&names.into_iter().collect(), None)
+        }
+
+        /// Up to `n` edges picked at random, with the nodes they join. As
+        /// [`Graph::sample_nodes`], the rest carries over and `seed` fixes the pick.
+        pub fn sample_edges(&self, n: usize, seed: u64) -> Graph {
+            let mut picked = (0..self.edges.len()).collect::<Vec<_>>();
+            SplitMix64(seed).shuffle(&mut picked);
+            picked.truncate(n);
+            let picked = picked.into_iter().collect::<HashSet<_>>();
+            let keep = picked
+                .iter()
+                .flat_map(|&i| [self.edges[i].node1(), self.edges[i].node2()])
+                .collect::<HashSet<_>>();
+            self.restricted(&keep, Some(&picked))
+        }
+
+        /// The first `n` nodes met spreading out from `start` one ring of neighbors at a
+        /// time, in either direction, and the edges between them. Empty if `start` isn't
+        /// in the graph.
+        pub fn snowball_sample(&self, start: &str, n: usize) -> Graph {
+            let start = self.node_names().into_iter().find(|name| *name == start);
+            let Some(start) = start.filter(|_| n > 0) else {
+                return self.restricted(&HashSet::new(), None);
+            };
+            let mut keep = HashSet::from([start]);
+            let mut queue = VecDeque::from([start]);
+            while let Some(name) = queue.pop_front() {
+                for next in self.neighbors(name) {
+                    if keep.len() >= n {
+                        break;
+                    }
+                    if keep.insert(next) {
+                        queue.push_back(next);
+                    }
+                }
+            }
+            self.restricted(&keep, None)
+        }
+
+        /// The graph cut down to the nodes in `keep` and either the edges at the positions
+        /// in `edges` or, without those, every edge between kept nodes.
+        fn restricted(&self, keep: &HashSet<&str>, edges: Option<&HashSet<usize>>) -> Graph {
+            let nodes = self
+                .nodes
+                .iter()
+                .filter(|node| keep.contains(node.name()))
+                .cloned()
+                .collect::<Vec<_>>();
+            let edges = self
+                .edges
+                .iter()
+                .enumerate()
+                .filter(|(i, edge)| match edges {
+                    Some(picked) => picked.contains(i),
+                    None => keep.contains(edge.node1()) && keep.contains(edge.node2()),
+                })
+                .map(|(_, edge)| edge.clone())
+                .collect();
+            let subgraphs = self
+                .subgraphs
+                .iter()
+                .map(|subgraph| subgraph.clone().retaining_nodes(|n| keep.contains(n)))
+                .filter(|subgraph| !subgraph.nodes().is_empty())
+                .collect();
+            Graph {
+                node_index: Arc::new(index_by_name(&nodes)),
+                nodes: Arc::new(nodes),
+                edges: Arc::new(edges),
+                adjacency: Cache::default(),
+                subgraphs,
+                observers: Observers::default(),
+                snapshots: Snapshots::default(),
+                ..self.clone()
+            }
+        }
+    }
+
+    // -------------------------------------------------------------------------
+    // PATTERNS
+    // -------------------------------------------------------------------------
+
+    /// Where [`Graph::find_pattern`] found the pattern: pattern node names paired
+    /// with the graph nodes they landed on, and the graph edges the pattern's edges
+    /// matched, both in pattern order.
+    #[derive(Debug, PartialEq, Eq, Clone)]
+    pub struct Match<'a> {
+        nodes: Vec<(&'a str, &'a str)>,
+        edges: Vec<&'a Edge>,
+    }
+
+    impl<'a> Match<'a> {
+        /// The graph node standing in for pattern node `name`.
+        pub fn node(&self, name: &str) -> Option<&'a str> {
+            self.nodes
+                .iter()
+                .find(|(pattern, _)| *pattern == name)
+                .map(|(_, node)| *node)
+        }
+
+        pub fn nodes(&self) -> &[(&'a str, &'a str)] {
+            &self.nodes
+        }
+
+        pub fn edges(&self) -> &[&'a Edge] {
+            &self.edges
+        }
+    }
+
+    impl Graph {
+        /// Every placement of `pattern` in this graph: distinct pattern nodes go to
+        /// distinct graph nodes, and each pattern edge needs a graph edge between the
+        /// matching nodes. Attributes on pattern nodes and edges must also be present,
+        /// with equal values, on what they match; anything else is ignored, so extra
+        /// graph edges do not spoil a match.
+        ///
+        /// Symmetric patterns match once per symmetry: the cycle `a -> b -> a` is
+        /// found both as `a = x, b = y` and as `a = y, b = x`.
+        pub fn find_pattern<'a>(&'a self, pattern: &'a Graph) -> Vec<Match<'a>> {
+            let wanted = pattern.node_names();
+            let candidates = self.node_names();
+            let mut matches = Vec::new();
+            let mut assigned = Vec::with_capacity(wanted.len());
+            self.extend_match(pattern, &wanted, &candidates, &mut assigned, &mut matches);
+            matches
+        }
+
+        /// Backtracking step: place `wanted[assigned.len()]` on each candidate that
+        /// keeps every pattern edge between placed nodes satisfied.
+        fn extend_match<'a>(
+            &'a self,
+            pattern: &'a Graph,
+            wanted: &[&'a str],
+            candidates: &[&'a str],
+            assigned: &mut Vec<&'a str>,
+            matches: &mut Vec<Match<'a>>,
+        ) {
+            let Some(&next) = wanted.get(assigned.len()) else {
+                let edges = pattern
+                    .edges
+                    .iter()
+                    .filter_map(|edge| {
+                        self.matching_edge(
+                            edge,
+                            Self::placed(wanted, assigned, edge.node1())?,
+                            Self::placed(wanted, assigned, edge.node2())?,
+                        )
+                    })
+                    .collect();
+                let nodes = wanted
+                    .iter()
+                    .copied()
+                    .zip(assigned.iter().copied())
+                    .collect();
+                matches.push(Match { nodes, edges });
+                return;
+            };
+            for &candidate in candidates {
+                if assigned.contains(&candidate) {
+                    continue;
+                }
+                let fits = match (pattern.node(next), self.node(candidate)) {
+                    (Some(wanted), Some(node)) => wanted
+                        .attrs()
+                        .all(|(key, value)| node.attr(key) == Some(value)),
+                    (Some(wanted), None) => wanted.attrs().next().is_none(),
+                    (None, _) => true,
+                };
+                if !fits {
+                    continue;
+                }
+                assigned.push(candidate);
+                let edges_fit = pattern.edges.iter().all(|edge| {
+                    match (
+                        Self::placed(wanted, assigned, edge.node1()),
+                        Self::placed(wanted, assigned, edge.node2()),
+                    ) {
+                        (Some(node1), Some(node2))
+                            if edge.node1() == next || edge.node2() == next =>
+                        {
+                            self.matching_edge(edge, node1, node2).is_some()
+                        }
+                        _ => true,
+                    }
+                });
+                if edges_fit {
+                    self.extend_match(pattern, wanted, candidates, assigned, matches);
+                }
+                assigned.pop();
+            }
+        }
+
+        /// The first edge from `node1` to `node2` (either way round in an undirected
+        /// graph) carrying all of `wanted`'s attributes.
+        fn matching_edge(&self, wanted: &Edge, node1: &str, node2: &str) -> Option<&Edge> {
+            let adjacency = self.adjacency();
+            let forward = adjacency
+                .outgoing(node1)
+                .iter()
+                .map(|&i| &self.edges[i])
+                .filter(|edge| edge.node2() == node2);
+            let backward = adjacency
+                .outgoing(node2)
+                .iter()
+                .map(|&i| &self.edges[i])
+                .filter(|edge| !self.directed && edge.node2() == node1);
+            forward.chain(backward).find(|edge| {
+                wanted
+                    .attrs()
+                    .all(|(key, value)| edge.attr(key) == Some(value))
+            })
+        }
+
+        /// The graph node pattern node `name` was placed on, if it has been yet.
+        fn placed<'a>(wanted: &[&str], assigned: &[&'a str], name: &str) -> Option<&'a str> {
+            let i = wanted.iter().position(|w| *w == name)?;
+            assigned.get(i).copied()
+        }
+
+        /// Declared nodes followed by edge endpoints that were never declared.
+        fn node_names(&self) -> Vec<&str> {
+            let mut seen = HashSet::new();
+            self.nodes
+                .iter()
+                .map(Node::name)
+                .chain(
+                    self.edges
+                        .iter()
+                        .flat_map(|edge| [edge.node1(), edge.node2()]),
+                )
+                .filter(|name| seen.insert(*name))
+                .collect()
+        }
+    }
+
+    // -------------------------------------------------------------------------
+    // LINTING
+    // -------------------------------------------------------------------------
+
+    /// Attributes Graphviz only honours on `cluster...` subgraphs.
+    const CLUSTER_ATTRS: [&str; 8] = [
+        "bgcolor",
+        "color",
+        "fillcolor",
+        "label",
+        "pencolor",
+        "penwidth",
+        "peripheries",
+        "style",
+    ];
+
+    impl Graph {
+        /// Likely mistakes that still make valid DOT, in node, edge, then subgraph order.
+        pub fn lint(&self) -> Vec<Lint> {
+            let adjacency = self.adjacency();
+            let unused = self
+                .nodes
+                .iter()
+                .filter(|node| {
+                    adjacency.outgoing(node.name()).is_empty()
+                        && adjacency.incoming(node.name()).is_empty()
+                })
+                .map(|node| Lint::UnusedNode(node.name().to_string()));
+            let invisible_nodes = self
+                .nodes
+                .iter()
+                .filter(|node| {
+                    node.attr("label").is_some() && node.styles().contains(&Style::Invis)
+                })
+                .map(|node| Lint::InvisibleNodeLabel(node.name().to_string()));
+            let duplicates = self
+                .edges
+                .iter()
+                .enumerate()
+                .filter(|&(i, edge)| self.find_edge(edge.node1(), edge.node2()) != Some(i))
+                .map(|(_, edge)| Lint::DuplicateEdge(edge.node1().into(), edge.node2().into()));
+            let invisible_edges = self
+                .edges
+                .iter()
+                .filter(|edge| {
+                    edge.attr("label").is_some() && edge.styles().contains(&Style::Invis)
+                })
+                .map(|edge| Lint::InvisibleEdgeLabel(edge.node1().into(), edge.node2().into()));
+            let not_clusters = self
+                .subgraphs
+                .iter()
+                .filter(|subgraph| {
+                    !subgraph.is_cluster()
+                        && subgraph
+                            .attrs()
+                            .any(|(key, _)| CLUSTER_ATTRS.contains(&key))
+                })
+                .map(|subgraph| Lint::NotACluster(subgraph.name().to_string()));
+
+            unused
+                .chain(invisible_nodes)
+                .chain(duplicates)
+                .chain(invisible_edges)
+                .chain(not_clusters)
+                .collect()
+        }
+    }
+
+    // -------------------------------------------------------------------------
+    // VISITING
+    // -------------------------------------------------------------------------
+
+    /// Callbacks for [`Graph::accept`]; every hook does nothing unless overridden.
+    pub trait GraphVisitor {
+        fn visit_graph_attr(&mut self, _key: &str, _value: &str) {}
+
+        fn visit_node(&mut self, _node: &Node) {}
+
+        fn visit_subgraph(&mut self, _subgraph: &Subgraph) {}
+
+        fn visit_edge(&mut self, _edge: &Edge) {}
+    }
+
+    impl Graph {
+        /// Walk the graph in the order `to_dot` writes it: graph attributes sorted by key,
+        /// then nodes, subgraphs and edges.
+        pub fn accept(&self, visitor: &mut impl GraphVisitor) {
+            let mut attrs = self.attrs.iter().collect::<Vec<_>>();
+            attrs.sort();
+            for (key, value) in attrs {
+                visitor.visit_graph_attr(key, value);
+            }
+            for node in self.nodes.iter() {
+                visitor.visit_node(node);
+            }
+            for subgraph in &self.subgraphs {
+                visitor.visit_subgraph(subgraph);
+            }
+            for edge in self.edges.iter() {
+                visitor.visit_edge(edge);
+            }
+        }
+    }
+
+    // -------------------------------------------------------------------------
+    // STREAMING
+    // -------------------------------------------------------------------------
+
+    /// Receives a graph one statement at a time, from [`parser::parse_into`],
+    /// [`Graph::emit`] or any other producer, so a large graph never has to be held whole.
+    ///
+    /// Calls arrive as `begin_graph`, then any mix of the item methods, then `end_graph`.
+    pub trait GraphSink {
+        fn begin_graph(&mut self, _directed: bool) {}
+
+        fn graph_attrs(&mut self, _attrs: &[(&str, &str)]) {}
+
+        fn node_defaults(&mut self, _attrs: &[(&str, &str)]) {}
+
+        fn edge_defaults(&mut self, _attrs: &[(&str, &str)]) {}
+
+        fn node(&mut self, _node: Node) {}
+
+        fn subgraph(&mut self, _subgraph: Subgraph) {}
+
+        fn edge(&mut self, _edge: Edge) {}
+
+        fn end_graph(&mut self) {}
+    }
+
+    /// Discards everything, for when only the parse's success matters.
+    impl GraphSink for () {}
+
+    /// Collects everything into the graph; panics on duplicates under the `Error` policies.
+    impl GraphSink for Graph {
+        fn begin_graph(&mut self, directed: bool) {
+            self.directed = directed;
+            self.adjacency = Cache::default();
+        }
+
+        fn graph_attrs(&mut self, attrs: &[(&str, &str)]) {
+            self.set_attrs(attrs);
+        }
+
+        fn node_defaults(&mut self, attrs: &[(&str, &str)]) {
+            self.node_defaults = self
+                .cascade
+                .merge_into(std::mem::take(&mut self.node_defaults), attrs);
+        }
+
+        fn edge_defaults(&mut self, attrs: &[(&str, &str)]) {
+            self.edge_defaults = self
+                .cascade
+                .merge_into(std::mem::take(&mut self.edge_defaults), attrs);
+        }
+
+        fn node(&mut self, node: Node) {
+            self.add_node(node)
+                .unwrap_or_else(|error| panic!("{error}"));
+        }
+
+        fn subgraph(&mut self, subgraph: Subgraph) {
+            self.subgraphs.push(subgraph);
+        }
+
+        fn edge(&mut self, edge: Edge) {
+            self.add_edge(edge)
+                .unwrap_or_else(|error| panic!("{error}"));
+        }
+    }
+
+    impl Graph {
+        /// Feed the graph to `sink` in the order `to_dot` writes it.
+        pub fn emit(&self, sink: &mut impl GraphSink) {
+            sink.begin_graph(self.directed);
+            let inherit = self.cascade.subgraph_inheritance;
+            if inherit && !self.attrs.is_empty() {
+                sink.graph_attrs(&pairs_of(&self.attrs));
+            }
+            if !self.node_defaults.is_empty() {
+                sink.node_defaults(&pairs_of(&self.node_defaults));
+            }
+            if !self.edge_defaults.is_empty() {
+                sink.edge_defaults(&pairs_of(&self.edge_defaults));
+            }
+            for node in self.nodes.iter() {
+                sink.node(node.clone());
+            }
+            for subgraph in &self.subgraphs {
+                sink.subgraph(subgraph.clone());
+            }
+            if !inherit && !self.attrs.is_empty() {
+                sink.graph_attrs(&pairs_of(&self.attrs));
+            }
+            for edge in self.edges.iter() {
+                sink.edge(edge.clone());
+            }
+            sink.end_graph();
+        }
+    }
+
+    /// A [`GraphSink`] writing DOT as statements arrive, in the same layout as `to_dot`
+    /// except that items stay in the order they were received.
+    #[derive(Debug)]
+    pub struct DotWriter<W> {
+        out: W,
+        directed: bool,
+        error: Option<io::Error>,
+    }
+
+    impl<W: io::Write> DotWriter<W> {
+        pub fn new(out: W) -> Self {
+            DotWriter {
+                out,
+                directed: false,
+                error: None,
+            }
+        }
+
+        /// The writer back, or the first write error, which stopped all later output.
+        pub fn finish(self) -> io::Result<W> {
+            match self.error {
+                Some(error) => Err(error),
+                None => Ok(self.out),
+            }
+        }
+
+        fn write(&mut self, text: &str) {
+            if self.error.is_none() {
+                if let Err(error) = self.out.write_all(text.as_bytes()) {
+                    self.error = Some(error);
+                }
+            }
+        }
+    }
+
+    impl<W: io::Write> GraphSink for DotWriter<W> {
+        fn begin_graph(&mut self, directed: bool) {
+            self.directed = directed;
+            self.write(if directed { "digraph {\n" } else { "graph {\n" });
+        }
+
+        fn graph_attrs(&mut self, attrs: &[(&str, &str)]) {
+            self.write(&format!(
+                "    graph{}\n",
+                render_attr_list(attrs.iter().copied())
+            ));
+        }
+
+        fn node_defaults(&mut self, attrs: &[(&str, &str)]) {
+            self.write(&format!(
+                "    node{}\n",
+                render_attr_list(attrs.iter().copied())
+            ));
+        }
+
+        fn edge_defaults(&mut self, attrs: &[(&str, &str)]) {
+            self.write(&format!(
+                "    edge{}\n",
+                render_attr_list(attrs.iter().copied())
+            ));
+        }
+
+        fn node(&mut self, node: Node) {
+            self.write(&render_node(&node));
+        }
+
+        fn subgraph(&mut self, subgraph: Subgraph) {
+            self.write(&render_subgraph(&subgraph, &[]));
+        }
+
+        fn edge(&mut self, edge: Edge) {
+            self.write(&render_edge(&edge, self.directed));
+        }
+
+        fn end_graph(&mut self) {
+            self.write("}\n");
+        }
+    }
+
+    // -------------------------------------------------------------------------
+    // HISTORY
+    // -------------------------------------------------------------------------
+
+    impl Graph {
+        /// Remember the graph as it is now under `label`, replacing any earlier version
+        /// with that label.
+        pub fn snapshot(&mut self, label: &str) {
+            let version = Graph {
+                snapshots: Snapshots::default(),
+                ..self.clone()
+            };
+            let snapshots = Arc::make_mut(&mut self.snapshots.0);
+            snapshots.retain(|(existing, _)| existing != label);
+            snapshots.push((label.to_string(), version));
+        }
+
+        /// The version recorded under `label`.
+        pub fn version(&self, label: &str) -> Option<&Graph> {
+            self.snapshots
+                .0
+                .iter()
+                .find(|(existing, _)| existing == label)
+                .map(|(_, version)| version)
+        }
+
+        /// Labels of the recorded versions, oldest first.
+        pub fn snapshot_labels(&self) -> impl Iterator<Item = &str> {
+            self.snapshots.0.iter().map(|(label, _)| label.as_str())
+        }
+
+        /// Go back to the version recorded under `label`, keeping every snapshot.
+        /// Returns `false`, changing nothing, if there is no such version.
+        pub fn restore(&mut self, label: &str) -> bool {
+            match self.version(label).cloned() {
+                Some(version) => {
+                    let snapshots = std::mem::take(&mut self.snapshots);
+                    *self = Graph {
+                        snapshots,
+                        ..version
+                    };
+                    true
+                }
+                None => false,
+            }
+        }
+    }
+
+    /// A graph that remembers its earlier versions for `undo` and `redo`.
+    ///
+    /// Every version is a `Graph` clone, which shares whatever collections the change
+    /// left alone, so a step costs about as much as the change itself.
+    #[derive(Debug, Clone)]
+    pub struct HistoryGraph {
+        current: Graph,
+        undo: Vec<Graph>,
+        redo: Vec<Graph>,
+    }
+
+    impl HistoryGraph {
+        pub fn new(graph: Graph) -> Self {
+            HistoryGraph {
+                current: graph,
+                undo: Vec::new(),
+                redo: Vec::new(),
+            }
+        }
+
+        pub fn graph(&self) -> &Graph {
+            &self.current
+        }
+
+        pub fn into_graph(self) -> Graph {
+            self.current
+        }
+
+        /// Make one undoable change with the builder API, e.g. `|g| g.with_attrs(..)`.
+        pub fn apply(&mut self, change: impl FnOnce(Graph) -> Graph) {
+            let next = change(self.current.clone());
+            self.push(next);
+        }
+
+        /// Nothing is recorded when the node is rejected.
+        pub fn add_node(&mut self, node: Node) -> Result<(), GraphError> {
+            let mut next = self.current.clone();
+            next.add_node(node)?;
+            self.push(next);
+            Ok(())
+        }
+
+        /// Nothing is recorded when the edge is rejected.
+        pub fn add_edge(&mut self, edge: Edge) -> Result<(), GraphError> {
+            let mut next = self.current.clone();
+            next.add_edge(edge)?;
+            self.push(next);
+            Ok(())
+        }
+
+        /// Nothing is recorded when there is no such node.
+        pub fn remove_node(&mut self, name: &str) -> Option<Node> {
+            let mut next = self.current.clone();
+            let node = next.remove_node(name)?;
+            self.push(next);
+            Some(node)
+        }
+
+        /// Nothing is recorded when there is no such edge.
+        pub fn remove_edge(&mut self, node1: &str, node2: &str) -> Option<Edge> {
+            let mut next = self.current.clone();
+            let edge = next.remove_edge(node1, node2)?;
+            self.push(next);
+            Some(edge)
+        }
+
+        /// Step back one change; `false` if there is nothing to undo.
+        pub fn undo(&mut self) -> bool {
+            match self.undo.pop() {
+                Some(previous) => {
+                    self.redo
+                        .push(std::mem::replace(&mut self.current, previous));
+                    true
+                }
+                None => false,
+            }
+        }
+
+        /// Reapply the last undone change; `false` if there is nothing to redo.
+        pub fn redo(&mut self) -> bool {
+            match self.redo.pop() {
+                Some(next) => {
+                    self.undo.push(std::mem::replace(&mut self.current, next));
+                    true
+                }
+                None => false,
+            }
+        }
+
+        pub fn can_undo(&self) -> bool {
+            !self.undo.is_empty()
+        }
+
+        pub fn can_redo(&self) -> bool {
+            !self.redo.is_empty()
+        }
+
+        /// A new change forgets everything that was undone.
+        fn push(&mut self, next: Graph) {
+            self.undo.push(std::mem::replace(&mut self.current, next));
+            self.redo.clear();
+        }
+    }
+
+    // -------------------------------------------------------------------------
+    // SHARING
+    // -------------------------------------------------------------------------
+
+    /// A graph many threads can read while others update it.
+    ///
+    /// Readers take a [`snapshot`](SharedGraph::snapshot), an immutable `Arc<Graph>` with
+    /// the whole read API, and never wait on an update in progress for longer than it
+    /// takes to clone an `Arc`. Updates are made on a copy, so a panicking update leaves
+    /// the shared graph untouched.
+    #[derive(Debug, Clone)]
+    pub struct SharedGraph {
+        current: Arc<RwLock<Arc<Graph>>>,
+        /// Held for a whole update so writers take turns without blocking readers.
+        writer: Arc<Mutex<()>>,
+    }
+
+    impl SharedGraph {
+        pub fn new(graph: Graph) -> Self {
+            SharedGraph {
+                current: Arc::new(RwLock::new(Arc::new(graph))),
+                writer: Arc::default(),
+            }
+        }
+
+        /// The graph as of the latest finished update.
+        pub fn snapshot(&self) -> Arc<Graph> {
+            let current = self.current.read().unwrap_or_else(PoisonError::into_inner);
+            Arc::clone(&current)
+        }
+
+        /// Change the graph; concurrent updates run one after the other.
+        pub fn update<T>(&self, change: impl FnOnce(&mut Graph) -> T) -> T {
+            // Poisoning only means an earlier update panicked, before it stored anything.
+            let _writer = self.writer.lock().unwrap_or_else(PoisonError::into_inner);
+            let mut next = Graph::clone(&self.snapshot());
+            let result = change(&mut next);
+            *self.current.write().unwrap_or_else(PoisonError::into_inner) = Arc::new(next);
+            result
+        }
+
+        /// Swap in a whole new graph, returning the old one.
+        pub fn replace(&self, graph: Graph) -> Arc<Graph> {
+            let _writer = self.writer.lock().unwrap_or_else(PoisonError::into_inner);
+            let mut current = self.current.write().unwrap_or_else(PoisonError::into_inner);
+            std::mem::replace(&mut *current, Arc::new(graph))
+        }
+
+        pub fn to_dot(&self) -> String {
+            self.snapshot().to_dot()
+        }
+    }
+
+    impl FromStr for Graph {
+        type Err = ParseError;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            Graph::from_dot(s)
+        }
+    }
+
+    impl fmt::Display for Graph {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str(&self.to_dot())
+        }
+    }
+
+    /// Everything that can go wrong while building a graph.
+    #[derive(Debug, PartialEq, Clone)]
+    pub enum GraphError {
+        /// A numeric attribute was NaN, infinite or negative.
+        InvalidNumber { key: String, value: f64 },
+        /// A node name was added twice under [`DuplicateNodes::Error`].
+        DuplicateNode(String),
+        /// An edge was added twice under [`DuplicateEdges::Error`].
+        DuplicateEdge(String, String),
+        /// A node name was empty or contained control characters.
+        InvalidName(String),
+    }
+
+    impl fmt::Display for GraphError {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self {
+                GraphError::InvalidNumber { key, value } => {
+                    write!(f, "invalid value {value} for attribute `{key}`")
+                }
+                GraphError::DuplicateNode(name) => write!(f, "duplicate node `{name}`"),
+                GraphError::DuplicateEdge(node1, node2) => {
+                    write!(f, "duplicate edge `{node1} -- {node2}`")
+                }
+                GraphError::InvalidName(name) => write!(f, "invalid node name {name:?}"),
+            }
+        }
+    }
+
+    impl Error for GraphError {}
+
+    /// A value that describes itself as attributes, for [`Node::with_attrs_from`] and
+    /// [`Edge::with_attrs_from`]. With the `derive` feature, `#[derive(IntoAttrs)]`
+    /// writes this from a struct's fields.
+    pub trait IntoAttrs {
+        /// `(key, value)` pairs, applied in order.
+        fn to_attrs(&self) -> Vec<(&'static str, String)>;
+    }
+
+    #[cfg(feature = "derive")]
+    pub use dot_dsl_derive::IntoAttrs;
+
+    /// The attribute changes between two nodes or edges, from `attr_diff`. Each list is
+    /// sorted by key.
+    #[derive(Debug, PartialEq, Eq, Clone, Default)]
+    pub struct AttrDiff<'a> {
+        added: Vec<(&'a str, &'a str)>,
+        removed: Vec<(&'a str, &'a str)>,
+        changed: Vec<(&'a str, &'a str, &'a str)>,
+    }
+
+    impl<'a> AttrDiff<'a> {
+        fn between(
+            old: impl Iterator<Item = (&'a str, &'a str)>,
+            new: impl Iterator<Item = (&'a str, &'a str)>,
+        ) -> Self {
+            let old = old.collect::<HashMap<_, _>>();
+            let new = new.collect::<HashMap<_, _>>();
+            let mut diff = AttrDiff::default();
+            for (&key, &value) in &new {
+                match old.get(key) {
+                    None => diff.added.push((key, value)),
+                    Some(&before) if before != value => diff.changed.push((key, before, value)),
+                    Some(_) => {}
+                }
+            }
+            for (&key, &value) in &old {
+                if !new.contains_key(key) {
+                    diff.removed.push((key, value));
+                }
+            }
+            diff.added.sort_unstable();
+            diff.removed.sort_unstable();
+            diff.changed.sort_unstable();
+            diff
+        }
+
+        /// Keys only the new side has, with their values.
+        pub fn added(&self) -> &[(&'a str, &'a str)] {
+            &self.added
+        }
+
+        /// Keys only the old side has, with their values.
+        pub fn removed(&self) -> &[(&'a str, &'a str)] {
+            &self.removed
+        }
+
+        /// Keys with different values, as `(key, old, new)`.
+        pub fn changed(&self) -> &[(&'a str, &'a str, &'a str)] {
+            &self.changed
+        }
+
+        pub fn is_empty(&self) -> bool {
+            self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+        }
+    }
+
+    /// One line per change: `+key=value`, `-key=value` or `~key: old -> new`.
+    impl fmt::Display for AttrDiff<'_> {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            for (key, value) in &self.added {
+                writeln!(f, "+{key}={value}")?;
+            }
+            for (key, value) in &self.removed {
+                writeln!(f, "-{key}={value}")?;
+            }
+            for (key, old, new) in &self.changed {
+                writeln!(f, "~{key}: {old} -> {new}")?;
+            }
+            Ok(())
+        }
+    }
+
+    /// A warning from [`Graph::lint`].
+    #[derive(Debug, PartialEq, Eq, Clone)]
+    pub enum Lint {
+        /// A node that no edge touches.
+        UnusedNode(String),
+        /// An edge repeating an earlier one, possibly with other attributes.
+        DuplicateEdge(String, String),
+        /// A node with both `style=invis` and a `label`, which is never drawn.
+        InvisibleNodeLabel(String),
+        /// An edge with both `style=invis` and a `label`, which is never drawn.
+        InvisibleEdgeLabel(String, String),
+        /// A subgraph with cluster attributes, such as `label`, whose name doesn't start
+        /// with `cluster`, so they are ignored.
+        NotACluster(String),
+    }
+
+    impl fmt::Display for Lint {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self {
+                Lint::UnusedNode(name) => write!(f, "node `{name}` has no edges"),
+                Lint::DuplicateEdge(node1, node2) => {
+                    write!(f, "edge `{node1} -- {node2}` is repeated")
+                }
+                Lint::InvisibleNodeLabel(name) => {
+                    write!(f, "node `{name}` is invisible but has a label")
+                }
+                Lint::InvisibleEdgeLabel(node1, node2) => {
+                    write!(f, "edge `{node1} -- {node2}` is invisible but has a label")
+                }
+                Lint::NotACluster(name) => write!(
+                    f,
+                    "subgraph `{name}` has cluster attributes but its name lacks the `cluster` prefix"
+                ),
+            }
+        }
+    }
+
+    /// The two-way mapping produced by [`Graph::sanitize_ids`].
+    #[derive(Debug, PartialEq, Eq, Clone, Default)]
+    pub struct IdMap {
+        to_sanitized: HashMap<String, String>,
+        to_original: HashMap<String, String>,
+    }
+
+    impl IdMap {
+        pub fn sanitized(&self, original: &str) -> Option<&str> {
+            self.to_sanitized.get(original).map(|s| s.as_str())
+        }
+
+        pub fn original(&self, sanitized: &str) -> Option<&str> {
+            self.to_original.get(sanitized).map(|s| s.as_str())
+        }
+
+        pub fn len(&self) -> usize {
+            self.to_original.len()
+        }
+
+        pub fn is_empty(&self) -> bool {
+            self.to_original.is_empty()
+        }
+
+        /// Register `original`, picking a sanitized name not used so far.
+        fn with_original(mut self, original: &str) -> Self {
+            if self.to_sanitized.contains_key(original) {
+                return self;
+            }
+            let base = sanitize_id(original);
+            let sanitized = (1..)
+                .map(|n| {
+                    if n == 1 {
+                        base.clone()
+                    } else {
+                        format!("{base}_{n}")
+                    }
+                })
+                .find(|candidate| !self.to_original.contains_key(candidate))
+                .expect("an unused suffix always exists");
+
+            self.to_sanitized
+                .insert(original.to_string(), sanitized.clone());
+            self.to_original.insert(sanitized, original.to_string());
+            self
+        }
+    }
+
+    /// Ready-made undirected graphs whose `n` nodes are named `0` to `n - 1`.
+    pub mod generators {
+        use crate::graph::graph_items::edge::Edge;
+        use crate::graph::graph_items::node::Node;
+        use crate::graph::{Graph, SplitMix64};
+
+        /// `0 -- 1 -- ... -- n - 1`.
+        pub fn path(n: usize) -> Graph {
+            build(n, (1..n).map(|i| (i - 1, i)))
+        }
+
+        /// A path that closes back on `0`; needs at least three nodes to have any edges.
+        pub fn cycle(n: usize) -> Graph {
+            let closing = (n >= 3).then(|| (n - 1, 0));
+            build(n, (1..n).map(|i| (i - 1, i)).chain(closing))
+        }
+
+        /// Every pair of nodes shares one edge.
+        pub fn complete(n: usize) -> Graph {
+            build(n, (0..n).flat_map(|i| (i + 1..n).map(move |j| (i, j))))
+        }
+
+        /// Node `0` joined to each of the other `n - 1` nodes.
+        pub fn star(n: usize) -> Graph {
+            build(n, (1..n).map(|i| (0, i)))
+        }
+
+        /// Nodes `0` to `m - 1` each joined to all of `m` to `m + n - 1`.
+        pub fn complete_bipartite(m: usize, n: usize) -> Graph {
+            build(m + n, (0..m).flat_map(|i| (m..m + n).map(move |j| (i, j))))
+        }
+
+        /// Erdős–Rényi graph: each pair of nodes shares an edge with probability `p`.
+        ///
+        /// The same `seed` always gives the same graph.
+        pub fn gnp(n: usize, p: f64, seed: u64) -> Graph {
+            let mut rng = SplitMix64(seed);
+            let pairs = (0..n)
+                .flat_map(|i| (i + 1..n).map(move |j| (i, j)))
+                .filter(|_| rng.next_f64() < p)
+                .collect::<Vec<_>>();
+            build(n, pairs.into_iter())
+        }
+
+        /// Barabási–Albert graph: after the first `m` nodes, each node attaches to `m`
+        /// distinct earlier nodes picked in proportion to their degree.
+        ///
+        /// The same `seed` always gives the same graph. Panics unless `0 < m < n`.
+        pub fn barabasi_albert(n: usize, m: usize, seed: u64) -> Graph {
+            assert!(0 < m && m < n, "barabasi_albert needs 0 < m < n");
+            let mut rng = SplitMix64(seed);
+            let mut pairs = Vec::with_capacity((n - m) * m);
+            // Every node appears here once per edge end, so sampling it is degree-weighted.
+            let mut ends = Vec::with_capacity(2 * (n - m) * m);
+            let mut targets = (0..m).collect::<Vec<_>>();
+            for source in m..n {
+                pairs.extend(targets.iter().map(|&target| (target, source)));
+                ends.extend(targets.iter().copied());
+                ends.extend(std::iter::repeat_n(source, m));
+                targets.clear();
+                while targets.len() < m {
+                    let target = ends[rng.below(ends.len())];
+                    if !targets.contains(&target) {
+                        targets.push(target);
+                    }
+                }
+            }
+            build(n, pairs.into_iter())
+        }
+
+        /// `rows` by `cols` lattice, node `r * cols + c` sitting at row `r`, column `c`.
+        pub fn grid(rows: usize, cols: usize) -> Graph {
+            Grid::new(rows, cols).build()
+        }
+
+        /// A [`grid`] with optional diagonals and pinned positions.
+        #[derive(Debug, Clone)]
+        pub struct Grid {
+            rows: usize,
+            cols: usize,
+            diagonals: bool,
+            positions: bool,
+        }
+
+        impl Grid {
+            pub fn new(rows: usize, cols: usize) -> Self {
+                Grid {
+                    rows,
+                    cols,
+                    diagonals: false,
+                    positions: false,
+                }
+            }
+
+            /// Also join each cell to the cells diagonally below it.
+            pub fn with_diagonals(self, diagonals: bool) -> Self {
+                Grid { diagonals, ..self }
+            }
+
+            /// Fill in pinned `pos` attributes so `neato` draws the lattice row 0 on top.
+            pub fn with_positions(self, positions: bool) -> Self {
+                Grid { positions, ..self }
+            }
+
+            pub fn build(&self) -> Graph {
+                let Grid { rows, cols, .. } = *self;
+                let index = |r: usize, c: usize| r * cols + c;
+                let mut pairs = Vec::new();
+                for r in 0..rows {
+                    for c in 0..cols {
+                        if c + 1 < cols {
+                            pairs.push((index(r, c), index(r, c + 1)));
+                        }
+                        if r + 1 < rows {
+                            pairs.push((index(r, c), index(r + 1, c)));
+                        }
+                        if self.diagonals && r + 1 < rows {
+                            if c + 1 < cols {
+                                pairs.push((index(r, c), index(r + 1, c + 1)));
+                            }
+                            if c > 0 {
+                                pairs.push((index(r, c), index(r + 1, c - 1)));
+                            }
+                        }
+                    }
+                }
+                let nodes = (0..rows * cols).map(|i| {
+                    let node = Node::named(i.to_string());
+                    if self.positions {
+                        let (r, c) = (i / cols, i % cols);
+                        node.with_attr("pos", format!("{c},{}!", rows - 1 - r))
+                    } else {
+                        node
+                    }
+                });
+                build_from(nodes.collect(), pairs.into_iter())
+            }
+        }
+
+        fn build(n: usize, pairs: impl Iterator<Item = (usize, usize)>) -> Graph {
+            let nodes = (0..n).map(|i| Node::named(i.to_string())).collect();
+            build_from(nodes, pairs)
+        }
+
+        fn build_from(nodes: Vec<Node>, pairs: impl Iterator<Item = (usize, usize)>) -> Graph {
+            let edges = pairs
+                .map(|(i, j)| Edge::between(i.to_string(), j.to_string()))
+                .collect::<Vec<_>>();
+            Graph::with_capacity(nodes.len(), edges.len())
+                .with_nodes(&nodes)
+                .with_edges(&edges)
+        }
+    }
+
+    /// How central each node is, as scores that can drive styling.
+    pub mod centrality {
+        use crate::graph::Graph;
+
+        /// Closeness centrality per node, declared nodes first: how few edges it
+        /// takes on average to reach the nodes it can reach, following direction in a
+        /// directed graph. Scaled by the share of the other nodes reached, as Wasserman
+        /// and Faust do, so nodes stuck in a small component score low; a node reaching
+        /// nothing scores 0, and one at distance 1 from all others 1.
+        pub fn closeness(graph: &Graph) -> Vec<(&str, f64)> {
+            let names = graph.node_names();
+            let others = names.len().saturating_sub(1) as f64;
+            names
+                .iter()
+                .map(|&name| {
+                    let distances = graph.hop_distances(name);
+                    let reached = (distances.len() - 1) as f64;
+                    let total = distances.values().sum::<usize>() as f64;
+                    let score = if total > 0.0 {
+                        (reached / others) * (reached / total)
+                    } else {
+                        0.0
+                    };
+                    (name, score)
+                })
+                .collect()
+        }
+    }
+
+    /// Deltas between graphs, for shipping changes instead of whole graphs.
+    pub mod patch {
+        use crate::graph::graph_items::edge::Edge;
+        use crate::graph::graph_items::node::Node;
+        use crate::graph::parser::{self, ParseError};
+        use crate::graph::{quote_id, quote_string, render_attr_list, Change, Graph, GraphError};
+        use std::fmt;
+        use std::str::FromStr;
+        use std::sync::Arc;
+
+        #[derive(Debug, PartialEq, Eq, Clone)]
+        pub enum PatchOp {
+            AddNode(Node),
+            /// Replace the node of the same name, attributes and all.
+            ChangeNode(Node),
+            RemoveNode(String),
+            AddEdge(Edge),
+            /// Replace the edge with the same [id](Edge::id), endpoints and all.
+            ChangeEdge(Edge),
+            /// Remove the first edge equal to this one, attributes included.
+            RemoveEdge(Edge),
+            SetAttr(String, String),
+            RemoveAttr(String),
+        }
+
+        /// A list of changes, written one per line:
+        ///
+        /// ```text
+        /// +node a [color="red"]
+        /// ~node b [shape="box"]
+        /// -node c
+        /// +edge a -- b
+        /// ~edge a -- c [id="e1", weight="3"]
+        /// -edge b -- c [weight="2"]
+        /// +attr rankdir="LR"
+        /// -attr bgcolor
+        /// ```
+        ///
+        /// Comments on nodes and edges are not carried.
+        #[derive(Debug, PartialEq, Eq, Clone, Default)]
+        pub struct GraphPatch {
+            ops: Vec<PatchOp>,
+        }
+
+        impl GraphPatch {
+            pub fn new() -> Self {
+                GraphPatch::default()
+            }
+
+            pub fn with_op(mut self, op: PatchOp) -> Self {
+                self.ops.push(op);
+                self
+            }
+
+            pub fn ops(&self) -> &[PatchOp] {
+                &self.ops
+            }
+
+            pub fn is_empty(&self) -> bool {
+                self.ops.is_empty()
+            }
+
+            /// The changes turning `from` into `to`. Applying them gives the nodes, edges
+            /// and graph attributes of `to`, though new items come after the old ones.
+            pub fn between(from: &Graph, to: &Graph) -> Self {
+                let mut ops = Vec::new();
+
+                // Edges with an id pair up by id; the rest form a multiset, pairing each
+                // edge of `to` with an equal one in `from`.
+                let mut unmatched = to.edges().iter().collect::<Vec<_>>();
+                for edge in from.edges() {
+                    let same = |other: &&Edge| match edge.id() {
+                        Some(id) => other.id() == Some(id),
+                        None => *other == edge,
+                    };
+                    match unmatched.iter().position(same) {
+                        Some(i) => {
+                            let other = unmatched.remove(i);
+                            if other != edge {
+                                ops.push(PatchOp::ChangeEdge(other.clone()));
+                            }
+                        }
+                        None => ops.push(PatchOp::RemoveEdge(edge.clone())),
+                    }
+                }
+                for node in from.nodes() {
+                    match to.node(node.name()) {
+                        None => ops.push(PatchOp::RemoveNode(node.name().to_string())),
+                        Some(other) if other != node => {
+                            ops.push(PatchOp::ChangeNode(other.clone()))
+                        }
+                        Some(_) => {}
+                    }
+                }
+                ops.extend(
+                    to.nodes()
+                        .iter()
+                        .filter(|node| !from.contains_node(node.name()))
+                        .map(|node| PatchOp::AddNode(node.clone())),
+                );
+                ops.extend(
+                    unmatched
+                        .into_iter()
+                        .map(|edge| PatchOp::AddEdge(edge.clone())),
+                );
+
+                let mut removed = from
+                    .attrs
+                    .keys()
+                    .filter(|key| !to.attrs.contains_key(*key))
+                    .collect::<Vec<_>>();
+                removed.sort();
+                ops.extend(
+                    removed
+                        .into_iter()
+                        .map(|key| PatchOp::RemoveAttr(key.clone())),
+                );
+                let mut set = to
+                    .attrs
+                    .iter()
+                    .filter(|(key, value)| from.attrs.get(*key) != Some(value))
+                    .collect::<Vec<_>>();
+                set.sort();
+                ops.extend(
+                    set.into_iter()
+                        .map(|(key, value)| PatchOp::SetAttr(key.clone(), value.clone())),
+                );
+
+                GraphPatch { ops }
+            }
+        }
+
+        impl Graph {
+            /// Apply `patch` in order. Removals of things that aren't there are skipped;
+            /// additions follow the duplicate policies and stop at the first error.
+            pub fn apply_patch(&mut self, patch: &GraphPatch) -> Result<(), GraphError> {
+                for op in &patch.ops {
+                    match op {
+                        PatchOp::AddNode(node) => self.add_node(node.clone())?,
+                        PatchOp::ChangeNode(node) => match self.node_index.get(node.name()) {
+                            Some(&i) => {
+                                Arc::make_mut(&mut self.nodes)[i] = node.clone();
+                                self.notify(Change::NodeUpdated(&self.nodes[i]));
+                            }
+                            None => self.add_node(node.clone())?,
+                        },
+                        PatchOp::RemoveNode(name) => {
+                            if let Some(&i) = self.node_index.get(name.as_str()) {
+                                self.remove_node_at(i);
+                            }
+                        }
+                        PatchOp::AddEdge(edge) => self.add_edge(edge.clone())?,
+                        PatchOp::ChangeEdge(edge) => {
+                            let id = edge.id();
+                            match id.and_then(|_| self.edges.iter().position(|e| e.id() == id)) {
+                                Some(i) => self.replace_edge_at(i, edge.clone()),
+                                None => self.add_edge(edge.clone())?,
+                            }
+                        }
+                        PatchOp::RemoveEdge(edge) => {
+                            if let Some(i) = self.edges.iter().position(|other| other == edge) {
+                                self.remove_edge_at(i);
+                            }
+                        }
+                        PatchOp::SetAttr(key, value) => self.set_attrs(&[(key, value)]),
+                        PatchOp::RemoveAttr(key) => {
+                            self.remove_attr(key);
+                        }
+                    }
+                }
+                Ok(())
+            }
+        }
+
+        impl fmt::Display for GraphPatch {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                for op in &self.ops {
+                    match op {
+                        PatchOp::AddNode(node) => write_node(f, "+node", node)?,
+                        PatchOp::ChangeNode(node) => write_node(f, "~node", node)?,
+                        PatchOp::RemoveNode(name) => writeln!(f, "-node {}", quote_id(name))?,
+                        PatchOp::AddEdge(edge) => write_edge(f, "+edge", edge)?,
+                        PatchOp::ChangeEdge(edge) => write_edge(f, "~edge", edge)?,
+                        PatchOp::RemoveEdge(edge) => write_edge(f, "-edge", edge)?,
+                        PatchOp::SetAttr(key, value) => {
+                            writeln!(f, "+attr {}={}", quote_id(key), quote_string(value))?
+                        }
+                        PatchOp::RemoveAttr(key) => writeln!(f, "-attr {}", quote_id(key))?,
+                    }
+                }
+                Ok(())
+            }
+        }
+
+        fn write_node(f: &mut fmt::Formatter<'_>, op: &str, node: &Node) -> fmt::Result {
+            writeln!(
+                f,
+                "{op} {}{}",
+                quote_id(node.name()),
+                render_attr_list(node.attrs())
+            )
+        }
+
+        fn write_edge(f: &mut fmt::Formatter<'_>, op: &str, edge: &Edge) -> fmt::Result {
+            writeln!(
+                f,
+                "{op} {} -- {}{}",
+                quote_id(edge.node1()),
+                quote_id(edge.node2()),
+                render_attr_list(edge.attrs())
+            )
+        }
+
+        /// Reads the format `Display` writes; blank lines are skipped.
+        impl FromStr for GraphPatch {
+            type Err = ParseError;
+
+            fn from_str(src: &str) -> Result<Self, ParseError> {
+                let mut ops = Vec::new();
+                let mut line_start = 0;
+                for line in src.split_inclusive('\n') {
+                    let offset = line_start;
+                    line_start += line.len();
+                    let line = line.trim_end();
+                    if line.trim_start().is_empty() {
+                        continue;
+                    }
+                    let (op, rest) = line.split_once(' ').unwrap_or((line, ""));
+                    // Each operation's payload is one DOT statement, so reuse the parser.
+                    let prefix = "graph { ";
+                    let graph = parser::parse(&format!("{prefix}{rest} }}")).map_err(|error| {
+                        let at =
+                            offset + op.len() + 1 + error.offset().saturating_sub(prefix.len());
+                        ParseError::new(error.message(), at.min(offset + line.len())).located(src)
+                    })?;
+                    let node = || graph.nodes().first().cloned();
+                    let edge = || graph.edges().first().cloned();
+                    let attr = || {
+                        graph
+                            .attrs
+                            .iter()
+                            .next()
+                            .map(|(k, v)| (k.clone(), v.clone()))
+                    };
+                    let parsed = match op {
+                        "+node" => node().map(PatchOp::AddNode),
+                        "~node" => node().map(PatchOp::ChangeNode),
+                        "-node" => node().map(|node| PatchOp::RemoveNode(node.name().to_string())),
+                        "+edge" => edge().map(PatchOp::AddEdge),
+                        "~edge" => edge().map(PatchOp::ChangeEdge),
+                        "-edge" => edge().map(PatchOp::RemoveEdge),
+                        "+attr" => attr().map(|(key, value)| PatchOp::SetAttr(key, value)),
+                        "-attr" => node().map(|node| PatchOp::RemoveAttr(node.name().to_string())),
+                        _ => None,
+                    };
+                    let op = parsed.ok_or_else(|| {
+                        ParseError::new("expected a patch operation", offset).located(src)
+                    })?;
+                    ops.push(op);
+                }
+                Ok(GraphPatch { ops })
+            }
+        }
+    }
+
+    /// Picking out nodes and edges by their attributes, e.g. `node[shape=box][label~=db]`.
+    pub mod query {
+        use crate::graph::graph_items::edge::Edge;
+        use crate::graph::graph_items::node::Node;
+        use crate::graph::parser::ParseError;
+        use crate::graph::Graph;
+        use std::str::FromStr;
+
+        #[derive(Debug, PartialEq, Eq, Clone, Copy)]
+        enum Target {
+            Nodes,
+            Edges,
+            Both,
+        }
+
+        #[derive(Debug, PartialEq, Eq, Clone)]
+        enum Test {
+            Present,
+            Equals(String),
+            NotEquals(String),
+            Contains(String),
+        }
+
+        /// Which items to select; every condition must hold.
+        ///
+        /// As text: `node`, `edge` or `*`, then any number of `[key]` (set), `[key=value]`,
+        /// `[key!=value]` (unset or different) and `[key~=value]` (contains). Values with
+        /// spaces or brackets go in double quotes.
+        #[derive(Debug, PartialEq, Eq, Clone)]
+        pub struct Query {
+            target: Target,
+            conditions: Vec<(String, Test)>,
+        }
+
+        impl Query {
+            pub fn nodes() -> Self {
+                Query::new(Target::Nodes)
+            }
+
+            pub fn edges() -> Self {
+                Query::new(Target::Edges)
+            }
+
+            /// Both nodes and edges.
+            pub fn any() -> Self {
+                Query::new(Target::Both)
+            }
+
+            fn new(target: Target) -> Self {
+                Query {
+                    target,
+                    conditions: Vec::new(),
+                }
+            }
+
+            pub fn with_attr(self, key: &str) -> Self {
+                self.with(key, Test::Present)
+            }
+
+            pub fn with_attr_eq(self, key: &str, value: &str) -> Self {
+                self.with(key, Test::Equals(value.to_string()))
+            }
+
+            pub fn with_attr_ne(self, key: &str, value: &str) -> Self {
+                self.with(key, Test::NotEquals(value.to_string()))
+            }
+
+            pub fn with_attr_containing(self, key: &str, value: &str) -> Self {
+                self.with(key, Test::Contains(value.to_string()))
+            }
+
+            fn with(mut self, key: &str, test: Test) -> Self {
+                self.conditions.push((key.to_string(), test));
+                self
+            }
+
+            pub fn matches_node(&self, node: &Node) -> bool {
+                self.target != Target::Edges && self.holds(|key| node.attr(key))
+            }
+
+            pub fn matches_edge(&self, edge: &Edge) -> bool {
+                self.target != Target::Nodes && self.holds(|key| edge.attr(key))
+            }
+
+            fn holds<'a>(&self, attr: impl Fn(&str) -> Option<&'a str>) -> bool {
+                self.conditions.iter().all(|(key, test)| {
+                    let value = attr(key);
+                    match test {
+                        Test::Present => value.is_some(),
+                        Test::Equals(expected) => value == Some(expected),
+                        Test::NotEquals(expected) => value != Some(expected),
+                        Test::Contains(part) => value.is_some_and(|v| v.contains(part.as_str())),
+                    }
+                })
+            }
+        }
+
+        impl FromStr for Query {
+            type Err = ParseError;
+
+            fn from_str(src: &str) -> Result<Self, ParseError> {
+                let error = |message: &str, offset: usize| {
+                    Err(ParseError::new(message, offset).located(src))
+                };
+                let trimmed = src.trim_start();
+                let mut pos = src.len() - trimmed.len();
+                let mut query = if let Some(rest) = trimmed.strip_prefix("node") {
+                    pos = src.len() - rest.len();
+                    Query::nodes()
+                } else if let Some(rest) = trimmed.strip_prefix("edge") {
+                    pos = src.len() - rest.len();
+                    Query::edges()
+                } else if let Some(rest) = trimmed.strip_prefix('*') {
+                    pos = src.len() - rest.len();
+                    Query::any()
+                } else {
+                    return error("expected `node`, `edge` or `*`", pos);
+                };
+                loop {
+                    pos += src[pos..].len() - src[pos..].trim_start().len();
+                    if pos == src.len() {
+                        return Ok(query);
+                    }
+                    if !src[pos..].starts_with('[') {
+                        return error("expected `[`", pos);
+                    }
+                    pos += 1;
+                    let key = match word(src, &mut pos) {
+                        Some(key) => key,
+                        None => return error("expected an attribute name", pos),
+                    };
+                    let rest = &src[pos..];
+                    let (test, len): (fn(String) -> Test, usize) = if rest.starts_with("!=") {
+                        (Test::NotEquals, 2)
+                    } else if rest.starts_with("~=") {
+                        (Test::Contains, 2)
+                    } else if rest.starts_with('=') {
+                        (Test::Equals, 1)
+                    } else if rest.starts_with(']') {
+                        pos += 1;
+                        query = query.with_attr(&key);
+                        continue;
+                    } else {
+                        return error("expected `]`, `=`, `!=` or `~=`", pos);
+                    };
+                    pos += len;
+                    let value = match word(src, &mut pos) {
+                        Some(value) => value,
+                        None => return error("expected a value", pos),
+                    };
+                    if !src[pos..].starts_with(']') {
+                        return error("expected `]`", pos);
+                    }
+                    pos += 1;
+                    query = query.with(&key, test(value));
+                }
+            }
+        }
+
+        /// A bare word or a double-quoted string with `\"` escapes, advancing `pos`.
+        pub(crate) fn word(src: &str, pos: &mut usize) -> Option<String> {
+            let rest = &src[*pos..];
+            if let Some(quoted) = rest.strip_prefix('"') {
+                let mut value = String::new();
+                let mut chars = quoted.char_indices();
+                while let Some((i, c)) = chars.next() {
+                    match c {
+                        '"' => {
+                            *pos += i + 2;
+                            return Some(value);
+                        }
+                        '\\' => value.extend(chars.next().map(|(_, c)| c)),
+                        c => value.push(c),
+                    }
+                }
+                return None;
+            }
+            let len = rest
+                .find(|c: char| matches!(c, '[' | ']' | '=' | '!' | '~' | '"') || c.is_whitespace())
+                .unwrap_or(rest.len());
+            *pos += len;
+            (len > 0).then(|| rest[..len].to_string())
+        }
+
+        /// The nodes and edges a [`Query`] matched, in graph order.
+        #[derive(Debug, PartialEq, Eq, Clone)]
+        pub struct Selection<'a> {
+            nodes: Vec<&'a Node>,
+            edges: Vec<&'a Edge>,
+        }
+
+        impl<'a> Selection<'a> {
+            pub fn nodes(&self) -> &[&'a Node] {
+                &self.nodes
+            }
+
+            pub fn edges(&self) -> &[&'a Edge] {
+                &self.edges
+            }
+
+            pub fn is_empty(&self) -> bool {
+                self.nodes.is_empty() && self.edges.is_empty()
+            }
+        }
+
+        impl Graph {
+            pub fn query(&self, query: &Query) -> Selection<'_> {
+                Selection {
+                    nodes: self
+                        .nodes()
+                        .iter()
+                        .filter(|n| query.matches_node(n))
+                        .collect(),
+                    edges: self
+                        .edges()
+                        .iter()
+                        .filter(|e| query.matches_edge(e))
+                        .collect(),
+                }
+            }
+
+            /// Like [`query`](Graph::query), with the query written as text.
+            pub fn select(&self, selector: &str) -> Result<Selection<'_>, ParseError> {
+                Ok(self.query(&selector.parse()?))
+            }
+        }
+    }
+
+    /// Presentation rules kept apart from the code that builds the graph.
+    pub mod stylesheet {
+        use crate::graph::parser::ParseError;
+        use crate::graph::query::{word, Query};
+        use crate::graph::Graph;
+        use std::str::FromStr;
+        use std::sync::Arc;
+
+        /// Rules like `node[kind=db] { shape: cylinder; color: blue }`, with [`Query`]
+        /// selectors.
+        ///
+        /// When several rules set a key the last one wins, and attributes the node or edge
+        /// already has win over all of them. Selectors only see those original attributes.
+        #[derive(Debug, PartialEq, Eq, Clone, Default)]
+        pub struct Stylesheet {
+            rules: Vec<(Query, Vec<(String, String)>)>,
+        }
+
+        impl Stylesheet {
+            pub fn new() -> Self {
+                Stylesheet::default()
+            }
+
+            pub fn with_rule(mut self, query: Query, attrs: &[(&str, &str)]) -> Self {
+                let attrs = attrs
+                    .iter()
+                    .map(|(k, v)| (k.to_string(), v.to_string()))
+                    .collect();
+                self.rules.push((query, attrs));
+                self
+            }
+
+            /// `graph` with every rule applied.
+            pub fn apply(&self, graph: Graph) -> Graph {
+                let nodes = graph
+                    .nodes
+                    .iter()
+                    .map(|node| {
+                        match self.styled(|query| query.matches_node(node), |key| node.attr(key)) {
+                            Some(styled) => node.clone().with_attrs(&as_str(&styled)),
+                            None => node.clone(),
+                        }
+                    })
+                    .collect();
+                let edges = graph
+                    .edges
+                    .iter()
+                    .map(|edge| {
+                        match self.styled(|query| query.matches_edge(edge), |key| edge.attr(key)) {
+                            Some(styled) => edge.clone().with_attrs(&as_str(&styled)),
+                            None => edge.clone(),
+                        }
+                    })
+                    .collect();
+                // Names and endpoints are untouched, so the indexes stay valid.
+                Graph {
+                    nodes: Arc::new(nodes),
+                    edges: Arc::new(edges),
+                    ..graph
+                }
+            }
+
+            /// The attributes the matching rules add, or `None` when there are none.
+            fn styled<'a>(
+                &self,
+                matches: impl Fn(&Query) -> bool,
+                existing: impl Fn(&str) -> Option<&'a str>,
+            ) -> Option<Vec<(String, String)>> {
+                let mut styled: Vec<(String, String)> = Vec::new();
+                for (_, attrs) in self.rules.iter().filter(|(query, _)| matches(query)) {
+                    for (key, value) in attrs.iter().filter(|(key, _)| existing(key).is_none()) {
+                        match styled.iter_mut().find(|(k, _)| k == key) {
+                            Some(entry) => entry.1 = value.clone(),
+                            None => styled.push((key.clone(), value.clone())),
+                        }
+                    }
+                }
+                (!styled.is_empty()).then_some(styled)
+            }
+        }
+
+        fn as_str(pairs: &[(String, String)]) -> Vec<(&str, &str)> {
+            pairs
+                .iter()
+                .map(|(k, v)| (k.as_str(), v.as_str()))
+                .collect()
+        }
+
+        /// Reads `selector { key: value; ... }` rules; values may be double-quoted, with
+        /// `\"` escapes, to hold `;` or `}`.
+        impl FromStr for Stylesheet {
+            type Err = ParseError;
+
+            fn from_str(src: &str) -> Result<Self, ParseError> {
+                let error =
+                    |message: &str, offset: usize| ParseError::new(message, offset).located(src);
+                let unterminated = |offset: usize| error("unterminated string", offset);
+                let mut stylesheet = Stylesheet::new();
+                let mut pos = 0;
+                while let Some(open) = find_unquoted(src, pos, &['{']).map_err(unterminated)? {
+                    let selector = &src[pos..open];
+                    let query = selector
+                        .parse::<Query>()
+                        .map_err(|e| error(e.message(), pos + e.offset()))?;
+                    let mut attrs = Vec::new();
+                    pos = open + 1;
+                    loop {
+                        let offset = pos;
+                        pos += src[pos..].len()
+                            - src[pos..]
+                                .trim_start_matches(|c: char| c == ';' || c.is_whitespace())
+                                .len();
+                        match src[pos..].chars().next() {
+                            None => return Err(error("expected `}`", src.len())),
+                            Some('}') => break,
+                            Some(_) => {}
+                        }
+                        let colon = find_unquoted(src, pos, &[':', ';', '}'])
+                            .map_err(unterminated)?
+                            .filter(|&i| src[i..].starts_with(':'))
+                            .ok_or_else(|| error("expected `key: value`", offset))?;
+                        let key = src[pos..colon].trim();
+                        pos = colon + 1;
+                        pos += src[pos..].len() - src[pos..].trim_start().len();
+                        let value = if src[pos..].starts_with('"') {
+                            word(src, &mut pos).ok_or_else(|| unterminated(pos))?
+                        } else {
+                            let end = find_unquoted(src, pos, &[';', '}'])
+                                .map_err(unterminated)?
+                                .unwrap_or(src.len());
+                            let value = src[pos..end].trim().to_string();
+                            pos = end;
+                            value
+                        };
+                        let rest = src[pos..].trim_start();
+                        if !(rest.is_empty() || rest.starts_with([';', '}'])) {
+                            return Err(error("expected `;` or `}`", src.len() - rest.len()));
+                        }
+                        attrs.push((key.to_string(), value));
+                    }
+                    stylesheet.rules.push((query, attrs));
+                    pos += 1;
+                }
+                if !src[pos..].trim().is_empty() {
+                    return Err(error("expected `{`", src.len()));
+                }
+                Ok(stylesheet)
+            }
+        }
+
+        /// The first of `targets` in `src` from `pos` on that isn't inside a
+        /// double-quoted string, or the offset of a string left open.
+        fn find_unquoted(
+            src: &str,
+            mut pos: usize,
+            targets: &[char],
+        ) -> Result<Option<usize>, usize> {
+            while let Some(c) = src[pos..].chars().next() {
+                if c == '"' {
+                    let start = pos;
+                    word(src, &mut pos).ok_or(start)?;
+                } else if targets.contains(&c) {
+                    return Ok(Some(pos));
+                } else {
+                    pos += c.len_utf8();
+                }
+            }
+            Ok(None)
+        }
+
+        impl Graph {
+            pub fn with_stylesheet(self, stylesheet: &Stylesheet) -> Self {
+                stylesheet.apply(self)
+            }
+        }
+    }
+
+    /// Ready-made looks, each a consistent set of graph attributes and node and edge
+    /// defaults.
+    pub mod themes {
+        use crate::graph::Graph;
+
+        #[derive(Debug, PartialEq, Eq, Clone, Copy)]
+        pub enum Theme {
+            /// Light text and lines on a near-black background.
+            Dark,
+            /// Dark text and lines on white, close to the Graphviz defaults but softer.
+            Light,
+            /// Black on white without fills, for paper and grayscale printers.
+            Print,
+            /// The Okabe-Ito palette, which stays distinguishable under common forms of
+            /// color blindness.
+            ColorblindSafe,
+        }
+
+        impl Theme {
+            pub fn graph_attrs(self) -> &'static [(&'static str, &'static str)] {
+                match self {
+                    Theme::Dark => &[("bgcolor", "#1e1e1e"), ("fontcolor", "#d4d4d4")],
+                    Theme::Light => &[("bgcolor", "#ffffff"), ("fontcolor", "#333333")],
+                    Theme::Print => &[("bgcolor", "white"), ("fontcolor", "black")],
+                    Theme::ColorblindSafe => &[("bgcolor", "#ffffff"), ("fontcolor", "#000000")],
+                }
+            }
+
+            pub fn node_defaults(self) -> &'static [(&'static str, &'static str)] {
+                match self {
+                    Theme::Dark => &[
+                        ("style", "filled"),
+                        ("fillcolor", "#2d2d2d"),
+                        ("color", "#569cd6"),
+                        ("fontcolor", "#d4d4d4"),
+                    ],
+                    Theme::Light => &[
+                        ("style", "filled"),
+                        ("fillcolor", "#f3f6fa"),
+                        ("color", "#4a6fa5"),
+                        ("fontcolor", "#333333"),
+                    ],
+                    Theme::Print => &[
+                        ("style", "solid"),
+                        ("color", "black"),
+                        ("fontcolor", "black"),
+                    ],
+                    Theme::ColorblindSafe => &[
+                        ("style", "filled"),
+                        ("fillcolor", "#56b4e9"),
+                        ("color", "#0072b2"),
+                        ("fontcolor", "#000000"),
+                    ],
+                }
+            }
+
+            pub fn edge_defaults(self) -> &'static [(&'static str, &'static str)] {
+                match self {
+                    Theme::Dark => &[("color", "#9cdcfe"), ("fontcolor", "#d4d4d4")],
+                    Theme::Light => &[("color", "#4a6fa5"), ("fontcolor", "#333333")],
+                    Theme::Print => &[("color", "black"), ("fontcolor", "black")],
+                    Theme::ColorblindSafe => &[("color", "#d55e00"), ("fontcolor", "#000000")],
+                }
+            }
+        }
+
+        impl Graph {
+            /// Apply `theme` on top of the graph's attributes and defaults. Attributes set
+            /// on individual nodes and edges still win.
+            pub fn with_theme(self, theme: Theme) -> Self {
+                self.with_attrs(theme.graph_attrs())
+                    .with_node_defaults(theme.node_defaults())
+                    .with_edge_defaults(theme.edge_defaults())
+            }
+        }
+    }
+
+    /// DOT skeletons with `${name}` placeholders, filled in from data later.
+    pub mod template {
+        use crate::graph::parser::{self, ParseError};
+        use crate::graph::{quote_id, quote_string, Graph};
+        use std::collections::HashMap;
+        use std::ops::Range;
+        use std::str::FromStr;
+
+        /// A DOT document where `${name}` stands for a value supplied at instantiation.
+        ///
+        /// Inside a quoted string the value is spliced in with its quotes escaped;
+        /// anywhere else it becomes a whole identifier, quoted when needed, so values can
+        /// never change the structure of the graph.
+        #[derive(Debug, PartialEq, Eq, Clone)]
+        pub struct GraphTemplate {
+            source: String,
+            slots: Vec<Slot>,
+        }
+
+        #[derive(Debug, PartialEq, Eq, Clone)]
+        struct Slot {
+            name: String,
+            span: Range<usize>,
+            quoted: bool,
+        }
+
+        impl GraphTemplate {
+            /// The placeholder names, in order of first appearance.
+            pub fn placeholders(&self) -> Vec<&str> {
+                let mut names: Vec<&str> = Vec::new();
+                for slot in &self.slots {
+                    if !names.contains(&slot.name.as_str()) {
+                        names.push(&slot.name);
+                    }
+                }
+                names
+            }
+
+            /// The filled-in graph. A missing value is reported at its placeholder in
+            /// the template.
+            pub fn instantiate(&self, values: &HashMap<&str, &str>) -> Result<Graph, ParseError> {
+                let text = self.fill(|slot| {
+                    let value = values.get(slot.name.as_str()).ok_or_else(|| {
+                        ParseError::spanning(
+                            &format!("no value for `{}`", slot.name),
+                            slot.span.start,
+                            slot.span.len(),
+                        )
+                        .located(&self.source)
+                    })?;
+                    Ok(if slot.quoted {
+                        let quoted = quote_string(value);
+                        quoted[1..quoted.len() - 1].to_string()
+                    } else {
+                        quote_id(value)
+                    })
+                })?;
+                Graph::from_dot(&text)
+            }
+
+            fn fill(
+                &self,
+                mut value: impl FnMut(&Slot) -> Result<String, ParseError>,
+            ) -> Result<String, ParseError> {
+                let mut text = String::with_capacity(self.source.len());
+                let mut pos = 0;
+                for slot in &self.slots {
+                    text.push_str(&self.source[pos..slot.span.start]);
+                    text.push_str(&value(slot)?);
+                    pos = slot.span.end;
+                }
+                text.push_str(&self.source[pos..]);
+                Ok(text)
+            }
+        }
+
+        /// Checks the placeholders and, with each one standing in for an identifier of
+        /// the same length, that the rest is valid DOT.
+        impl FromStr for GraphTemplate {
+            type Err = ParseError;
+
+            fn from_str(src: &str) -> Result<Self, ParseError> {
+                let mut slots = Vec::new();
+                let mut quoted = false;
+                let mut escaped = false;
+                let mut chars = src.char_indices().peekable();
+                while let Some((i, c)) = chars.next() {
+                    match c {
+                        _ if escaped => escaped = false,
+                        '\\' if quoted => escaped = true,
+                        '"' => quoted = !quoted,
+                        '$' if chars.peek().map(|&(_, c)| c) == Some('{') => {
+                            let end = src[i..].find('}').map(|j| i + j).ok_or_else(|| {
+                                ParseError::spanning("unterminated placeholder", i, 2).located(src)
+                            })?;
+                            let name = &src[i + 2..end];
+                            let valid = !name.is_empty()
+                                && name
+                                    .chars()
+                                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.');
+                            if !valid {
+                                return Err(ParseError::spanning(
+                                    "invalid placeholder name",
+                                    i,
+                                    end + 1 - i,
+                                )
+                                .located(src));
+                            }
+                            slots.push(Slot {
+                                name: name.to_string(),
+                                span: i..end + 1,
+                                quoted,
+                            });
+                            while chars.next_if(|&(j, _)| j <= end).is_some() {}
+                        }
+                        _ => {}
+                    }
+                }
+                let template = GraphTemplate {
+                    source: src.to_string(),
+                    slots,
+                };
+                let skeleton = template.fill(|slot| Ok("x".repeat(slot.span.len())))?;
+                parser::parse(&skeleton).map_err(|error| {
+                    ParseError::spanning(error.message(), error.offset(), error.token().len())
+                        .located(src)
+                })?;
+                Ok(template)
+            }
+        }
+    }
+
+    /// A compact binary encoding of graphs, from [`Graph::to_bytes`], which loads far
+    /// faster than re-parsing DOT.
+    ///
+    /// The layout is the magic `DOTG`, a version byte, a flags byte (bit 0: directed),
+    /// then a table of every distinct string, then the graph's comments, attributes,
+    /// defaults, nodes, edges and subgraphs with strings replaced by table indexes. All
+    /// counts, lengths and indexes are LEB128 varints. A node name used by a thousand
+    /// edges is therefore stored once. Options such as duplicate policies are not
+    /// saved, like with DOT.
+    pub mod binary {
+        use crate::graph::graph_items::edge::Edge;
+        use crate::graph::graph_items::node::Node;
+        use crate::graph::graph_items::subgraph::Subgraph;
+        use crate::graph::{index_by_name, Graph};
+        use std::collections::HashMap;
+        use std::error::Error;
+        use std::fmt;
+        use std::sync::Arc;
+
+        const MAGIC: &[u8; 4] = b"DOTG";
+        /// Bumped whenever the layout changes; older versions stay readable.
+        pub const VERSION: u8 = 1;
+        const DIRECTED: u8 = 1;
+
+        /// Why [`Graph::from_bytes`] rejected its input.
+        #[derive(Debug, PartialEq, Eq, Clone)]
+        pub enum DecodeError {
+            /// The input doesn't start with the `DOTG` magic.
+            BadMagic,
+            /// Written by a newer version of this crate.
+            UnsupportedVersion(u8),
+            /// The input ended in the middle of the graph.
+            Truncated,
+            /// A string in the table wasn't UTF-8.
+            InvalidUtf8,
+            /// A string index pointed past the end of the table.
+            UnknownString(usize),
+            /// Bytes were left over after the graph.
+            TrailingBytes,
+        }
+
+        impl fmt::Display for DecodeError {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                match self {
+                    DecodeError::BadMagic => write!(f, "not a binary graph"),
+                    DecodeError::UnsupportedVersion(version) => {
+                        write!(f, "unsupported binary graph version {version}")
+                    }
+                    DecodeError::Truncated => write!(f, "binary graph is truncated"),
+                    DecodeError::InvalidUtf8 => write!(f, "binary graph holds invalid UTF-8"),
+                    DecodeError::UnknownString(i) => write!(f, "unknown string index {i}"),
+                    DecodeError::TrailingBytes => write!(f, "trailing bytes after binary graph"),
+                }
+            }
+        }
+
+        impl Error for DecodeError {}
+
+        pub(crate) fn encode(graph: &Graph) -> Vec<u8> {
+            let mut body = Encoder::default();
+            body.strings(&graph.comments);
+            body.map(&graph.attrs);
+            body.map(&graph.node_defaults);
+            body.map(&graph.edge_defaults);
+            body.len(graph.nodes.len());
+            for node in graph.nodes.iter() {
+                body.string(node.name());
+                body.attrs(node.attrs());
+                body.strings(node.comments());
+            }
+            body.len(graph.edges.len());
+            for edge in graph.edges.iter() {
+                body.string(edge.node1());
+                body.string(edge.node2());
+                body.attrs(edge.attrs());
+                body.strings(edge.comments());
+            }
+            body.len(graph.subgraphs.len());
+            for subgraph in &graph.subgraphs {
+                body.string(subgraph.name());
+                body.attrs(subgraph.attrs());
+                body.attrs(subgraph.node_defaults());
+                body.strings(subgraph.nodes());
+            }
+
+            let mut out = MAGIC.to_vec();
+            out.push(VERSION);
+            out.push(if graph.directed { DIRECTED } else { 0 });
+            write_varint(&mut out, body.table.len());
+            for s in &body.table {
+                write_varint(&mut out, s.len());
+                out.extend_from_slice(s.as_bytes());
+            }
+            out.extend_from_slice(&body.out);
+            out
+        }
+
+        pub(crate) fn decode(bytes: &[u8]) -> Result<Graph, DecodeError> {
+            let rest = bytes.strip_prefix(MAGIC).ok_or(DecodeError::BadMagic)?;
+            let mut decoder = Decoder {
+                bytes: rest,
+                table: Vec::new(),
+            };
+            let version = decoder.byte()?;
+            if version > VERSION {
+                return Err(DecodeError::UnsupportedVersion(version));
+            }
+            let flags = decoder.byte()?;
+            for _ in 0..decoder.len()? {
+                let len = decoder.varint()?;
+                let s = decoder.take(len)?;
+                decoder
+                    .table
+                    .push(std::str::from_utf8(s).map_err(|_| DecodeError::InvalidUtf8)?);
+            }
+
+            let mut graph = Graph::new().with_directed(flags & DIRECTED != 0);
+            graph.comments = decoder.strings()?;
+            graph.attrs = decoder.map()?;
+            graph.node_defaults = decoder.map()?;
+            graph.edge_defaults = decoder.map()?;
+            let mut nodes = Vec::with_capacity(decoder.len()?);
+            for _ in 0..nodes.capacity() {
+                let node = Node::new(decoder.string()?).with_attrs(&decoder.attrs()?);
+                nodes.push(decoder.list()?.fold(node, Node::with_comment));
+            }
+            let mut edges = Vec::with_capacity(decoder.len()?);
+            for _ in 0..edges.capacity() {
+                let (node1, node2) = (decoder.string()?, decoder.string()?);
+                let edge = Edge::new(node1, node2).with_attrs(&decoder.attrs()?);
+                edges.push(decoder.list()?.fold(edge, Edge::with_comment));
+            }
+            let mut subgraphs = Vec::with_capacity(decoder.len()?);
+            for _ in 0..subgraphs.capacity() {
+                let subgraph = Subgraph::new(decoder.string()?)
+                    .with_attrs(&decoder.attrs()?)
+                    .with_node_defaults(&decoder.attrs()?);
+                let members = decoder.list()?.collect::<Vec<_>>();
+                subgraphs.push(subgraph.with_nodes(&members));
+            }
+            if !decoder.bytes.is_empty() {
+                return Err(DecodeError::TrailingBytes);
+            }
+
+            graph.node_index = Arc::new(index_by_name(&nodes));
+            graph.nodes = Arc::new(nodes);
+            graph.edges = Arc::new(edges);
+            graph.subgraphs = subgraphs;
+            Ok(graph)
+        }
+
+        #[derive(Default)]
+        struct Encoder<'a> {
+            out: Vec<u8>,
+            table: Vec<&'a str>,
+            ids: HashMap<&'a str, usize>,
+        }
+
+        impl<'a> Encoder<'a> {
+            fn len(&mut self, len: usize) {
+                write_varint(&mut self.out, len);
+            }
+
+            fn string(&mut self, s: &'a str) {
+                let next = self.table.len();
+                let id = *self.ids.entry(s).or_insert(next);
+                if id == next {
+                    self.table.push(s);
+                }
+                write_varint(&mut self.out, id);
+            }
+
+            fn strings(&mut self, strings: &'a [String]) {
+                self.len(strings.len());
+                for s in strings {
+                    self.string(s);
+                }
+            }
+
+            /// Sorted by key, so equal graphs encode to equal bytes.
+            fn attrs(&mut self, attrs: impl Iterator<Item = (&'a str, &'a str)>) {
+                let mut attrs = attrs.collect::<Vec<_>>();
+                attrs.sort();
+                self.len(attrs.len());
+                for (key, value) in attrs {
+                    self.string(key);
+                    self.string(value);
+                }
+            }
+
+            fn map(&mut self, map: &'a HashMap<String, String>) {
+                self.attrs(map.iter().map(|(k, v)| (k.as_str(), v.as_str())));
+            }
+        }
+
+        struct Decoder<'a> {
+            bytes: &'a [u8],
+            table: Vec<&'a str>,
+        }
+
+        impl<'a> Decoder<'a> {
+            fn take(&mut self, len: usize) -> Result<&'a [u8], DecodeError> {
+                if len > self.bytes.len() {
+                    return Err(DecodeError::Truncated);
+                }
+                let (taken, rest) = self.bytes.split_at(len);
+                self.bytes = rest;
+                Ok(taken)
+            }
+
+            fn byte(&mut self) -> Result<u8, DecodeError> {
+                Ok(self.take(1)?[0])
+            }
+
+            fn varint(&mut self) -> Result<usize, DecodeError> {
+                let mut value = 0usize;
+                for shift in (0..usize::BITS).step_by(7) {
+                    let byte = self.byte()?;
+                    value |= usize::from(byte & 0x7f) << shift;
+                    if byte & 0x80 == 0 {
+                        return Ok(value);
+                    }
+                }
+                Err(DecodeError::Truncated)
+            }
+
+            /// A count of items that each take at least a byte, so a corrupt count can't
+            /// make us allocate more than the input could hold.
+            fn len(&mut self) -> Result<usize, DecodeError> {
+                let len = self.varint()?;
+                if len > self.bytes.len() {
+                    return Err(DecodeError::Truncated);
+                }
+                Ok(len)
+            }
+
+            fn string(&mut self) -> Result<&'a str, DecodeError> {
+                let i = self.varint()?;
+                self.table
+                    .get(i)
+                    .copied()
+                    .ok_or(DecodeError::UnknownString(i))
+            }
+
+            fn list(&mut self) -> Result<impl Iterator<Item = &'a str>, DecodeError> {
+                let len = self.len()?;
+                let strings = (0..len)
+                    .map(|_| self.string())
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(strings.into_iter())
+            }
+
+            fn strings(&mut self) -> Result<Vec<String>, DecodeError> {
+                Ok(self.list()?.map(str::to_string).collect())
+            }
+
+            fn attrs(&mut self) -> Result<Vec<(&'a str, &'a str)>, DecodeError> {
+                let len = self.len()?;
+                (0..len)
+                    .map(|_| Ok((self.string()?, self.string()?)))
+                    .collect()
+            }
+
+            fn map(&mut self) -> Result<HashMap<String, String>, DecodeError> {
+                Ok(self
+                    .attrs()?
+                    .into_iter()
+                    .map(|(k, v)| (k.to_string(), v.to_string()))
+                    .collect())
+            }
+        }
+
+        fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+            while value >= 0x80 {
+                out.push(value as u8 | 0x80);
+                value >>= 7;
+            }
+            out.push(value as u8);
+        }
+    }
+
+    /// The DOT tokenizer on its own, for editors and linters that don't need a [`Graph`].
+    pub mod dot_lexer {
+        use std::fmt;
+        use std::ops::Range;
+
+        #[derive(Debug, PartialEq, Eq, Clone, Copy)]
+        pub enum Keyword {
+            Strict,
+            Graph,
+            Digraph,
+            Node,
+            Edge,
+            Subgraph,
+        }
+
+        #[derive(Debug, PartialEq, Eq, Clone)]
+        pub enum TokenKind {
+            /// A plain identifier, numeral or quoted string, already unescaped.
+            Id(String),
+            /// An `<...>` HTML string; its text is left in the source.
+            Html,
+            Keyword(Keyword),
+            LBrace,
+            RBrace,
+            LBracket,
+            RBracket,
+            Equals,
+            Semicolon,
+            Comma,
+            Colon,
+            Plus,
+            UndirectedEdge,
+            DirectedEdge,
+            /// A `//` or `/* */` comment, or a `#` preprocessor line.
+            Comment,
+            /// Text that isn't a token; lexing carries on after it.
+            Error(LexError),
+        }
+
+        #[derive(Debug, PartialEq, Eq, Clone, Copy)]
+        pub enum LexError {
+            UnexpectedCharacter,
+            MalformedNumber,
+            /// Spans the rest of the input, like the other unterminated errors.
+            UnterminatedString,
+            UnterminatedComment,
+            UnterminatedHtml,
+        }
+
+        impl LexError {
+            pub fn message(&self) -> &'static str {
+                match self {
+                    LexError::UnexpectedCharacter => "unexpected character",
+                    LexError::MalformedNumber => "malformed number",
+                    LexError::UnterminatedString => "unterminated string",
+                    LexError::UnterminatedComment => "unterminated comment",
+                    LexError::UnterminatedHtml => "unterminated HTML string",
+                }
+            }
+        }
+
+        impl fmt::Display for LexError {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str(self.message())
+            }
+        }
+
+        #[derive(Debug, PartialEq, Eq, Clone)]
+        pub struct Token {
+            pub(crate) kind: TokenKind,
+            pub(crate) span: Range<usize>,
+        }
+
+        impl Token {
+            pub fn kind(&self) -> &TokenKind {
+                &self.kind
+            }
+
+            /// The byte range of the token in the source.
+            pub fn span(&self) -> Range<usize> {
+                self.span.clone()
+            }
+        }
+
+        /// Every token in `src`, comments and errors included; whitespace is skipped.
+        pub fn tokenize(src: &str) -> Tokens<'_> {
+            Tokens {
+                src,
+                pos: 0,
+                line_start: true,
+            }
+        }
+
+        /// The iterator returned by [`tokenize`].
+        #[derive(Debug, Clone)]
+        pub struct Tokens<'a> {
+            src: &'a str,
+            pos: usize,
+            line_start: bool,
+        }
+
+        impl Iterator for Tokens<'_> {
+            type Item = Token;
+
+            fn next(&mut self) -> Option<Token> {
+                // Only ASCII whitespace separates tokens; anything non-ASCII is an ID char.
+                let rest = &self.src[self.pos..];
+                let skipped = rest.len()
+                    - rest
+                        .trim_start_matches(|c: char| c.is_ascii_whitespace())
+                        .len();
+                self.line_start |= rest[..skipped].contains('\n');
+                self.pos += skipped;
+
+                let start = self.pos;
+                let rest = &self.src[start..];
+                let c = rest.chars().next()?;
+                let line_start = std::mem::replace(&mut self.line_start, false);
+
+                let (kind, len) = match c {
+                    // `#` lines are C preprocessor output and ignored like comments.
+                    '/' if rest.starts_with("//") => (TokenKind::Comment, line_len(rest)),
+                    '#' if line_start => (TokenKind::Comment, line_len(rest)),
+                    '/' if rest.starts_with("/*") => match rest[2..].find("*/") {
+                        Some(end) => (TokenKind::Comment, end + 4),
+                        None => (TokenKind::Error(LexError::UnterminatedComment), rest.len()),
+                    },
+                    '{' => (TokenKind::LBrace, 1),
+                    '}' => (TokenKind::RBrace, 1),
+                    '[' => (TokenKind::LBracket, 1),
+                    ']' => (TokenKind::RBracket, 1),
+                    '=' => (TokenKind::Equals, 1),
+                    ';' => (TokenKind::Semicolon, 1),
+                    ',' => (TokenKind::Comma, 1),
+                    ':' => (TokenKind::Colon, 1),
+                    '+' => (TokenKind::Plus, 1),
+                    '-' if rest.starts_with("--") => (TokenKind::UndirectedEdge, 2),
+                    '-' if rest.starts_with("->") => (TokenKind::DirectedEdge, 2),
+                    '"' => lex_quoted(rest),
+                    '<' => lex_html(rest),
+                    c if c == '-' || c == '.' || c.is_ascii_digit() => {
+                        let len = rest
+                            .char_indices()
+                            .skip(1)
+                            .find(|&(_, c)| !(c == '.' || c.is_ascii_digit()))
+                            .map_or(rest.len(), |(i, _)| i);
+                        let numeral = &rest[..len];
+                        if crate::graph::is_numeral(numeral) {
+                            (TokenKind::Id(numeral.to_string()), len)
+                        } else {
+                            (TokenKind::Error(LexError::MalformedNumber), len)
+                        }
+                    }
+                    c if is_id_char(c) => {
+                        let len = rest
+                            .char_indices()
+                            .find(|&(_, c)| !is_id_char(c))
+                            .map_or(rest.len(), |(i, _)| i);
+                        let word = &rest[..len];
+                        let kind = keyword(word)
+                            .map(TokenKind::Keyword)
+                            .unwrap_or_else(|| TokenKind::Id(word.to_string()));
+                        (kind, len)
+                    }
+                    c => (
+                        TokenKind::Error(LexError::UnexpectedCharacter),
+                        c.len_utf8(),
+                    ),
+                };
+                self.pos += len;
+                Some(Token {
+                    kind,
+                    span: start..self.pos,
+                })
+            }
+        }
+
+        /// The DOT keywords, which are case-insensitive and must be quoted to be used as IDs.
+        pub(crate) fn is_keyword(id: &str) -> bool {
+            keyword(id).is_some()
+        }
+
+        fn keyword(id: &str) -> Option<Keyword> {
+            match id.to_ascii_lowercase().as_str() {
+                "strict" => Some(Keyword::Strict),
+                "graph" => Some(Keyword::Graph),
+                "digraph" => Some(Keyword::Digraph),
+                "node" => Some(Keyword::Node),
+                "edge" => Some(Keyword::Edge),
+                "subgraph" => Some(Keyword::Subgraph),
+                _ => None,
+            }
+        }
+
+        /// DOT treats every non-ASCII character as a letter.
+        fn is_id_char(c: char) -> bool {
+            c.is_ascii_alphanumeric() || c == '_' || !c.is_ascii()
+        }
+
+        /// Up to, not including, the end of the line.
+        fn line_len(rest: &str) -> usize {
+            rest.find('\n').unwrap_or(rest.len())
+        }
+
+        /// Lex a double-quoted string. Only `\"` and backslash-newline are escapes; every
+        /// other backslash sequence is kept verbatim since it means something to Graphviz.
+        fn lex_quoted(rest: &str) -> (TokenKind, usize) {
+            let mut value = String::new();
+            let mut chars = rest.char_indices().skip(1);
+            while let Some((i, c)) = chars.next() {
+                match c {
+                    '"' => return (TokenKind::Id(value), i + 1),
+                    '\\' => match chars.next() {
+                        Some((_, '"')) => value.push('"'),
+                        Some((_, '\n')) => {}
+                        Some((_, other)) => {
+                            value.push('\\');
+                            value.push(other);
+                        }
+                        None => break,
+                    },
+                    c => value.push(c),
+                }
+            }
+            (TokenKind::Error(LexError::UnterminatedString), rest.len())
+        }
+
+        fn lex_html(rest: &str) -> (TokenKind, usize) {
+            let mut depth = 0usize;
+            for (i, c) in rest.char_indices() {
+                match c {
+                    '<' => depth += 1,
+                    '>' => {
+                        depth -= 1;
+                        if depth == 0 {
+                            return (TokenKind::Html, i + 1);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            (TokenKind::Error(LexError::UnterminatedHtml), rest.len())
+        }
+    }
+
+    /// Reading DOT source back into a [`Graph`].
+    pub mod parser {
+        use super::attrs::Key;
+        use super::dot_lexer::{self, Keyword, LexError, Token, TokenKind};
+        use super::graph_items::edge::Edge;
+        use super::graph_items::node::Node;
+        use super::graph_items::subgraph::Subgraph;
+        use super::{Graph, GraphSink};
+        use std::borrow::Cow;
+        use std::collections::{HashSet, VecDeque};
+        use std::error::Error;
+        use std::fmt;
+        use std::io::{self, BufRead};
+        use std::ops::Range;
+        use std::sync::Arc;
+
+        /// A syntax error, with where it was found and the source line it was found on.
+        #[derive(Debug, PartialEq, Eq, Clone)]
+        pub struct ParseError {
+            message: String,
+            offset: usize,
+            len: usize,
+            line: usize,
+            column: usize,
+            token: String,
+            source_line: String,
+            limit: Option<LimitExceeded>,
+        }
+
+        impl ParseError {
+            /// An error at one character; `located` fills in the rest.
+            pub(crate) fn new(message: &str, offset: usize) -> Self {
+                ParseError::spanning(message, offset, 0)
+            }
+
+            /// An error covering `len` bytes, or one character if `len` is zero.
+            pub(crate) fn spanning(message: &str, offset: usize, len: usize) -> Self {
+                ParseError {
+                    message: message.to_string(),
+                    offset,
+                    len,
+                    line: 0,
+                    column: 0,
+                    token: String::new(),
+                    source_line: String::new(),
+                    limit: None,
+                }
+            }
+
+            fn limit(limit: LimitExceeded, offset: usize, len: usize) -> Self {
+                ParseError {
+                    limit: Some(limit),
+                    ..ParseError::spanning(&limit.to_string(), offset, len)
+                }
+            }
+
+            /// The limit that stopped the parse, if that's what this error is about.
+            pub fn limit_exceeded(&self) -> Option<LimitExceeded> {
+                self.limit
+            }
+
+            pub(crate) fn located(self, src: &str) -> Self {
+                let line_start = src[..self.offset].rfind('\n').map_or(0, |i| i + 1);
+                let line_end = src[self.offset..]
+                    .find('\n')
+                    .map_or(src.len(), |i| self.offset + i);
+                let len = match self.len {
+                    0 => src[self.offset..].chars().next().map_or(0, char::len_utf8),
+                    len => len,
+                };
+                // Multi-line tokens are shown up to the end of their first line.
+                let token_end = (self.offset + len).min(line_end);
+                ParseError {
+                    len,
+                    line: src[..line_start].matches('\n').count() + 1,
+                    column: src[line_start..self.offset].chars().count() + 1,
+                    token: src[self.offset..token_end].to_string(),
+                    source_line: src[line_start..line_end].trim_end_matches('\r').to_string(),
+                    ..self
+                }
+            }
+
+            pub fn message(&self) -> &str {
+                &self.message
+            }
+
+            /// The byte offset of the offending token.
+            pub fn offset(&self) -> usize {
+                self.offset
+            }
+
+            /// 1-based.
+            pub fn line(&self) -> usize {
+                self.line
+            }
+
+            /// 1-based, counted in characters.
+            pub fn column(&self) -> usize {
+                self.column
+            }
+
+            /// The source text that was rejected; empty at the end of input.
+            pub fn token(&self) -> &str {
+                &self.token
+            }
+        }
+
+        /// Renders the message followed by the source line, with the token underlined:
+        ///
+        /// ```text
+        /// expected an identifier at line 1, column 14
+        ///   |
+        /// 1 | graph { a -- node }
+        ///   |              ^^^^
+        /// ```
+        impl fmt::Display for ParseError {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                writeln!(
+                    f,
+                    "{} at line {}, column {}",
+                    self.message, self.line, self.column
+                )?;
+                let gutter = " ".repeat(self.line.to_string().len());
+                // Keep tabs so the caret lines up however wide they are displayed.
+                let indent = self
+                    .source_line
+                    .chars()
+                    .take(self.column - 1)
+                    .map(|c| if c == '\t' { '\t' } else { ' ' })
+                    .collect::<String>();
+                let carets = "^".repeat(self.token.chars().count().max(1));
+                writeln!(f, "{gutter} |")?;
+                writeln!(f, "{} | {}", self.line, self.source_line)?;
+                write!(f, "{gutter} | {indent}{carets}")
+            }
+        }
+
+        impl Error for ParseError {}
+
+        /// Why [`parse_reader`] failed.
+        #[derive(Debug)]
+        pub enum ReadError {
+            Io(io::Error),
+            Parse(ParseError),
+        }
+
+        impl fmt::Display for ReadError {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                match self {
+                    ReadError::Io(error) => write!(f, "cannot read DOT source: {error}"),
+                    ReadError::Parse(error) => error.fmt(f),
+                }
+            }
+        }
+
+        impl Error for ReadError {
+            fn source(&self) -> Option<&(dyn Error + 'static)> {
+                match self {
+                    ReadError::Io(error) => Some(error),
+                    ReadError::Parse(error) => Some(error),
+                }
+            }
+        }
+
+        impl From<io::Error> for ReadError {
+            fn from(error: io::Error) -> Self {
+                ReadError::Io(error)
+            }
+        }
+
+        impl From<ParseError> for ReadError {
+            fn from(error: ParseError) -> Self {
+                ReadError::Parse(error)
+            }
+        }
+
+        /// How forgiving [`parse_with`] is, and how much input it accepts.
+        #[derive(Debug, Clone, Default)]
+        pub struct ParseOptions {
+            lenient: bool,
+            limits: Limits,
+        }
+
+        /// Caps on untrusted input; `None` is unlimited.
+        #[derive(Debug, Clone, Copy, Default)]
+        struct Limits {
+            nodes: Option<usize>,
+            edges: Option<usize>,
+            depth: Option<usize>,
+            attr_len: Option<usize>,
+        }
+
+        impl ParseOptions {
+            pub fn new() -> Self {
+                ParseOptions::default()
+            }
+
+            /// Skip malformed statements instead of failing, reporting each one as a
+            /// diagnostic. A missing graph header or an unterminated string still fails,
+            /// as does going over a limit.
+            pub fn with_lenient(self, lenient: bool) -> Self {
+                ParseOptions { lenient, ..self }
+            }
+
+            /// Fail once more than `max` distinct nodes, edge endpoints included, appear.
+            pub fn with_max_nodes(mut self, max: usize) -> Self {
+                self.limits.nodes = Some(max);
+                self
+            }
+
+            /// Fail once more than `max` edges appear.
+            pub fn with_max_edges(mut self, max: usize) -> Self {
+                self.limits.edges = Some(max);
+                self
+            }
+
+            /// Fail when braces and brackets nest more than `max` deep; the graph's own
+            /// braces count as one level.
+            pub fn with_max_depth(mut self, max: usize) -> Self {
+                self.limits.depth = Some(max);
+                self
+            }
+
+            /// Fail on an attribute value longer than `max` bytes.
+            pub fn with_max_attr_len(mut self, max: usize) -> Self {
+                self.limits.attr_len = Some(max);
+                self
+            }
+        }
+
+        /// Which [`ParseOptions`] limit the input went over, and the limit itself.
+        #[derive(Debug, PartialEq, Eq, Clone, Copy)]
+        pub enum LimitExceeded {
+            Nodes(usize),
+            Edges(usize),
+            Depth(usize),
+            AttrLength(usize),
+        }
+
+        impl fmt::Display for LimitExceeded {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                match self {
+                    LimitExceeded::Nodes(max) => write!(f, "more than {max} nodes"),
+                    LimitExceeded::Edges(max) => write!(f, "more than {max} edges"),
+                    LimitExceeded::Depth(max) => write!(f, "nested more than {max} deep"),
+                    LimitExceeded::AttrLength(max) => {
+                        write!(f, "attribute value longer than {max} bytes")
+                    }
+                }
+            }
+        }
+
+        /// The parser's view of the token stream: no comments, and lexing errors turned into
+        /// `ParseError`s. With `diagnostics`, stray characters and bad numbers are reported
+        /// there and skipped instead of failing.
+        fn tokenize(
+            src: &str,
+            mut diagnostics: Option<&mut Vec<ParseError>>,
+        ) -> Result<Vec<Token>, ParseError> {
+            let mut tokens = Vec::new();
+            for token in dot_lexer::tokenize(src) {
+                match token.kind {
+                    TokenKind::Comment => {}
+                    TokenKind::Error(
+                        error @ (LexError::UnexpectedCharacter | LexError::MalformedNumber),
+                    ) => {
+                        let error = ParseError::spanning(
+                            error.message(),
+                            token.span.start,
+                            token.span.len(),
+                        );
+                        match diagnostics.as_deref_mut() {
+                            Some(diagnostics) => diagnostics.push(error),
+                            None => return Err(error),
+                        }
+                    }
+                    // Unterminated strings and comments swallow the rest of the input.
+                    TokenKind::Error(error) => {
+                        return Err(ParseError::new(error.message(), token.span.start))
+                    }
+                    _ => tokens.push(token),
+                }
+            }
+            Ok(tokens)
+        }
+
+        struct Parser<'a> {
+            src: &'a str,
+            tokens: Vec<Token>,
+            pos: usize,
+            sink: &'a mut dyn GraphSink,
+            directed: bool,
+            /// `Some` in lenient mode, collecting the errors that were skipped over.
+            diagnostics: Option<Vec<ParseError>>,
+            /// Byte ranges of the statements parsed so far, `;` included.
+            spans: Vec<Range<usize>>,
+            /// Where the text between the graph's braces starts.
+            body_start: usize,
+            limits: Limits,
+            /// The node names seen so far, only tracked under a node limit.
+            names: HashSet<String>,
+            edge_count: usize,
+        }
+
+        /// Parse a complete `graph { ... }` or `digraph { ... }` document.
+        pub fn parse(src: &str) -> Result<Graph, ParseError> {
+            parse_with(src, &ParseOptions::new()).map(|(graph, _)| graph)
+        }
+
+        /// Like [`parse`], and also returns the problems lenient mode skipped over.
+        pub fn parse_with(
+            src: &str,
+            options: &ParseOptions,
+        ) -> Result<(Graph, Vec<ParseError>), ParseError> {
+            let mut graph = Graph::new();
+            let diagnostics = parse_into(src, options, &mut graph)?;
+            Ok((graph, diagnostics))
+        }
+
+        /// Parse `src` statement by statement into `sink` instead of building a [`Graph`],
+        /// returning the problems lenient mode skipped over.
+        ///
+        /// On error the sink has already seen the statements before it, and no `end_graph`.
+        pub fn parse_into(
+            src: &str,
+            options: &ParseOptions,
+            sink: &mut impl GraphSink,
+        ) -> Result<Vec<ParseError>, ParseError> {
+            let parser = run(src, options, sink)?;
+            let diagnostics = parser.diagnostics.unwrap_or_default();
+            Ok(diagnostics.into_iter().map(|e| e.located(src)).collect())
+        }
+
+        fn run<'a>(
+            src: &'a str,
+            options: &ParseOptions,
+            sink: &'a mut dyn GraphSink,
+        ) -> Result<Parser<'a>, ParseError> {
+            let mut diagnostics = options.lenient.then(Vec::new);
+            let tokens = tokenize(src, diagnostics.as_mut()).map_err(|e| e.located(src))?;
+            if let Some(max) = options.limits.depth {
+                check_depth(&tokens, max).map_err(|e| e.located(src))?;
+            }
+            let mut parser = Parser::new(src, tokens, diagnostics, sink);
+            parser.limits = options.limits;
+            parser.graph().map_err(|e| e.located(src))?;
+            parser.sink.end_graph();
+            Ok(parser)
+        }
+
+        /// Parse DOT read from `reader` a statement at a time, so only the statement being
+        /// read is held as text, not the whole document. Errors point into the input as a
+        /// whole, just as [`parse`] would report them.
+        pub fn parse_reader(reader: impl io::Read) -> Result<Graph, ReadError> {
+            let mut reader = io::BufReader::new(reader);
+            let mut stream = Stream::default();
+            let mut graph = Graph::new();
+            let mut line = String::new();
+            while reader.read_line(&mut line)? > 0 {
+                stream.pending.push_str(&line);
+                line.clear();
+                stream.scan(&mut graph)?;
+            }
+            stream.finish(&mut graph)?;
+            Ok(graph)
+        }
+
+        /// One statement from a [`DotReader`], named after the [`GraphSink`] call it
+        /// stands for.
+        #[derive(Debug, PartialEq, Eq, Clone)]
+        pub enum Event {
+            BeginGraph { directed: bool },
+            GraphAttrs(Vec<(String, String)>),
+            NodeDefaults(Vec<(String, String)>),
+            EdgeDefaults(Vec<(String, String)>),
+            Node(Node),
+            Subgraph(Subgraph),
+            Edge(Edge),
+            EndGraph,
+        }
+
+        /// Pulls DOT from a reader one statement at a time and yields it as [`Event`]s,
+        /// without building a [`Graph`], so filters and converters can handle inputs
+        /// larger than memory. Errors are those [`parse_reader`] would report, yielded
+        /// after the events of the statements before them; after one the iterator ends.
+        pub struct DotReader<R> {
+            reader: io::BufReader<R>,
+            stream: Stream,
+            line: String,
+            events: Events,
+            done: bool,
+            /// Held back until the events queued before it have been handed out.
+            error: Option<ReadError>,
+        }
+
+        impl<R: io::Read> DotReader<R> {
+            pub fn new(reader: R) -> Self {
+                DotReader {
+                    reader: io::BufReader::new(reader),
+                    stream: Stream::default(),
+                    line: String::new(),
+                    events: Events::default(),
+                    done: false,
+                    error: None,
+                }
+            }
+
+            /// Read on until at least one event is queued or the input runs out.
+            fn fill(&mut self) -> Result<(), ReadError> {
+                while self.events.0.is_empty() {
+                    self.line.clear();
+                    if self.reader.read_line(&mut self.line)? == 0 {
+                        self.done = true;
+                        return Ok(self.stream.finish(&mut self.events)?);
+                    }
+                    self.stream.pending.push_str(&self.line);
+                    self.stream.scan(&mut self.events)?;
+                }
+                Ok(())
+            }
+        }
+
+        impl<R: io::Read> Iterator for DotReader<R> {
+            type Item = Result<Event, ReadError>;
+
+            fn next(&mut self) -> Option<Self::Item> {
+                if self.events.0.is_empty() && !self.done {
+                    if let Err(error) = self.fill() {
+                        self.done = true;
+                        self.error = Some(error);
+                    }
+                }
+                match self.events.0.pop_front() {
+                    Some(event) => Some(Ok(event)),
+                    None => self.error.take().map(Err),
+                }
+            }
+        }
+
+        /// The events a [`DotReader`] has parsed but not yet handed out.
+        #[derive(Default)]
+        struct Events(VecDeque<Event>);
+
+        impl GraphSink for Events {
+            fn begin_graph(&mut self, directed: bool) {
+                self.0.push_back(Event::BeginGraph { directed });
+            }
+
+            fn graph_attrs(&mut self, attrs: &[(&str, &str)]) {
+                self.0.push_back(Event::GraphAttrs(owned(attrs)));
+            }
+
+            fn node_defaults(&mut self, attrs: &[(&str, &str)]) {
+                self.0.push_back(Event::NodeDefaults(owned(attrs)));
+            }
+
+            fn edge_defaults(&mut self, attrs: &[(&str, &str)]) {
+                self.0.push_back(Event::EdgeDefaults(owned(attrs)));
+            }
+
+            fn node(&mut self, node: Node) {
+                self.0.push_back(Event::Node(node));
+            }
+
+            fn subgraph(&mut self, subgraph: Subgraph) {
+                self.0.push_back(Event::Subgraph(subgraph));
+            }
+
+            fn edge(&mut self, edge: Edge) {
+                self.0.push_back(Event::Edge(edge));
+            }
+
+            fn end_graph(&mut self) {
+                self.0.push_back(Event::EndGraph);
+            }
+        }
+
+        fn owned(attrs: &[(&str, &str)]) -> Vec<(String, String)> {
+            attrs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect()
+        }
+
+        /// Parse an edge list read from `reader` a line at a time: each line holds two
+        /// node names separated by a comma or whitespace, or a single name to declare a
+        /// node. A name holding a separator, a `"` or a leading `#` is double-quoted, with
+        /// `\"` and `\\` escapes. Blank lines and lines starting with `#` are skipped.
+        /// Only the current line is held as text, and each distinct name is stored once
+        /// and shared by every edge naming it, so peak memory is about the size of the
+        /// graph itself.
+        ///
+        /// The graph is undirected; use [`Graph::with_directed`] to change that.
+        pub fn parse_edge_list(reader: impl io::Read) -> Result<Graph, ReadError> {
+            let mut reader = io::BufReader::new(reader);
+            let mut graph = Graph::new();
+            let mut line = String::new();
+            let (mut offset, mut number) = (0, 0);
+            let mut names = HashSet::<Arc<str>>::new();
+            let mut shared = |name: &str| match names.get(name) {
+                Some(shared) => Key::Shared(Arc::clone(shared)),
+                None => {
+                    let shared = Arc::<str>::from(name);
+                    names.insert(Arc::clone(&shared));
+                    Key::Shared(shared)
+                }
+            };
+            while reader.read_line(&mut line)? > 0 {
+                let text = line.trim_end_matches(['\n', '\r']);
+                let located = |error: ParseError| {
+                    let mut error = error.located(text);
+                    error.line += number;
+                    error.offset += offset;
+                    error
+                };
+                let fields = edge_list_fields(text).map_err(located)?;
+                match fields.as_slice() {
+                    [] => {}
+                    [name] => GraphSink::node(&mut graph, Node::new(&name.1)),
+                    [(_, node1), (_, node2)] => {
+                        let edge = Edge::shared(shared(node1), shared(node2));
+                        GraphSink::edge(&mut graph, edge);
+                    }
+                    [_, _, (extra, _), ..] => {
+                        let error = ParseError::spanning(
+                            "expected at most two names on a line",
+                            extra.start,
+                            extra.len(),
+                        );
+                        return Err(located(error).into());
                     }
                 }
+                offset += line.len();
+                number += 1;
+                line.clear();
+            }
+            Ok(graph)
+        }
 
-                pub fn with_attrs(self, attrs: &[(&str, &str)]) -> Self {
-                    // Merge existing attrs with the new list, purely functional
-                    let merged_attrs = merge_map_and_list(&self.attrs, attrs);
-                    Node {
-                        name: self.name,
-                        attrs: merged_attrs,
+        /// A name on an edge-list line, and where it is in the line.
+        type Field<'a> = (Range<usize>, Cow<'a, str>);
+
+        /// The names on one edge-list line; none for a comment.
+        fn edge_list_fields(text: &str) -> Result<Vec<Field<'_>>, ParseError> {
+            let is_separator = |c: char| c == ',' || c.is_whitespace();
+            let mut fields = Vec::new();
+            let mut chars = text.char_indices().peekable();
+            while let Some(&(start, c)) = chars.peek() {
+                if is_separator(c) {
+                    chars.next();
+                } else if c == '#' && fields.is_empty() {
+                    break;
+                } else if c == '"' {
+                    chars.next();
+                    let mut name = String::new();
+                    let end = loop {
+                        match chars.next() {
+                            Some((i, '"')) => break Some(i + 1),
+                            Some((_, '\\')) => match chars.next() {
+                                Some((_, c @ ('"' | '\\'))) => name.push(c),
+                                Some((_, c)) => {
+                                    name.push('\\');
+                                    name.push(c);
+                                }
+                                None => break None,
+                            },
+                            Some((_, c)) => name.push(c),
+                            None => break None,
+                        }
+                    };
+                    let Some(end) = end else {
+                        return Err(ParseError::spanning("unterminated quoted name", start, 1));
+                    };
+                    if let Some(&(i, c)) = chars.peek().filter(|&&(_, c)| !is_separator(c)) {
+                        let message = "expected a comma or whitespace after a quoted name";
+                        return Err(ParseError::spanning(message, i, c.len_utf8()));
+                    }
+                    fields.push((start..end, Cow::Owned(name)));
+                } else {
+                    let len = text[start..]
+                        .find(is_separator)
+                        .unwrap_or(text.len() - start);
+                    fields.push((start..start + len, Cow::Borrowed(&text[start..start + len])));
+                    while chars.peek().is_some_and(|&(i, _)| i < start + len) {
+                        chars.next();
                     }
                 }
+            }
+            Ok(fields)
+        }
 
-                pub fn attr(&self, key: &str) -> Option<&str> {
-                    self.attrs.get(key).map(|s| s.as_str())
+        /// [`parse_reader`]'s state: the text not yet parsed, where it starts in the
+        /// input, and enough lexical context to tell where a statement ends.
+        #[derive(Default)]
+        struct Stream {
+            pending: String,
+            /// How far `pending` has been scanned.
+            scanned: usize,
+            /// Byte offset and line (from zero) of `pending`'s start.
+            offset: usize,
+            line: usize,
+            /// What came before `pending` on its first line.
+            line_prefix: String,
+            in_string: bool,
+            escaped: bool,
+            line_comment: bool,
+            block_comment: bool,
+            /// Whether a token came before on the current line, since `#` only starts a
+            /// comment at the start of one, as in the lexer.
+            mid_line: bool,
+            depth: usize,
+            /// Whether the last token seen is an operator that needs a right-hand side,
+            /// such as a trailing `--`, so the statement goes on on the next line.
+            dangling: bool,
+            /// Where `pending` could be cut, once it's clear the next line doesn't carry on
+            /// the same statement.
+            cut: Option<usize>,
+            /// `Some` once the header has been read.
+            directed: Option<bool>,
+            /// Whether the closing `}` has been seen.
+            closed: bool,
+        }
+
+        impl Stream {
+            fn scan(&mut self, sink: &mut dyn GraphSink) -> Result<(), ParseError> {
+                let mut i = self.scanned;
+                while let Some(c) = self.pending[i..].chars().next() {
+                    let next = self.pending[i + c.len_utf8()..].chars().next();
+                    let mut end = i + c.len_utf8();
+                    if self.closed {
+                        // Only trailing trivia is allowed; `finish` checks it.
+                    } else if self.line_comment {
+                        self.line_comment = c != '\n';
+                        self.mid_line = self.line_comment;
+                    } else if self.block_comment {
+                        if c == '*' && next == Some('/') {
+                            self.block_comment = false;
+                            end += 1;
+                        }
+                    } else if self.in_string {
+                        match c {
+                            _ if self.escaped => self.escaped = false,
+                            '\\' => self.escaped = true,
+                            '"' => {
+                                self.in_string = false;
+                                self.dangling = false;
+                            }
+                            _ => {}
+                        }
+                    } else if c == '/' && next == Some('/') || c == '#' && !self.mid_line {
+                        self.line_comment = true;
+                    } else if c == '/' && next == Some('*') {
+                        self.block_comment = true;
+                        self.mid_line = true;
+                        end += 1;
+                    } else if c.is_whitespace() {
+                        if c == '\n' {
+                            self.mid_line = false;
+                        }
+                        if c == '\n' && self.depth == 0 && self.directed.is_some() && !self.dangling
+                        {
+                            self.cut.get_or_insert(end);
+                        }
+                    } else {
+                        self.mid_line = true;
+                        if let Some(cut) = self.cut.take() {
+                            // A chain, attribute list or concatenation can go on on the
+                            // next line.
+                            if !matches!(c, '-' | '[' | '+' | '=' | ':') {
+                                self.flush(cut, sink)?;
+                                i -= cut;
+                                end -= cut;
+                            }
+                        }
+                        // Erring towards `true` only keeps statements together longer.
+                        self.dangling = matches!(c, '-' | '>' | '+' | '=' | ':' | ',');
+                        match c {
+                            '"' => self.in_string = true,
+                            '{' if self.directed.is_none() => {
+                                self.header(end, sink)?;
+                                end = 0;
+                            }
+                            '[' | '{' => self.depth += 1,
+                            ']' => self.depth = self.depth.saturating_sub(1),
+                            '}' if self.depth > 0 => self.depth -= 1,
+                            // Before the header there are no statements to parse; `finish`
+                            // reports what's wrong with it once the input runs out.
+                            '}' | ';' if self.directed.is_none() => {}
+                            '}' => {
+                                self.flush(i, sink)?;
+                                self.closed = true;
+                                end = 1;
+                            }
+                            ';' if self.depth == 0 => {
+                                self.flush(end, sink)?;
+                                end = 0;
+                            }
+                            _ => {}
+                        }
+                    }
+                    i = end;
                 }
+                self.scanned = i;
+                Ok(())
+            }
 
-                pub fn name(&self) -> &str {
-                    &self.name
+            /// Parse `pending[..end]`, the header up to and including its `{`.
+            fn header(&mut self, end: usize, sink: &mut dyn GraphSink) -> Result<(), ParseError> {
+                let head = format!("{}}}", &self.pending[..end]);
+                let directed = run(&head, &ParseOptions::new(), &mut ())
+                    .map_err(|error| self.relocated(error.located(&self.pending)))?
+                    .directed;
+                sink.begin_graph(directed);
+                self.directed = Some(directed);
+                self.advance(end);
+                Ok(())
+            }
+
+            /// Parse the statements in `pending[..end]` into `sink` and drop them.
+            fn flush(&mut self, end: usize, sink: &mut dyn GraphSink) -> Result<(), ParseError> {
+                let chunk = &self.pending[..end];
+                let directed = self.directed.expect("statements only follow the header");
+                let tokens = tokenize(chunk, None);
+                let result = tokens.and_then(|tokens| {
+                    let mut parser = Parser::new(chunk, tokens, None, sink);
+                    parser.directed = directed;
+                    while parser.peek().is_some() {
+                        parser.stmt_and_semicolon()?;
+                    }
+                    Ok(())
+                });
+                // Locate errors in all of `pending`, so one at the end of the chunk points at
+                // the token after it, as it would in the whole document.
+                result.map_err(|error| self.relocated(error.located(&self.pending)))?;
+                self.advance(end);
+                Ok(())
+            }
+
+            /// Move the start of `pending` forward by `len` bytes.
+            fn advance(&mut self, len: usize) {
+                let consumed = &self.pending[..len];
+                self.offset += len;
+                match consumed.rfind('\n') {
+                    Some(last) => {
+                        self.line += consumed.matches('\n').count();
+                        self.line_prefix = consumed[last + 1..].to_string();
+                    }
+                    None => self.line_prefix.push_str(consumed),
+                }
+                self.pending.drain(..len);
+            }
+
+            /// An error located within `pending`, moved to where `pending` starts.
+            fn relocated(&self, mut error: ParseError) -> ParseError {
+                if error.line == 1 {
+                    error.column += self.line_prefix.chars().count();
+                    error.source_line.insert_str(0, &self.line_prefix);
+                }
+                error.line += self.line;
+                error.offset += self.offset;
+                error
+            }
+
+            /// Check what's left once the input has run out, ending the graph in `sink`.
+            fn finish(&mut self, sink: &mut dyn GraphSink) -> Result<(), ParseError> {
+                if !self.closed {
+                    if self.directed.is_none() {
+                        // Not even a header: let the parser say what's wrong with it.
+                        return Err(run(&self.pending, &ParseOptions::new(), &mut ())
+                            .err()
+                            .unwrap_or_else(|| ParseError::new("expected `{`", 0)));
+                    }
+                    let end = self.pending.len();
+                    self.flush(end, sink)?;
+                    let error = ParseError::new("expected `}`", 0).located("");
+                    return Err(self.relocated(error));
                 }
+                // `pending` holds the closing `}` and whatever followed it, lexed together
+                // so a `#` straight after the `}` isn't taken for a comment.
+                let error = match tokenize(&self.pending, None) {
+                    Err(error) => Some(error),
+                    Ok(tokens) => tokens.get(1).map(|token| {
+                        ParseError::spanning(
+                            "unexpected input after the graph",
+                            token.span.start,
+                            token.span.len(),
+                        )
+                    }),
+                };
+                if let Some(error) = error {
+                    return Err(self.relocated(error.located(&self.pending)));
+                }
+                sink.end_graph();
+                Ok(())
             }
         }
 
-        pub mod edge {
-            // Import the helper from the parent `graph` module.
-            use crate::graph::merge_map_and_list;
-            use std::collections::HashMap;
+        /// Checked upfront, so deeply nested input fails before anything recurses into it.
+        fn check_depth(tokens: &[Token], max: usize) -> Result<(), ParseError> {
+            let mut depth = 0usize;
+            for token in tokens {
+                match token.kind {
+                    TokenKind::LBrace | TokenKind::LBracket => {
+                        depth += 1;
+                        if depth > max {
+                            let span = &token.span;
+                            return Err(ParseError::limit(
+                                LimitExceeded::Depth(max),
+                                span.start,
+                                span.len(),
+                            ));
+                        }
+                    }
+                    TokenKind::RBrace | TokenKind::RBracket => depth = depth.saturating_sub(1),
+                    _ => {}
+                }
+            }
+            Ok(())
+        }
 
-            #[derive(Debug, PartialEq, Eq, Clone)]
-            pub struct Edge {
-                node1: String,
-                node2: String,
-                attrs: HashMap<String, String>,
+        /// The byte ranges of the statements making up `src`, which holds no graph header.
+        fn statement_spans(src: &str, directed: bool) -> Result<Vec<Range<usize>>, ParseError> {
+            let tokens = tokenize(src, None).map_err(|e| e.located(src))?;
+            let mut ignored = ();
+            let mut parser = Parser::new(src, tokens, None, &mut ignored);
+            parser.directed = directed;
+            while parser.peek().is_some() {
+                parser.stmt_and_semicolon().map_err(|e| e.located(src))?;
             }
+            Ok(parser.spans)
+        }
 
-            impl Edge {
-                pub fn new(node1: &str, node2: &str) -> Self {
-                    Edge {
-                        node1: node1.to_string(),
-                        node2: node2.to_string(),
-                        attrs: HashMap::new(),
+        /// Whether `fragment`, put in place of `src[range]`, lexes as it does on its own.
+        /// It doesn't when a trailing `//` comment swallows the rest of the line, such as
+        /// the closing `}`, or when a `#` starts a comment only because the fragment starts
+        /// a line that it doesn't in `src`.
+        fn lexes_in_place(src: &str, range: Range<usize>, fragment: &str) -> bool {
+            let line_start = src[..range.start].rfind('\n').map_or(0, |i| i + 1);
+            let line_end = src[range.end..]
+                .find('\n')
+                .map_or(src.len(), |i| range.end + i);
+            let before = &src[line_start..range.start];
+            let spliced = format!("{before}{fragment}{}", &src[range.end..line_end]);
+            let (start, end) = (before.len(), before.len() + fragment.len());
+            let mut in_place = dot_lexer::tokenize(&spliced)
+                .skip_while(|token| token.span.end <= start)
+                .take_while(|token| token.span.start < end);
+            let mut alone = dot_lexer::tokenize(fragment);
+            loop {
+                match (in_place.next(), alone.next()) {
+                    (None, None) => return true,
+                    (Some(placed), Some(token)) => {
+                        if placed.span.start < start
+                            || placed.kind != token.kind
+                            || placed.span.start - start != token.span.start
+                            || placed.span.end - start != token.span.end
+                        {
+                            return false;
+                        }
                     }
+                    _ => return false,
                 }
+            }
+        }
 
-                pub fn with_attrs(self, attrs: &[(&str, &str)]) -> Self {
-                    let merged_attrs = merge_map_and_list(&self.attrs, attrs);
-                    Edge {
-                        node1: self.node1,
-                        node2: self.node2,
-                        attrs: merged_attrs,
+        /// DOT source kept exactly as written, comments and layout included, split into
+        /// statements that can be edited one at a time.
+        ///
+        /// Printing a `Document` gives back its source, changed only where it was edited.
+        #[derive(Debug, PartialEq, Eq, Clone)]
+        pub struct Document {
+            /// Everything up to and including the opening `{`.
+            head: String,
+            statements: Vec<Statement>,
+            /// The closing `}` and whatever surrounds it.
+            tail: String,
+            directed: bool,
+        }
+
+        /// One statement of a [`Document`], with the comments and whitespace before it.
+        #[derive(Debug, PartialEq, Eq, Clone)]
+        pub struct Statement {
+            trivia: String,
+            text: String,
+        }
+
+        impl Statement {
+            /// The statement itself, `;` included when it had one.
+            pub fn text(&self) -> &str {
+                &self.text
+            }
+
+            /// The whitespace and comments between this statement and the previous one.
+            pub fn trivia(&self) -> &str {
+                &self.trivia
+            }
+        }
+
+        impl Document {
+            pub fn parse(src: &str) -> Result<Self, ParseError> {
+                let mut ignored = ();
+                let parser = run(src, &ParseOptions::new(), &mut ignored)?;
+                let mut end = parser.body_start;
+                let statements = parser
+                    .spans
+                    .iter()
+                    .map(|span| {
+                        let statement = Statement {
+                            trivia: src[end..span.start].to_string(),
+                            text: src[span.clone()].to_string(),
+                        };
+                        end = span.end;
+                        statement
+                    })
+                    .collect();
+                Ok(Document {
+                    head: src[..parser.body_start].to_string(),
+                    statements,
+                    tail: src[end..].to_string(),
+                    directed: parser.directed,
+                })
+            }
+
+            pub fn statements(&self) -> &[Statement] {
+                &self.statements
+            }
+
+            /// Replace the text of statement `index`, keeping the comments above it.
+            ///
+            /// `text` must be exactly one statement. Panics if `index` is out of bounds.
+            pub fn replace_statement(
+                &mut self,
+                index: usize,
+                text: &str,
+            ) -> Result<(), ParseError> {
+                self.check_single(text)?;
+                self.statements[index].text = text.to_string();
+                Ok(())
+            }
+
+            /// Add a statement at the end, indented like the last one.
+            pub fn push_statement(&mut self, text: &str) -> Result<(), ParseError> {
+                self.check_single(text)?;
+                let trivia = match self.statements.last() {
+                    Some(last) => last
+                        .trivia
+                        .rfind('\n')
+                        .map_or("\n    ", |i| &last.trivia[i..]),
+                    None => "\n    ",
+                };
+                self.statements.push(Statement {
+                    trivia: trivia.to_string(),
+                    text: text.to_string(),
+                });
+                Ok(())
+            }
+
+            /// Remove statement `index` along with its line, keeping any comments above it.
+            ///
+            /// Panics if `index` is out of bounds.
+            pub fn remove_statement(&mut self, index: usize) -> Statement {
+                let mut removed = self.statements.remove(index);
+                let trivia = removed.trivia.trim_end_matches([' ', '\t']);
+                let kept = trivia.strip_suffix('\n').unwrap_or(trivia).to_string();
+                match self.statements.get_mut(index) {
+                    Some(next) => next.trivia.insert_str(0, &kept),
+                    None => self.tail.insert_str(0, &kept),
+                }
+                removed.trivia.replace_range(..kept.len(), "");
+                removed
+            }
+
+            /// Replace the bytes in `range` of the current source with `text`, reparsing only
+            /// the statements the edit touches, and return the indices of the statements
+            /// that took their place.
+            ///
+            /// Edits reaching into the graph header or past the closing `}`, or whose
+            /// statements don't parse on their own, fall back to reparsing everything. On
+            /// error the document is left unchanged. Panics if `range` is out of bounds.
+            pub fn edit(
+                &mut self,
+                range: Range<usize>,
+                text: &str,
+            ) -> Result<Range<usize>, ParseError> {
+                let mut src = self.to_string();
+                let mut regions = Vec::with_capacity(self.statements.len());
+                let mut body_end = self.head.len();
+                for statement in &self.statements {
+                    let start = body_end;
+                    body_end += statement.trivia.len() + statement.text.len();
+                    regions.push(start..body_end);
+                }
+                // The closing `}` is the first token in `tail` that isn't a comment.
+                let tail_trivia = dot_lexer::tokenize(&self.tail)
+                    .find(|token| token.kind != TokenKind::Comment)
+                    .map_or(0, |token| token.span.start);
+
+                if self.head.len() <= range.start && range.end <= body_end + tail_trivia {
+                    // Touching a statement's edge counts, since the edit may extend it.
+                    let first = regions
+                        .iter()
+                        .position(|region| region.end >= range.start)
+                        .unwrap_or(regions.len());
+                    let last = regions
+                        .iter()
+                        .rposition(|region| region.start <= range.end)
+                        .map_or(0, |i| i + 1)
+                        .max(first);
+                    let from = regions.get(first).map_or(body_end, |region| region.start);
+                    let takes_tail = range.end >= body_end;
+                    let to = if takes_tail {
+                        body_end + tail_trivia
+                    } else {
+                        regions[last - 1].end
+                    };
+                    let mut fragment = src[from..to].to_string();
+                    fragment.replace_range(range.start - from..range.end - from, text);
+                    let spans = if lexes_in_place(&src, from..to, &fragment) {
+                        statement_spans(&fragment, self.directed).ok()
+                    } else {
+                        None
+                    };
+                    if let Some(spans) = spans {
+                        let mut end = 0;
+                        let statements = spans
+                            .into_iter()
+                            .map(|span| {
+                                let statement = Statement {
+                                    trivia: fragment[end..span.start].to_string(),
+                                    text: fragment[span.clone()].to_string(),
+                                };
+                                end = span.end;
+                                statement
+                            })
+                            .collect::<Vec<_>>();
+                        let replaced = first..first + statements.len();
+                        self.statements.splice(first..last, statements);
+                        let rest = &fragment[end..];
+                        if takes_tail {
+                            self.tail.replace_range(..tail_trivia, rest);
+                        } else {
+                            match self.statements.get_mut(replaced.end) {
+                                Some(next) => next.trivia.insert_str(0, rest),
+                                None => self.tail.insert_str(0, rest),
+                            }
+                        }
+                        return Ok(replaced);
                     }
                 }
 
-                pub fn attr(&self, key: &str) -> Option<&str> {
-                    self.attrs.get(key).map(|s| s.as_str())
+                src.replace_range(range, text);
+                *self = Document::parse(&src)?;
+                Ok(0..self.statements.len())
+            }
+
+            /// The graph the document currently describes.
+            pub fn graph(&self) -> Result<Graph, ParseError> {
+                parse(&self.to_string())
+            }
+
+            fn check_single(&self, text: &str) -> Result<(), ParseError> {
+                let spans = statement_spans(text, self.directed)?;
+                match spans.as_slice() {
+                    [span] if span.start == 0 && span.end == text.len() => Ok(()),
+                    _ => Err(ParseError::new("expected exactly one statement", 0).located(text)),
                 }
             }
         }
-    }
 
-    use graph_items::edge::Edge;
-    use graph_items::node::Node;
+        impl fmt::Display for Document {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str(&self.head)?;
+                for statement in &self.statements {
+                    f.write_str(&statement.trivia)?;
+                    f.write_str(&statement.text)?;
+                }
+                f.write_str(&self.tail)
+            }
+        }
 
-    #[derive(Debug, PartialEq, Eq)]
-    pub struct Graph {
-        pub nodes: Vec<Node>,
-        pub edges: Vec<Edge>,
-        pub attrs: HashMap<String, String>,
+        fn pairs_as_str(pairs: &[(String, String)]) -> Vec<(&str, &str)> {
+            pairs
+                .iter()
+                .map(|(k, v)| (k.as_str(), v.as_str()))
+                .collect()
+        }
+
+        impl<'a> Parser<'a> {
+            fn new(
+                src: &'a str,
+                tokens: Vec<Token>,
+                diagnostics: Option<Vec<ParseError>>,
+                sink: &'a mut dyn GraphSink,
+            ) -> Self {
+                Parser {
+                    src,
+                    tokens,
+                    pos: 0,
+                    sink,
+                    directed: false,
+                    diagnostics,
+                    spans: Vec::new(),
+                    body_start: 0,
+                    limits: Limits::default(),
+                    names: HashSet::new(),
+                    edge_count: 0,
+                }
+            }
+
+            fn peek(&self) -> Option<&TokenKind> {
+                self.tokens.get(self.pos).map(|token| &token.kind)
+            }
+
+            fn offset(&self) -> usize {
+                self.tokens
+                    .get(self.pos)
+                    .map_or(self.src.len(), |token| token.span.start)
+            }
+
+            fn error<T>(&self, message: &str) -> Result<T, ParseError> {
+                let len = self
+                    .tokens
+                    .get(self.pos)
+                    .map_or(0, |token| token.span.len());
+                Err(ParseError::spanning(message, self.offset(), len))
+            }
+
+            fn eat(&mut self, kind: &TokenKind) -> bool {
+                if self.peek() == Some(kind) {
+                    self.pos += 1;
+                    true
+                } else {
+                    false
+                }
+            }
+
+            fn expect(&mut self, kind: &TokenKind, message: &str) -> Result<(), ParseError> {
+                if self.eat(kind) {
+                    Ok(())
+                } else {
+                    self.error(message)
+                }
+            }
+
+            /// An ID, joining `"a" + "b"` concatenations.
+            fn id(&mut self) -> Result<String, ParseError> {
+                let mut id = match self.peek() {
+                    Some(TokenKind::Id(id)) => id.clone(),
+                    Some(TokenKind::Html) => return self.error("HTML strings are not supported"),
+                    _ => return self.error("expected an identifier"),
+                };
+                self.pos += 1;
+                while self.eat(&TokenKind::Plus) {
+                    match self.peek() {
+                        Some(TokenKind::Id(next)) => id.push_str(next),
+                        _ => return self.error("expected a string after `+`"),
+                    }
+                    self.pos += 1;
+                }
+                Ok(id)
+            }
+
+            fn graph(&mut self) -> Result<(), ParseError> {
+                if self.eat(&TokenKind::Keyword(Keyword::Strict)) {
+                    return self.error("strict graphs are not supported");
+                }
+                match self.peek() {
+                    Some(TokenKind::Keyword(Keyword::Graph)) => self.pos += 1,
+                    Some(TokenKind::Keyword(Keyword::Digraph)) => {
+                        self.directed = true;
+                        self.pos += 1;
+                    }
+                    _ => return self.error("expected `graph` or `digraph`"),
+                }
+                if let Some(TokenKind::Id(_)) = self.peek() {
+                    self.id()?;
+                }
+                self.expect(&TokenKind::LBrace, "expected `{`")?;
+                self.sink.begin_graph(self.directed);
+                self.body_start = self.tokens[self.pos - 1].span.end;
+                while !self.eat(&TokenKind::RBrace) {
+                    if self.peek().is_none() {
+                        return self.recover(self.error("expected `}`"));
+                    }
+                    let start = self.pos;
+                    if let Err(error) = self.stmt_and_semicolon() {
+                        self.recover(Err(error))?;
+                        self.skip_stmt(start);
+                        self.eat(&TokenKind::Semicolon);
+                    }
+                }
+                if self.peek().is_some() {
+                    return self.recover(self.error("unexpected input after the graph"));
+                }
+                Ok(())
+            }
+
+            /// In lenient mode, record the error and carry on.
+            fn recover(&mut self, result: Result<(), ParseError>) -> Result<(), ParseError> {
+                match (result, &mut self.diagnostics) {
+                    (Err(error), Some(diagnostics)) if error.limit.is_none() => {
+                        diagnostics.push(error);
+                        Ok(())
+                    }
+                    (result, _) => result,
+                }
+            }
+
+            /// Skip the rest of a statement that failed to parse: up to its `;`, the `}`
+            /// closing the graph, or the next line, whichever comes first outside brackets.
+            fn skip_stmt(&mut self, start: usize) {
+                let mut depth = 0usize;
+                while let Some(kind) = self.peek() {
+                    // Always make progress, even past a token no statement can start with.
+                    if self.pos > start && depth == 0 {
+                        let previous = &self.tokens[self.pos - 1];
+                        let new_line = self.src[previous.span.end..self.offset()].contains('\n');
+                        if new_line || matches!(kind, TokenKind::Semicolon | TokenKind::RBrace) {
+                            return;
+                        }
+                    }
+                    match kind {
+                        TokenKind::LBrace | TokenKind::LBracket => depth += 1,
+                        TokenKind::RBrace | TokenKind::RBracket => depth = depth.saturating_sub(1),
+                        _ => {}
+                    }
+                    self.pos += 1;
+                }
+            }
+
+            /// A statement and its optional `;`, remembering where they were.
+            fn stmt_and_semicolon(&mut self) -> Result<(), ParseError> {
+                let start = self.offset();
+                self.stmt()?;
+                self.eat(&TokenKind::Semicolon);
+                self.spans.push(start..self.tokens[self.pos - 1].span.end);
+                Ok(())
+            }
+
+            fn stmt(&mut self) -> Result<(), ParseError> {
+                match self.peek() {
+                    Some(TokenKind::Keyword(Keyword::Graph)) => {
+                        self.pos += 1;
+                        let attrs = self.attr_lists()?;
+                        self.sink.graph_attrs(&pairs_as_str(&attrs));
+                        Ok(())
+                    }
+                    // Defaults apply to the whole graph, not only to what follows them.
+                    Some(TokenKind::Keyword(Keyword::Node)) => {
+                        self.pos += 1;
+                        let attrs = self.attr_lists()?;
+                        self.sink.node_defaults(&pairs_as_str(&attrs));
+                        Ok(())
+                    }
+                    Some(TokenKind::Keyword(Keyword::Edge)) => {
+                        self.pos += 1;
+                        let attrs = self.attr_lists()?;
+                        self.sink.edge_defaults(&pairs_as_str(&attrs));
+                        Ok(())
+                    }
+                    Some(TokenKind::Keyword(Keyword::Subgraph) | TokenKind::LBrace) => {
+                        self.error("subgraphs are not supported")
+                    }
+                    Some(TokenKind::Id(_)) => {
+                        let start = self.offset();
+                        let first = self.node_id()?;
+                        if self.eat(&TokenKind::Equals) {
+                            let value = self.attr_value()?;
+                            self.sink.graph_attrs(&[(&first, &value)]);
+                            return Ok(());
+                        }
+                        let mut chain = vec![first];
+                        loop {
+                            match (self.peek(), self.directed) {
+                                (Some(TokenKind::UndirectedEdge), false)
+                                | (Some(TokenKind::DirectedEdge), true) => self.pos += 1,
+                                (Some(TokenKind::DirectedEdge), false) => {
+                                    return self.error("`->` is not allowed in a graph")
+                                }
+                                (Some(TokenKind::UndirectedEdge), true) => {
+                                    return self.error("`--` is not allowed in a digraph")
+                                }
+                                _ => break,
+                            }
+                            chain.push(self.node_id()?);
+                        }
+                        let attrs = self.attr_lists()?;
+                        self.count(&chain, start)?;
+                        if chain.len() == 1 {
+                            self.node_stmt(&chain[0], &attrs);
+                        } else {
+                            let attrs = pairs_as_str(&attrs);
+                            for pair in chain.windows(2) {
+                                self.sink
+                                    .edge(Edge::new(&pair[0], &pair[1]).with_attrs(&attrs));
+                            }
+                        }
+                        Ok(())
+                    }
+                    _ => self.error("expected a statement"),
+                }
+            }
+
+            fn node_id(&mut self) -> Result<String, ParseError> {
+                let id = self.id()?;
+                if self.peek() == Some(&TokenKind::Colon) {
+                    return self.error("ports are not supported");
+                }
+                Ok(id)
+            }
+
+            /// Repeated node statements are merged by `Graph::add_node`, like Graphviz does.
+            fn node_stmt(&mut self, name: &str, attrs: &[(String, String)]) {
+                let attrs = pairs_as_str(attrs);
+                self.sink.node(Node::new(name).with_attrs(&attrs));
+            }
+
+            fn attr_value(&mut self) -> Result<String, ParseError> {
+                let start = self.offset();
+                let value = self.id()?;
+                match self.limits.attr_len {
+                    Some(max) if value.len() > max => {
+                        let len = self.offset() - start;
+                        Err(ParseError::limit(
+                            LimitExceeded::AttrLength(max),
+                            start,
+                            len,
+                        ))
+                    }
+                    _ => Ok(value),
+                }
+            }
+
+            /// Count the nodes and the edges of the statement at `start`, a chain of
+            /// node ids, against the limits.
+            fn count(&mut self, chain: &[String], start: usize) -> Result<(), ParseError> {
+                let len = self.tokens[self.pos - 1].span.end - start;
+                if let Some(max) = self.limits.nodes {
+                    self.names.extend(chain.iter().cloned());
+                    if self.names.len() > max {
+                        return Err(ParseError::limit(LimitExceeded::Nodes(max), start, len));
+                    }
+                }
+                self.edge_count += chain.len() - 1;
+                match self.limits.edges {
+                    Some(max) if self.edge_count > max => {
+                        Err(ParseError::limit(LimitExceeded::Edges(max), start, len))
+                    }
+                    _ => Ok(()),
+                }
+            }
+
+            /// `[a=1, b=2][c=3]`, possibly absent.
+            fn attr_lists(&mut self) -> Result<Vec<(String, String)>, ParseError> {
+                let mut attrs = Vec::new();
+                while self.eat(&TokenKind::LBracket) {
+                    while !self.eat(&TokenKind::RBracket) {
+                        let key = self.id()?;
+                        self.expect(&TokenKind::Equals, "expected `=`")?;
+                        let value = self.attr_value()?;
+                        attrs.push((key, value));
+                        if !self.eat(&TokenKind::Comma) {
+                            self.eat(&TokenKind::Semicolon);
+                        }
+                    }
+                }
+                Ok(attrs)
+            }
+        }
     }
 
-    impl Graph {
-        pub fn new() -> Self {
-            Graph {
-                nodes: Vec::new(),
-                edges: Vec::new(),
-                attrs: HashMap::new(),
+    /// A C interface for building graphs, compiled with the `ffi` feature.
+    ///
+    /// Graphs are opaque `DotGraph *` handles from `dot_graph_new` or
+    /// `dot_graph_from_dot`, released with `dot_graph_free`. Strings go in as
+    /// NUL-terminated UTF-8 and come out from `dot_graph_to_dot`, to be released with
+    /// `dot_string_free`. `include/dot_dsl.h` declares everything here; build a
+    /// library for C with `cargo rustc --release --features ffi --crate-type cdylib`.
+    #[cfg(feature = "ffi")]
+    pub mod ffi {
+        use crate::graph::graph_items::edge::Edge;
+        use crate::graph::graph_items::node::Node;
+        use crate::graph::{Change, Graph, GraphError};
+        use std::ffi::{c_char, c_int, CStr, CString};
+        use std::ptr;
+        use std::sync::Arc;
+
+        /// The call succeeded.
+        pub const DOT_OK: c_int = 0;
+        /// A pointer was null or a string wasn't valid UTF-8.
+        pub const DOT_EINVAL: c_int = -1;
+        /// The graph rejected the change, e.g. a duplicate under an `Error` policy.
+        pub const DOT_EGRAPH: c_int = -2;
+        /// The node or edge to set an attribute on doesn't exist.
+        pub const DOT_ENOTFOUND: c_int = -3;
+
+        /// Create an empty graph, directed unless `directed` is false.
+        #[no_mangle]
+        pub extern "C" fn dot_graph_new(directed: bool) -> *mut Graph {
+            Box::into_raw(Box::new(Graph::new().with_directed(directed)))
+        }
+
+        /// Parse `src` as DOT, returning null if it doesn't parse.
+        ///
+        /// # Safety
+        ///
+        /// `src` must be null or a NUL-terminated string.
+        #[no_mangle]
+        pub unsafe extern "C" fn dot_graph_from_dot(src: *const c_char) -> *mut Graph {
+            match str_arg(src).map(Graph::from_dot) {
+                Some(Ok(graph)) => Box::into_raw(Box::new(graph)),
+                _ => ptr::null_mut(),
             }
         }
 
-        pub fn with_nodes(self, nodes: &[Node]) -> Self {
-            // purely functional concatenation
-            let merged_nodes = concat_slices(&self.nodes, nodes);
-            Graph {
-                nodes: merged_nodes,
-                edges: self.edges,
-                attrs: self.attrs,
+        /// Release a graph. Null is ignored.
+        ///
+        /// # Safety
+        ///
+        /// `graph` must be null or a handle not yet freed.
+        #[no_mangle]
+        pub unsafe extern "C" fn dot_graph_free(graph: *mut Graph) {
+            if !graph.is_null() {
+                drop(Box::from_raw(graph));
             }
         }
 
-        pub fn with_edges(self, edges: &[Edge]) -> Self {
-            let merged_edges = concat_slices(&self.edges, edges);
-            Graph {
-                nodes: self.nodes,
-                edges: merged_edges,
-                attrs: self.attrs,
+        /// Add a node, applying the graph's duplicate node policy.
+        ///
+        /// # Safety
+        ///
+        /// `graph` must be null or a live handle, and `name` null or a NUL-terminated
+        /// string.
+        #[no_mangle]
+        pub unsafe extern "C" fn dot_graph_add_node(
+            graph: *mut Graph,
+            name: *const c_char,
+        ) -> c_int {
+            let (Some(graph), Some(name)) = (graph.as_mut(), str_arg(name)) else {
+                return DOT_EINVAL;
+            };
+            status(
+                Node::builder(name.to_string())
+                    .build()
+                    .and_then(|node| graph.add_node(node)),
+            )
+        }
+
+        /// Add an edge, applying the graph's duplicate edge policy.
+        ///
+        /// # Safety
+        ///
+        /// `graph` must be null or a live handle, and `node1` and `node2` null or
+        /// NUL-terminated strings.
+        #[no_mangle]
+        pub unsafe extern "C" fn dot_graph_add_edge(
+            graph: *mut Graph,
+            node1: *const c_char,
+            node2: *const c_char,
+        ) -> c_int {
+            let (Some(graph), Some(node1), Some(node2)) =
+                (graph.as_mut(), str_arg(node1), str_arg(node2))
+            else {
+                return DOT_EINVAL;
+            };
+            status(graph.add_edge(Edge::new(node1, node2)))
+        }
+
+        /// Set a graph attribute.
+        ///
+        /// # Safety
+        ///
+        /// `graph` must be null or a live handle, and `key` and `value` null or
+        /// NUL-terminated strings.
+        #[no_mangle]
+        pub unsafe extern "C" fn dot_graph_set_attr(
+            graph: *mut Graph,
+            key: *const c_char,
+            value: *const c_char,
+        ) -> c_int {
+            let (Some(graph), Some(key), Some(value)) =
+                (graph.as_mut(), str_arg(key), str_arg(value))
+            else {
+                return DOT_EINVAL;
+            };
+            graph.set_attrs(&[(key, value)]);
+            DOT_OK
+        }
+
+        /// Set an attribute on the declared node `name`.
+        ///
+        /// # Safety
+        ///
+        /// `graph` must be null or a live handle, and the strings null or
+        /// NUL-terminated.
+        #[no_mangle]
+        pub unsafe extern "C" fn dot_graph_set_node_attr(
+            graph: *mut Graph,
+            name: *const c_char,
+            key: *const c_char,
+            value: *const c_char,
+        ) -> c_int {
+            let (Some(graph), Some(name), Some(key), Some(value)) =
+                (graph.as_mut(), str_arg(name), str_arg(key), str_arg(value))
+            else {
+                return DOT_EINVAL;
+            };
+            let Some(i) = graph.node_index.get(name).copied() else {
+                return DOT_ENOTFOUND;
+            };
+            let nodes = Arc::make_mut(&mut graph.nodes);
+            let node = std::mem::replace(&mut nodes[i], Node::new(""));
+            nodes[i] = node.with_attrs(&[(key, value)]);
+            graph.notify(Change::NodeUpdated(&graph.nodes[i]));
+            DOT_OK
+        }
+
+        /// Set an attribute on the first edge joining `node1` and `node2`.
+        ///
+        /// # Safety
+        ///
+        /// `graph` must be null or a live handle, and the strings null or
+        /// NUL-terminated.
+        #[no_mangle]
+        pub unsafe extern "C" fn dot_graph_set_edge_attr(
+            graph: *mut Graph,
+            node1: *const c_char,
+            node2: *const c_char,
+            key: *const c_char,
+            value: *const c_char,
+        ) -> c_int {
+            let (Some(graph), Some(node1), Some(node2), Some(key), Some(value)) = (
+                graph.as_mut(),
+                str_arg(node1),
+                str_arg(node2),
+                str_arg(key),
+                str_arg(value),
+            ) else {
+                return DOT_EINVAL;
+            };
+            let Some(i) = graph.find_edge(node1, node2) else {
+                return DOT_ENOTFOUND;
+            };
+            let edges = Arc::make_mut(&mut graph.edges);
+            let edge = std::mem::replace(&mut edges[i], Edge::new("", ""));
+            edges[i] = edge.with_attrs(&[(key, value)]);
+            graph.notify(Change::EdgeUpdated(&graph.edges[i]));
+            DOT_OK
+        }
+
+        /// Render the graph as DOT, returning null if `graph` is null. The string
+        /// belongs to the caller; release it with `dot_string_free`.
+        ///
+        /// # Safety
+        ///
+        /// `graph` must be null or a live handle.
+        #[no_mangle]
+        pub unsafe extern "C" fn dot_graph_to_dot(graph: *const Graph) -> *mut c_char {
+            match graph.as_ref() {
+                // Names and values can't hold NUL: they arrived as C strings.
+                Some(graph) => {
+                    CString::new(graph.to_dot()).map_or(ptr::null_mut(), CString::into_raw)
+                }
+                None => ptr::null_mut(),
             }
         }
 
-        pub fn with_attrs(self, attrs: &[(&str, &str)]) -> Self {
-            let merged_attrs = merge_map_and_list(&self.attrs, attrs);
-            Graph {
-                nodes: self.nodes,
-                edges: self.edges,
-                attrs: merged_attrs,
+        /// Release a string from `dot_graph_to_dot`. Null is ignored.
+        ///
+        /// # Safety
+        ///
+        /// `s` must be null or a string from `dot_graph_to_dot` not yet freed.
+        #[no_mangle]
+        pub unsafe extern "C" fn dot_string_free(s: *mut c_char) {
+            if !s.is_null() {
+                drop(CString::from_raw(s));
             }
         }
 
-        pub fn node(&self, name: &str) -> Option<&Node> {
-            find_node_by_name(&self.nodes, name)
+        /// Borrow a C string argument, or `None` if it is null or not UTF-8.
+        unsafe fn str_arg<'a>(s: *const c_char) -> Option<&'a str> {
+            if s.is_null() {
+                None
+            } else {
+                CStr::from_ptr(s).to_str().ok()
+            }
+        }
+
+        fn status(result: Result<(), GraphError>) -> c_int {
+            match result {
+                Ok(()) => DOT_OK,
+                Err(_) => DOT_EGRAPH,
+            }
         }
     }
 
     // -------------------------------------------------------------------------
-    // HELPER FUNCTIONS BELOW (purely functional merging, recursion, etc.)
+    // HELPER FUNCTIONS BELOW (purely functional merging, etc.)
     // -------------------------------------------------------------------------
 
     /// Merge an existing `HashMap` of `String->String` with a slice of `(&str, &str)`.
@@ -143,75 +8286,302 @@ pub mod graph {
         map: &HashMap<String, String>,
         kvs: &[(&str, &str)],
     ) -> HashMap<String, String> {
-        let map_vec = map
-            .iter()
-            .map(|(k, v)| (k.clone(), v.clone()))
-            .collect::<Vec<_>>();
-        let new_map_from_list = from_kv_list(kvs);
+        merge_into(map.clone(), kvs)
+    }
 
-        merge_two_maps(&map_vec, &new_map_from_list)
+    /// Like `merge_map_and_list`, but consumes the map so only the new pairs cost
+    /// anything. When a key is repeated within `kvs` the first occurrence wins, hence
+    /// the reversed walk.
+    fn merge_into(
+        mut map: HashMap<String, String>,
+        kvs: &[(&str, &str)],
+    ) -> HashMap<String, String> {
+        map.reserve(kvs.len());
+        for (k, v) in kvs.iter().rev() {
+            map.insert(k.to_string(), v.to_string());
+        }
+        map
     }
 
-    /// Recursively build a HashMap<String, String> from a slice of (&str, &str).
-    fn from_kv_list(kvs: &[(&str, &str)]) -> HashMap<String, String> {
-        match kvs.split_first() {
-            None => HashMap::new(),
-            Some(((k, v), tail)) => {
-                // Build the tail map
-                let tail_map = from_kv_list(tail);
-                // Insert/overwrite the current (k, v)
-                merge_two_maps(&[], &insert_single_kv(&tail_map, k, v))
-            }
+    /// Parse an optional attribute value. The error spans the whole value, which is
+    /// what its offset, line and column are relative to.
+    fn parse_attr<T: FromStr>(key: &str, value: Option<&str>) -> Result<Option<T>, ParseError> {
+        value
+            .map(|value| {
+                value.trim().parse().map_err(|_| {
+                    let message = format!("cannot read attribute `{key}`");
+                    ParseError::spanning(&message, 0, value.len()).located(value)
+                })
+            })
+            .transpose()
+    }
+
+    /// Format a float the way DOT expects it (no exponent, no `-0`), rejecting values
+    /// Graphviz would refuse.
+    fn format_non_negative(key: &str, value: f64) -> Result<String, GraphError> {
+        if value.is_finite() && value >= 0.0 {
+            // Rust's `Display` for floats never uses exponent notation, and `+ 0.0`
+            // turns `-0.0` into `0`.
+            Ok(format!("{}", value + 0.0))
+        } else {
+            Err(GraphError::InvalidNumber {
+                key: key.to_string(),
+                value,
+            })
         }
     }
 
-    /// Return a new HashMap = map + (k, v), with (k, v) overwriting if needed.
-    fn insert_single_kv(map: &HashMap<String, String>, k: &str, v: &str) -> HashMap<String, String> {
-        let as_vec = map
+    /// Render comments one per line: `// text` for single lines, `/* ... */` when the
+    /// comment itself spans several lines.
+    fn render_comments(comments: &[String], indent: &str) -> String {
+        comments
             .iter()
-            .map(|(kk, vv)| (kk.clone(), vv.clone()))
-            .collect::<Vec<_>>();
-        let appended = concat_slices(&as_vec, &[(k.to_string(), v.to_string())]);
-        appended.into_iter().collect()
+            .map(|comment| {
+                if comment.contains('\n') {
+                    format!("{indent}/* {} */\n", comment.replace("*/", "* /"))
+                } else {
+                    format!("{indent}// {comment}\n")
+                }
+            })
+            .collect()
     }
 
-    /// Merge two "maps" (represented by a Vec of key/value pairs, and a HashMap).
-    /// On key collisions, the second_map overrides.
-    fn merge_two_maps(
-        first: &[(String, String)],
-        second_map: &HashMap<String, String>,
-    ) -> HashMap<String, String> {
-        let second_vec = second_map
+    /// A string map's entries as borrowed pairs, in no particular order.
+    fn pairs_of(map: &HashMap<String, String>) -> Vec<(&str, &str)> {
+        map.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect()
+    }
+
+    fn render_node(node: &Node) -> String {
+        render_node_at(node, "    ")
+    }
+
+    fn render_node_at(node: &Node, indent: &str) -> String {
+        format!(
+            "{}{indent}{}{}\n",
+            render_comments(node.comments(), indent),
+            quote_id(node.name()),
+            render_attr_list(node.attrs())
+        )
+    }
+
+    /// A subgraph with its members by name, except those in `declared`, which are
+    /// written out in full.
+    fn render_subgraph(subgraph: &Subgraph, declared: &[&Node]) -> String {
+        let mut attrs = String::new();
+        if subgraph.attrs().next().is_some() {
+            attrs += &format!("        graph{}\n", render_attr_list(subgraph.attrs()));
+        }
+        if subgraph.node_defaults().next().is_some() {
+            attrs += &format!(
+                "        node{}\n",
+                render_attr_list(subgraph.node_defaults())
+            );
+        }
+        let members = subgraph
+            .nodes()
             .iter()
-            .map(|(k, v)| (k.clone(), v.clone()))
+            .map(
+                |name| match declared.iter().find(|node| node.name() == name) {
+                    Some(node) => render_node_at(node, "        "),
+                    None => format!("        {}\n", quote_id(name)),
+                },
+            )
+            .collect::<String>();
+        format!(
+            "    subgraph {} {{\n{attrs}{members}    }}\n",
+            quote_id(subgraph.name())
+        )
+    }
+
+    fn render_edge(edge: &Edge, directed: bool) -> String {
+        let edge_op = if directed { "->" } else { "--" };
+        format!(
+            "{}    {} {edge_op} {}{}\n",
+            render_comments(edge.comments(), "    "),
+            quote_id(edge.node1()),
+            quote_id(edge.node2()),
+            render_attr_list(edge.attrs())
+        )
+    }
+
+    /// Render ` [k1="v1", k2="v2"]` sorted by key, or nothing for an empty list.
+    fn render_attr_list<'a, K, V>(attrs: impl Iterator<Item = (K, V)>) -> String
+    where
+        K: AsRef<str> + 'a,
+        V: AsRef<str> + 'a,
+    {
+        let mut pairs = attrs
+            .map(|(k, v)| format!("{}={}", quote_id(k.as_ref()), quote_string(v.as_ref())))
             .collect::<Vec<_>>();
+        pairs.sort();
 
-        let combined = concat_slices(first, &second_vec);
-        combined.into_iter().collect()
+        if pairs.is_empty() {
+            String::new()
+        } else {
+            format!(" [{}]", pairs.join(", "))
+        }
     }
 
-    /// Purely functional concatenation with recursion (no mutation).
-    fn concat_slices<T: Clone>(a: &[T], b: &[T]) -> Vec<T> {
-        match a.split_first() {
-            None => b.to_vec(),
-            Some((head, tail)) => {
-                let tail_concat = concat_slices(tail, b);
-                [vec![head.clone()].as_slice(), &tail_concat].concat()
-            }
+    /// Render `[k1=v1,k2=v2]` sorted by key, quoting only what [`quote_id`] must, or
+    /// nothing for an empty list.
+    fn compact_attr_list<'a, K, V>(attrs: impl Iterator<Item = (K, V)>) -> String
+    where
+        K: AsRef<str> + 'a,
+        V: AsRef<str> + 'a,
+    {
+        let mut pairs = attrs
+            .map(|(k, v)| format!("{}={}", quote_id(k.as_ref()), quote_id(v.as_ref())))
+            .collect::<Vec<_>>();
+        pairs.sort();
+
+        if pairs.is_empty() {
+            String::new()
+        } else {
+            format!("[{}]", pairs.join(","))
         }
     }
 
-    /// Recursively find a `Node` by name, returning the first match or None.
-    fn find_node_by_name<'a>(nodes: &'a [Node], name: &str) -> Option<&'a Node> {
-        match nodes.split_first() {
-            None => None,
-            Some((head, tail)) => {
-                if head.name() == name {
-                    Some(head)
-                } else {
-                    find_node_by_name(tail, name)
+    /// Leave plain identifiers and numerals bare, quote everything else, including
+    /// the keywords (`graph`, `node`, ...) which would otherwise change the meaning.
+    fn quote_id(id: &str) -> String {
+        if (is_plain_id(id) && !dot_lexer::is_keyword(id)) || is_numeral(id) {
+            id.to_string()
+        } else {
+            quote_string(id)
+        }
+    }
+
+    /// Wrap in double quotes, escaping embedded quotes. Other backslash sequences
+    /// (`\n`, `\l`, ...) are meaningful to Graphviz and are kept as they are, except a
+    /// trailing backslash which would otherwise escape the closing quote.
+    fn quote_string(s: &str) -> String {
+        let escaped = s.replace('"', "\\\"");
+        let trailing = s.chars().rev().take_while(|&c| c == '\\').count();
+        if trailing % 2 == 1 {
+            format!("\"{escaped}\\\"")
+        } else {
+            format!("\"{escaped}\"")
+        }
+    }
+
+    /// Replace every char outside `[A-Za-z0-9_]` with `_`, and prefix `_` when the result
+    /// would start with a digit, be empty or be a keyword.
+    fn sanitize_id(id: &str) -> String {
+        let replaced = id
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect::<String>();
+        let needs_prefix = replaced.is_empty()
+            || replaced.starts_with(|c: char| c.is_ascii_digit())
+            || dot_lexer::is_keyword(&replaced);
+        if needs_prefix {
+            format!("_{replaced}")
+        } else {
+            replaced
+        }
+    }
+
+    /// `[a-zA-Z_][a-zA-Z0-9_]*`
+    fn is_plain_id(id: &str) -> bool {
+        id.chars()
+            .next()
+            .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+            && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    }
+
+    /// `-?(.[0-9]+|[0-9]+(.[0-9]*)?)`
+    fn is_numeral(id: &str) -> bool {
+        let digits = id.strip_prefix('-').unwrap_or(id);
+        let (int, frac) = digits.split_once('.').unwrap_or((digits, ""));
+        let all_digits = |part: &str| part.chars().all(|c| c.is_ascii_digit());
+
+        (!int.is_empty() || !frac.is_empty())
+            && all_digits(int)
+            && all_digits(frac)
+            && (digits.contains('.') || !int.is_empty())
+    }
+
+    /// Append `styles` to an existing comma-separated style list, skipping duplicates.
+    fn join_styles(existing: Option<&str>, styles: &[Style]) -> String {
+        let joined = styles
+            .iter()
+            .fold(split_styles(existing), |mut joined, style| {
+                if !joined.contains(style) {
+                    joined.push(style.clone());
                 }
+                joined
+            });
+
+        joined
+            .iter()
+            .map(|style| style.as_str())
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    /// Split a `style` value on the commas that are not inside parentheses,
+    /// so `setlinewidth(1,2)` stays a single entry.
+    fn split_styles(value: Option<&str>) -> Vec<Style> {
+        let (mut parts, last, _) = value.unwrap_or("").chars().fold(
+            (Vec::new(), String::new(), 0usize),
+            |(mut parts, mut current, depth), c| {
+                let depth = match c {
+                    ',' if depth == 0 => {
+                        parts.push(std::mem::take(&mut current));
+                        return (parts, current, depth);
+                    }
+                    '(' => depth + 1,
+                    ')' => depth.saturating_sub(1),
+                    _ => depth,
+                };
+                current.push(c);
+                (parts, current, depth)
+            },
+        );
+        parts.push(last);
+
+        parts
+            .iter()
+            .filter(|part| !part.trim().is_empty())
+            .map(|part| part.parse().unwrap_or_else(|never| match never {}))
+            .collect()
+    }
+
+    /// Map every node name to its position in `nodes`.
+    fn index_by_name(nodes: &[Node]) -> HashMap<String, usize> {
+        nodes
+            .iter()
+            .enumerate()
+            .map(|(i, node)| (node.name().to_string(), i))
+            .collect()
+    }
+
+    /// A tiny, fast generator; good enough for layouts and fuzzing, not for secrets.
+    struct SplitMix64(u64);
+
+    impl SplitMix64 {
+        fn next_u64(&mut self) -> u64 {
+            self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+            let mut z = self.0;
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+            z ^ (z >> 31)
+        }
+
+        /// Uniform in `[0, 1)`.
+        fn next_f64(&mut self) -> f64 {
+            (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+        }
+
+        fn below(&mut self, n: usize) -> usize {
+            (self.next_u64() % n as u64) as usize
+        }
+
+        /// Fisher–Yates, so every order is equally likely.
+        fn shuffle<T>(&mut self, items: &mut [T]) {
+            for i in (1..items.len()).rev() {
+                items.swap(i, self.below(i + 1));
             }
         }
     }
-}
\ No newline at end of file
+}
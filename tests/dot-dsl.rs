@@ -1,16 +1,36 @@
 use std::collections::HashMap;
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::Duration;
 
+use dot_dsl::graph::binary::DecodeError;
+use dot_dsl::graph::centrality;
+use dot_dsl::graph::dot_lexer::{self, LexError, TokenKind};
+use dot_dsl::graph::generators;
 use dot_dsl::graph::graph_items::edge::Edge;
+use dot_dsl::graph::graph_items::esc_string::{EscString, Justify};
+use dot_dsl::graph::graph_items::label::Label;
 use dot_dsl::graph::graph_items::node::Node;
-use dot_dsl::graph::Graph;
+use dot_dsl::graph::graph_items::style::Style;
+use dot_dsl::graph::graph_items::subgraph::Subgraph;
+use dot_dsl::graph::parser::{self, Document, Event, LimitExceeded, ParseOptions};
+use dot_dsl::graph::patch::{GraphPatch, PatchOp};
+use dot_dsl::graph::query::Query;
+use dot_dsl::graph::stylesheet::Stylesheet;
+use dot_dsl::graph::template::GraphTemplate;
+use dot_dsl::graph::themes::Theme;
+use dot_dsl::graph::{
+    AttrDiff, Cascade, Change, DotOptions, DotWriter, DuplicateEdges, DuplicateNodes, Graph,
+    GraphAttrStyle, GraphError, GraphStats, GraphVisitor, HistoryGraph, ImplicitNodes, Lint, Match,
+    SharedGraph,
+};
 
 #[test]
 fn empty_graph() {
     let graph = Graph::new();
 
-    assert!(graph.nodes.is_empty());
+    assert!(graph.nodes().is_empty());
 
-    assert!(graph.edges.is_empty());
+    assert!(graph.edges().is_empty());
 
     assert!(graph.attrs.is_empty());
 }
@@ -21,11 +41,11 @@ fn graph_with_one_node() {
 
     let graph = Graph::new().with_nodes(&nodes);
 
-    assert!(graph.edges.is_empty());
+    assert!(graph.edges().is_empty());
 
     assert!(graph.attrs.is_empty());
 
-    assert_eq!(graph.nodes, vec![Node::new("a")]);
+    assert_eq!(graph.nodes(), vec![Node::new("a")]);
 }
 
 #[test]
@@ -34,12 +54,12 @@ fn graph_with_one_node_with_keywords() {
 
     let graph = Graph::new().with_nodes(&nodes);
 
-    assert!(graph.edges.is_empty());
+    assert!(graph.edges().is_empty());
 
     assert!(graph.attrs.is_empty());
 
     assert_eq!(
-        graph.nodes,
+        graph.nodes(),
         vec![Node::new("a").with_attrs(&[("color", "green")])]
     );
 }
@@ -50,11 +70,11 @@ fn graph_with_one_edge() {
 
     let graph = Graph::new().with_edges(&edges);
 
-    assert!(graph.nodes.is_empty());
+    assert!(graph.nodes().is_empty());
 
     assert!(graph.attrs.is_empty());
 
-    assert_eq!(graph.edges, vec![Edge::new("a", "b")]);
+    assert_eq!(graph.edges(), vec![Edge::new("a", "b")]);
 }
 
 #[test]
@@ -63,12 +83,12 @@ fn graph_with_one_edge_with_keywords() {
 
     let graph = Graph::new().with_edges(&edges);
 
-    assert!(graph.nodes.is_empty());
+    assert!(graph.nodes().is_empty());
 
     assert!(graph.attrs.is_empty());
 
     assert_eq!(
-        graph.edges,
+        graph.edges(),
         vec![Edge::new("a", "b").with_attrs(&[("color", "blue")])]
     );
 }
@@ -79,9 +99,9 @@ fn graph_with_one_attribute() {
 
     let expected_attrs = HashMap::from([("foo".to_string(), "1".to_string())]);
 
-    assert!(graph.nodes.is_empty());
+    assert!(graph.nodes().is_empty());
 
-    assert!(graph.edges.is_empty());
+    assert!(graph.edges().is_empty());
 
     assert_eq!(graph.attrs, expected_attrs);
 }
@@ -113,7 +133,7 @@ fn graph_with_attributes() {
         .with_attrs(&attrs);
 
     assert_eq!(
-        graph.nodes,
+        graph.nodes(),
         vec![
             Node::new("a").with_attrs(&[("color", "green")]),
             Node::new("c"),
@@ -122,7 +142,7 @@ fn graph_with_attributes() {
     );
 
     assert_eq!(
-        graph.edges,
+        graph.edges(),
         vec![
             Edge::new("b", "c"),
             Edge::new("a", "b").with_attrs(&[("color", "blue")]),
@@ -153,19 +173,19 @@ fn edges_store_attributes() {
         .with_attrs(&attrs);
 
     assert_eq!(
-        graph.edges,
+        graph.edges(),
         vec![
             Edge::new("b", "c"),
             Edge::new("a", "b").with_attrs(&[("color", "blue"), ("fill", "darkblue")]),
         ]
     );
 
-    assert_eq!(graph.edges[1].attr("color"), Some("blue"));
-    assert_eq!(graph.edges[1].attr("fill"), Some("darkblue"));
-    assert_eq!(graph.edges[1].attr("foo"), None);
-    assert_eq!(graph.edges[0].attr("color"), None);
-    assert_eq!(graph.edges[0].attr("fill"), None);
-    assert_eq!(graph.edges[0].attr("foo"), None);
+    assert_eq!(graph.edges()[1].attr("color"), Some("blue"));
+    assert_eq!(graph.edges()[1].attr("fill"), Some("darkblue"));
+    assert_eq!(graph.edges()[1].attr("foo"), None);
+    assert_eq!(graph.edges()[0].attr("color"), None);
+    assert_eq!(graph.edges()[0].attr("fill"), None);
+    assert_eq!(graph.edges()[0].attr("foo"), None);
 }
 
 #[test]
@@ -194,3 +214,3323 @@ fn graph_nodes_store_attributes() {
     assert_eq!(c.attr("bat"), None);
     assert_eq!(c.attr("bim"), Some("bef"));
 }
+
+#[test]
+fn node_styles_accumulate() {
+    let node = Node::new("a").with_styles(&[Style::Filled]).with_styles(&[
+        Style::Dashed,
+        Style::Filled,
+        Style::Bold,
+    ]);
+
+    assert_eq!(node.attr("style"), Some("filled,dashed,bold"));
+
+    assert_eq!(
+        node.styles(),
+        vec![Style::Filled, Style::Dashed, Style::Bold]
+    );
+}
+
+#[test]
+fn edge_styles_split_existing_list() {
+    let edge = Edge::new("a", "b").with_attrs(&[("style", "setlinewidth(1,2), dotted")]);
+
+    assert_eq!(
+        edge.styles(),
+        vec![Style::Other("setlinewidth(1,2)".to_string()), Style::Dotted]
+    );
+
+    assert_eq!(Edge::new("a", "b").styles(), vec![]);
+}
+
+#[test]
+fn numeric_setters_format_values() {
+    let edge = Edge::new("a", "b")
+        .with_penwidth(2.0)
+        .and_then(|edge| edge.with_weight(0.0000125))
+        .expect("values are valid");
+
+    assert_eq!(edge.attr("penwidth"), Some("2"));
+
+    assert_eq!(edge.attr("weight"), Some("0.0000125"));
+
+    let node = Node::new("a").with_fontsize(10.5).expect("value is valid");
+
+    assert_eq!(node.attr("fontsize"), Some("10.5"));
+}
+
+#[test]
+fn numeric_setters_reject_invalid_values() {
+    assert_eq!(
+        Edge::new("a", "b").with_weight(-1.0),
+        Err(GraphError::InvalidNumber {
+            key: "weight".to_string(),
+            value: -1.0,
+        })
+    );
+
+    assert!(Node::new("a").with_penwidth(f64::NAN).is_err());
+
+    assert!(Graph::new().with_ranksep(f64::INFINITY).is_err());
+}
+
+#[test]
+fn graph_renders_as_dot() {
+    let graph = Graph::new()
+        .with_nodes(&[
+            Node::new("a").with_attrs(&[("color", "red")]),
+            Node::new("b").with_attrs(&[("label", "Say \"hi\""), ("color", "blue")]),
+        ])
+        .with_edges(&[Edge::new("a", "b").with_attrs(&[("color", "green")])])
+        .with_attrs(&[("bgcolor", "yellow")]);
+
+    assert_eq!(
+        graph.to_dot(),
+        concat!(
+            "graph {\n",
+            "    graph [bgcolor=\"yellow\"]\n",
+            "    a [color=\"red\"]\n",
+            "    b [color=\"blue\", label=\"Say \\\"hi\\\"\"]\n",
+            "    a -- b [color=\"green\"]\n",
+            "}\n",
+        )
+    );
+}
+
+#[test]
+fn comments_are_emitted_next_to_statements() {
+    let graph = Graph::new()
+        .with_comment("generated by dot-dsl 0.1.0")
+        .with_nodes(&[Node::new("a").with_comment("record 42")])
+        .with_edges(&[Edge::new("a", "b").with_comment("first line\nsecond line */ end")]);
+
+    assert_eq!(
+        graph.to_string(),
+        concat!(
+            "// generated by dot-dsl 0.1.0\n",
+            "graph {\n",
+            "    // record 42\n",
+            "    a\n",
+            "    /* first line\nsecond line * / end */\n",
+            "    a -- b\n",
+            "}\n",
+        )
+    );
+}
+
+#[test]
+fn parse_readme_example() {
+    let graph = Graph::from_dot(
+        r#"
+        graph {
+            graph [bgcolor="yellow"]
+            a [color="red"]
+            b [color="blue"]
+            a -- b [color="green"]
+        }
+        "#,
+    )
+    .expect("valid DOT");
+
+    assert_eq!(
+        graph.nodes(),
+        vec![
+            Node::new("a").with_attrs(&[("color", "red")]),
+            Node::new("b").with_attrs(&[("color", "blue")]),
+        ]
+    );
+
+    assert_eq!(
+        graph.edges(),
+        vec![Edge::new("a", "b").with_attrs(&[("color", "green")])]
+    );
+
+    assert_eq!(
+        graph.attrs.get("bgcolor").map(|s| s.as_str()),
+        Some("yellow")
+    );
+}
+
+#[test]
+fn keyword_node_names_are_quoted_and_parsed() {
+    let graph = Graph::new()
+        .with_nodes(&[Node::new("graph"), Node::new("Node"), Node::new("subgraph")])
+        .with_edges(&[Edge::new("edge", "graph")]);
+
+    let dot = graph.to_dot();
+
+    assert!(dot.contains("    \"graph\"\n"));
+    assert!(dot.contains("    \"Node\"\n"));
+    assert!(dot.contains("    \"edge\" -- \"graph\"\n"));
+
+    assert_eq!(Graph::from_dot(&dot), Ok(graph));
+}
+
+#[test]
+fn unquoted_keyword_is_not_a_node_name() {
+    let error = Graph::from_dot("graph { a -- node }").unwrap_err();
+
+    assert_eq!(error.offset(), 13);
+}
+
+#[test]
+fn unicode_identifiers_round_trip() {
+    let graph = Graph::new()
+        .with_nodes(&[
+            Node::new("café").with_attrs(&[("label", "naïve")]),
+            Node::new("🦀"),
+            Node::new("שלום"),
+        ])
+        .with_edges(&[Edge::new("🦀", "שלום")]);
+
+    let dot = graph.to_dot();
+
+    assert!(dot.contains("    \"café\" [label=\"naïve\"]\n"));
+    assert!(dot.contains("    \"🦀\" -- \"שלום\"\n"));
+
+    assert_eq!(Graph::from_dot(&dot), Ok(graph));
+
+    let bare = Graph::from_dot("graph { café -- 東京 }").expect("non-ASCII is an ID char");
+
+    assert_eq!(bare.edges(), vec![Edge::new("café", "東京")]);
+}
+
+#[test]
+fn sanitize_ids_is_reversible() {
+    let graph = Graph::new()
+        .with_nodes(&[Node::new("my node"), Node::new("my-node"), Node::new("1st")])
+        .with_edges(&[Edge::new("my node", "édge")]);
+
+    let (sanitized, ids) = graph.sanitize_ids();
+
+    assert_eq!(
+        sanitized.nodes(),
+        vec![
+            Node::new("my_node"),
+            Node::new("my_node_2"),
+            Node::new("_1st")
+        ]
+    );
+
+    assert_eq!(sanitized.edges(), vec![Edge::new("my_node", "_dge")]);
+
+    assert_eq!(ids.original("my_node_2"), Some("my-node"));
+
+    assert_eq!(ids.sanitized("édge"), Some("_dge"));
+
+    assert_eq!(ids.len(), 4);
+}
+
+#[test]
+fn duplicate_nodes_are_merged_by_default() {
+    let graph = Graph::new().with_nodes(&[
+        Node::new("a").with_attrs(&[("color", "green"), ("shape", "box")]),
+        Node::new("b"),
+        Node::new("a").with_attrs(&[("color", "red")]),
+    ]);
+
+    assert_eq!(
+        graph.nodes(),
+        vec![
+            Node::new("a").with_attrs(&[("color", "red"), ("shape", "box")]),
+            Node::new("b"),
+        ]
+    );
+}
+
+#[test]
+fn duplicate_node_policies() {
+    let nodes = [
+        Node::new("a").with_attrs(&[("color", "green")]),
+        Node::new("a").with_attrs(&[("color", "red")]),
+    ];
+
+    let keep_first = Graph::new()
+        .with_duplicate_nodes(DuplicateNodes::KeepFirst)
+        .with_nodes(&nodes);
+
+    assert_eq!(keep_first.nodes(), vec![nodes[0].clone()]);
+
+    let strict = Graph::new()
+        .with_duplicate_nodes(DuplicateNodes::Error)
+        .try_with_nodes(&nodes);
+
+    assert_eq!(strict, Err(GraphError::DuplicateNode("a".to_string())));
+
+    let mut graph = Graph::new().with_duplicate_nodes(DuplicateNodes::Error);
+
+    assert_eq!(graph.add_node(Node::new("b")), Ok(()));
+    assert!(graph.add_node(Node::new("b")).is_err());
+    assert_eq!(graph.nodes(), vec![Node::new("b")]);
+}
+
+#[test]
+fn duplicate_edges_are_kept_by_default() {
+    let graph = Graph::new().with_edges(&[Edge::new("a", "b"), Edge::new("a", "b")]);
+
+    assert_eq!(
+        graph.edges(),
+        vec![Edge::new("a", "b"), Edge::new("a", "b")]
+    );
+}
+
+#[test]
+fn duplicate_edge_policies() {
+    let edges = [
+        Edge::new("a", "b").with_attrs(&[("color", "blue")]),
+        Edge::new("b", "c"),
+        Edge::new("b", "a").with_attrs(&[("label", "again")]),
+    ];
+
+    let merged = Graph::new()
+        .with_duplicate_edges(DuplicateEdges::Merge)
+        .with_edges(&edges);
+
+    assert_eq!(
+        merged.edges(),
+        vec![
+            Edge::new("a", "b").with_attrs(&[("color", "blue"), ("label", "again")]),
+            Edge::new("b", "c"),
+        ]
+    );
+
+    let strict = Graph::new()
+        .with_duplicate_edges(DuplicateEdges::Error)
+        .try_with_edges(&edges);
+
+    assert_eq!(
+        strict,
+        Err(GraphError::DuplicateEdge("b".to_string(), "a".to_string()))
+    );
+}
+
+#[test]
+fn node_lookup_on_large_graph() {
+    let names = (0..200_000).map(|i| format!("n{i}")).collect::<Vec<_>>();
+    let nodes = names.iter().map(|name| Node::new(name)).collect::<Vec<_>>();
+
+    let graph = Graph::new().with_nodes(&nodes);
+
+    assert!(graph.contains_node("n199999"));
+    assert!(!graph.contains_node("n200000"));
+
+    assert_eq!(
+        graph.node("n123456").map(|node| node.name()),
+        Some("n123456")
+    );
+}
+
+#[test]
+fn traversal_of_undirected_graph() {
+    let graph = Graph::new().with_edges(&[
+        Edge::new("a", "b"),
+        Edge::new("c", "a"),
+        Edge::new("b", "d"),
+        Edge::new("a", "b"),
+        Edge::new("d", "e"),
+    ]);
+
+    assert_eq!(graph.neighbors("a").collect::<Vec<_>>(), vec!["b", "c"]);
+
+    assert_eq!(graph.bfs("a"), vec!["a", "b", "c", "d", "e"]);
+
+    assert_eq!(graph.dfs("c"), vec!["c", "a", "b", "d", "e"]);
+
+    assert_eq!(
+        graph.shortest_path("c", "e"),
+        Some(vec!["c", "a", "b", "d", "e"])
+    );
+
+    assert_eq!(graph.shortest_path("a", "z"), None);
+}
+
+#[test]
+fn directed_graph_follows_edge_direction() {
+    let graph = Graph::new().with_directed(true).with_edges(&[
+        Edge::new("a", "b"),
+        Edge::new("b", "c"),
+        Edge::new("d", "b"),
+    ]);
+
+    assert_eq!(graph.bfs("b"), vec!["b", "c"]);
+
+    assert_eq!(
+        graph.neighbors("b").collect::<Vec<_>>(),
+        vec!["c", "a", "d"]
+    );
+
+    assert_eq!(graph.shortest_path("c", "a"), None);
+
+    let dot = graph.to_dot();
+
+    assert_eq!(dot, "digraph {\n    a -> b\n    b -> c\n    d -> b\n}\n");
+
+    assert_eq!(Graph::from_dot(&dot), Ok(graph));
+
+    assert!(Graph::from_dot("digraph { a -- b }").is_err());
+}
+
+#[test]
+fn long_attribute_lists_do_not_overflow_the_stack() {
+    let keys = (0..100_000).map(|i| format!("k{i}")).collect::<Vec<_>>();
+    let attrs = keys.iter().map(|k| (k.as_str(), "v")).collect::<Vec<_>>();
+
+    let node = Node::new("a").with_attrs(&attrs);
+
+    assert_eq!(node.attr("k99999"), Some("v"));
+
+    assert_eq!(node.attrs().count(), 100_000);
+}
+
+#[test]
+fn incremental_building_scales_linearly() {
+    let mut graph = Graph::new().with_duplicate_edges(DuplicateEdges::Merge);
+    for i in 0..100_000 {
+        let node1 = format!("n{}", i % 1000);
+        let node2 = format!("n{}", (i * 7) % 1000);
+        let weight = i.to_string();
+        graph
+            .add_edge(Edge::new(&node1, &node2).with_attrs(&[("weight", &weight)]))
+            .expect("merging never fails");
+    }
+
+    assert!(graph.edges().len() <= 1000);
+
+    let many = (0..100_000).fold(Graph::new(), |graph, i| {
+        graph.with_edges(&[Edge::new("a", &i.to_string())])
+    });
+
+    assert_eq!(many.edges().len(), 100_000);
+}
+
+#[test]
+fn shared_attribute_keys_keep_independent_values() {
+    let nodes = (0..1000)
+        .map(|i| Node::new(&format!("n{i}")).with_attrs(&[("label", &i.to_string())]))
+        .collect::<Vec<_>>();
+
+    let graph = Graph::new().with_nodes(&nodes);
+
+    assert_eq!(
+        graph.node("n7").and_then(|node| node.attr("label")),
+        Some("7")
+    );
+    assert_eq!(
+        graph.node("n999").and_then(|node| node.attr("label")),
+        Some("999")
+    );
+}
+
+#[test]
+fn attribute_equality_ignores_order_and_size() {
+    assert_eq!(
+        Node::new("a").with_attrs(&[("color", "red"), ("shape", "box")]),
+        Node::new("a").with_attrs(&[("shape", "box"), ("color", "red")])
+    );
+
+    let keys = (0..12).map(|i| format!("k{i}")).collect::<Vec<_>>();
+    let forward = keys.iter().map(|k| (k.as_str(), "v")).collect::<Vec<_>>();
+    let backward = forward.iter().rev().copied().collect::<Vec<_>>();
+
+    let edge = Edge::new("a", "b").with_attrs(&forward);
+
+    assert_eq!(edge, Edge::new("a", "b").with_attrs(&backward));
+
+    assert_eq!(edge.attr("k0"), Some("v"));
+    assert_eq!(edge.attr("k11"), Some("v"));
+    assert_eq!(edge.attrs().count(), 12);
+}
+
+#[test]
+fn cow_constructors_accept_literals_and_owned_strings() {
+    let id = 7;
+
+    let node = Node::named(format!("n{id}"))
+        .with_attr("shape", "box")
+        .with_attr(String::from("label"), format!("Node {id}"));
+
+    assert_eq!(
+        node,
+        Node::new("n7").with_attrs(&[("shape", "box"), ("label", "Node 7")])
+    );
+
+    let edge = Edge::between("n7", format!("n{}", id + 1)).with_attr("color", "red");
+
+    assert_eq!(edge, Edge::new("n7", "n8").with_attrs(&[("color", "red")]));
+
+    assert_eq!(edge.with_attr("color", "blue").attr("color"), Some("blue"));
+}
+
+#[test]
+fn clones_share_storage_until_modified() {
+    let original = Graph::new()
+        .with_nodes(&[Node::new("a"), Node::new("b")])
+        .with_edges(&[Edge::new("a", "b")]);
+
+    let copy = original.clone();
+
+    assert_eq!(copy, original);
+    assert_eq!(copy.nodes().as_ptr(), original.nodes().as_ptr());
+
+    let modified = copy.with_nodes(&[Node::new("c")]);
+
+    assert_eq!(original.nodes(), vec![Node::new("a"), Node::new("b")]);
+    assert_eq!(modified.nodes().len(), 3);
+
+    assert_eq!(modified.edges().as_ptr(), original.edges().as_ptr());
+}
+
+#[test]
+fn graph_with_capacity() {
+    let graph = Graph::with_capacity(100, 250);
+
+    let (nodes, edges) = graph.capacity();
+
+    assert!(nodes >= 100);
+    assert!(edges >= 250);
+
+    let graph = graph.with_nodes(&[Node::new("a")]);
+
+    assert_eq!(graph.nodes(), vec![Node::new("a")]);
+    assert!(graph.capacity().0 >= 100);
+}
+
+#[test]
+fn fluent_edge_builder() {
+    let edge = Edge::from("a")
+        .to("b")
+        .directed()
+        .with_attr("color", "red")
+        .with_styles(&[Style::Dashed])
+        .with_styles(&[Style::Bold])
+        .build();
+
+    let expected = Edge::new("a", "b").with_attrs(&[
+        ("dir", "forward"),
+        ("color", "red"),
+        ("style", "dashed,bold"),
+    ]);
+
+    assert_eq!(edge, expected);
+
+    assert_eq!(Edge::from("a").to("b").build(), Edge::new("a", "b"));
+
+    assert_eq!(
+        Edge::from("a").to("b").undirected().build().attr("dir"),
+        Some("none")
+    );
+}
+
+#[test]
+fn node_builder_validates() {
+    let node = Node::builder("a")
+        .with_attr("color", "red")
+        .with_fontsize(12.0)
+        .build();
+
+    assert_eq!(
+        node,
+        Ok(Node::new("a").with_attrs(&[("color", "red"), ("fontsize", "12")]))
+    );
+
+    assert_eq!(
+        Node::builder("").build(),
+        Err(GraphError::InvalidName(String::new()))
+    );
+
+    assert_eq!(
+        Node::builder("a\u{0}b").build(),
+        Err(GraphError::InvalidName("a\u{0}b".to_string()))
+    );
+
+    assert_eq!(
+        Node::builder("a").with_width(-1.0).with_height(2.0).build(),
+        Err(GraphError::InvalidNumber {
+            key: "width".to_string(),
+            value: -1.0
+        })
+    );
+}
+
+struct Tree {
+    label: &'static str,
+    children: Vec<Tree>,
+}
+
+fn leaf(label: &'static str) -> Tree {
+    Tree {
+        label,
+        children: Vec::new(),
+    }
+}
+
+#[test]
+fn graph_from_tree() {
+    let tree = Tree {
+        label: "root",
+        children: vec![
+            Tree {
+                label: "x",
+                children: vec![leaf("y")],
+            },
+            leaf("x"),
+        ],
+    };
+
+    let graph = Graph::from_tree(&tree, |t| &t.children, |t| t.label.to_string());
+
+    assert!(graph.is_directed());
+
+    assert_eq!(
+        graph.nodes(),
+        vec![
+            Node::new("n0").with_attrs(&[("label", "root")]),
+            Node::new("n1").with_attrs(&[("label", "x")]),
+            Node::new("n2").with_attrs(&[("label", "y")]),
+            Node::new("n3").with_attrs(&[("label", "x")]),
+        ]
+    );
+
+    assert_eq!(
+        graph.edges(),
+        vec![
+            Edge::new("n0", "n1"),
+            Edge::new("n1", "n2"),
+            Edge::new("n0", "n3"),
+        ]
+    );
+}
+
+#[test]
+fn classic_generators() {
+    assert_eq!(
+        generators::path(3).edges(),
+        vec![Edge::new("0", "1"), Edge::new("1", "2")]
+    );
+
+    assert_eq!(generators::cycle(4).edges().len(), 4);
+    assert_eq!(generators::cycle(2).edges().len(), 1);
+
+    let complete = generators::complete(5);
+
+    assert_eq!(complete.nodes().len(), 5);
+    assert_eq!(complete.edges().len(), 10);
+
+    assert_eq!(
+        generators::star(3).edges(),
+        vec![Edge::new("0", "1"), Edge::new("0", "2")]
+    );
+
+    let bipartite = generators::complete_bipartite(2, 3);
+
+    assert_eq!(bipartite.nodes().len(), 5);
+    assert_eq!(bipartite.edges().len(), 6);
+    assert_eq!(
+        bipartite.neighbors("0").collect::<Vec<_>>(),
+        vec!["2", "3", "4"]
+    );
+}
+
+#[test]
+fn seeded_random_generators() {
+    assert_eq!(generators::gnp(20, 0.3, 7), generators::gnp(20, 0.3, 7));
+    assert_eq!(generators::gnp(10, 0.0, 7).edges().len(), 0);
+    assert_eq!(generators::gnp(10, 1.0, 7).edges().len(), 45);
+
+    let graph = generators::barabasi_albert(30, 2, 42);
+
+    assert_eq!(graph, generators::barabasi_albert(30, 2, 42));
+    assert_eq!(graph.nodes().len(), 30);
+    assert_eq!(graph.edges().len(), 28 * 2);
+}
+
+#[test]
+fn grid_generator() {
+    let graph = generators::grid(2, 3);
+
+    assert_eq!(graph.nodes().len(), 6);
+    assert_eq!(graph.edges().len(), 7);
+    assert_eq!(
+        graph.neighbors("4").collect::<Vec<_>>(),
+        vec!["5", "1", "3"]
+    );
+
+    let graph = generators::Grid::new(2, 3)
+        .with_diagonals(true)
+        .with_positions(true)
+        .build();
+
+    assert_eq!(graph.edges().len(), 11);
+    assert_eq!(graph.node("0").unwrap().attr("pos"), Some("0,1!"));
+    assert_eq!(graph.node("5").unwrap().attr("pos"), Some("2,0!"));
+}
+
+#[test]
+fn lenient_parsing_skips_bad_statements() {
+    let src =
+        "graph {\n    a -- b\n    c -- [color=red]\n    node [shape=box]; d\n    e -- f @\n    g\n";
+
+    assert!(Graph::from_dot(src).is_err());
+
+    let (graph, diagnostics) =
+        parser::parse_with(src, &ParseOptions::new().with_lenient(true)).unwrap();
+
+    assert_eq!(graph.nodes(), vec![Node::new("d"), Node::new("g")]);
+    assert_eq!(graph.node_defaults()["shape"], "box");
+    assert_eq!(
+        graph.edges(),
+        vec![Edge::new("a", "b"), Edge::new("e", "f")]
+    );
+
+    let messages = diagnostics
+        .iter()
+        .map(|diagnostic| diagnostic.message())
+        .collect::<Vec<_>>();
+
+    assert_eq!(
+        messages,
+        vec![
+            "unexpected character",
+            "expected an identifier",
+            "expected `}`",
+        ]
+    );
+}
+
+#[test]
+fn lenient_parsing_still_needs_a_header() {
+    let options = ParseOptions::new().with_lenient(true);
+
+    assert!(parser::parse_with("a -- b", &options).is_err());
+}
+
+#[test]
+fn parse_errors_point_at_the_token() {
+    let error = Graph::from_dot("graph {\n\ta -- node\n}").unwrap_err();
+
+    assert_eq!(error.offset(), 14);
+    assert_eq!(error.line(), 2);
+    assert_eq!(error.column(), 7);
+    assert_eq!(error.token(), "node");
+
+    assert_eq!(
+        error.to_string(),
+        "expected an identifier at line 2, column 7\n  |\n2 | \ta -- node\n  | \t     ^^^^"
+    );
+
+    let error = Graph::from_dot("graph { a").unwrap_err();
+
+    assert_eq!((error.line(), error.column(), error.token()), (1, 10, ""));
+}
+
+#[test]
+fn document_round_trips_exactly() {
+    let src = "// generated by hand\ndigraph deps {\n    rankdir=LR\n    // the core\n    a -> b [color=red];\n\n    b -> c   /* later */\n}\n";
+
+    let document = Document::parse(src).unwrap();
+
+    assert_eq!(document.to_string(), src);
+
+    let texts = document
+        .statements()
+        .iter()
+        .map(|statement| statement.text())
+        .collect::<Vec<_>>();
+
+    assert_eq!(texts, vec!["rankdir=LR", "a -> b [color=red];", "b -> c"]);
+    assert_eq!(document.statements()[1].trivia(), "\n    // the core\n    ");
+}
+
+#[test]
+fn document_edits_touch_only_their_statement() {
+    let src = "digraph {\n    a -> b  // keep me\n    // about c\n    c -> d\n}\n";
+
+    let mut document = Document::parse(src).unwrap();
+
+    document.replace_statement(0, "a -> x").unwrap();
+    document.push_statement("e").unwrap();
+
+    assert_eq!(
+        document.to_string(),
+        "digraph {\n    a -> x  // keep me\n    // about c\n    c -> d\n    e\n}\n"
+    );
+
+    document.remove_statement(1);
+
+    assert_eq!(
+        document.to_string(),
+        "digraph {\n    a -> x  // keep me\n    // about c\n    e\n}\n"
+    );
+
+    assert!(document.replace_statement(0, "a -- b").is_err());
+    assert!(document.replace_statement(0, "a; b").is_err());
+
+    assert_eq!(document.graph().unwrap().edges(), vec![Edge::new("a", "x")]);
+}
+
+#[test]
+fn public_lexer_reports_every_token() {
+    let src = "graph { a -- \"b c\" // done\n @ }";
+
+    let tokens = dot_lexer::tokenize(src)
+        .map(|token| (token.kind().clone(), &src[token.span()]))
+        .collect::<Vec<_>>();
+
+    assert_eq!(
+        tokens,
+        vec![
+            (TokenKind::Keyword(dot_lexer::Keyword::Graph), "graph"),
+            (TokenKind::LBrace, "{"),
+            (TokenKind::Id("a".to_string()), "a"),
+            (TokenKind::UndirectedEdge, "--"),
+            (TokenKind::Id("b c".to_string()), "\"b c\""),
+            (TokenKind::Comment, "// done"),
+            (TokenKind::Error(LexError::UnexpectedCharacter), "@"),
+            (TokenKind::RBrace, "}"),
+        ]
+    );
+}
+
+#[test]
+fn document_edits_reparse_only_touched_statements() {
+    let src = "digraph {\n    a -> b\n    b -> c\n    c -> d\n}\n";
+
+    let mut document = Document::parse(src).unwrap();
+
+    // `b -> c` becomes `b -> x [color=red]`.
+    let at = src.find("c\n").unwrap();
+    let changed = document.edit(at..at + 1, "x [color=red]").unwrap();
+
+    // The edit touches the start of `c -> d` too, so both are reparsed.
+    assert_eq!(changed, 1..3);
+    assert_eq!(document.statements()[1].text(), "b -> x [color=red]");
+
+    // Typing a new statement just before the closing brace.
+    let end = document.to_string().rfind('}').unwrap();
+    let changed = document.edit(end..end, "    d -> e\n").unwrap();
+
+    assert_eq!(changed, 3..4);
+    assert_eq!(
+        document.to_string(),
+        "digraph {\n    a -> b\n    b -> x [color=red]\n    c -> d\n    d -> e\n}\n"
+    );
+    assert_eq!(document, Document::parse(&document.to_string()).unwrap());
+
+    // Editing the header reparses everything.
+    let error = document.edit(0..7, "graph").unwrap_err();
+
+    assert_eq!(error.message(), "`->` is not allowed in a graph");
+    assert_eq!(document.statements().len(), 4);
+}
+
+#[test]
+fn document_edits_cannot_comment_out_the_closing_brace() {
+    let mut document = Document::parse("graph { a }").unwrap();
+
+    assert!(document.edit(10..10, "//").is_err());
+    assert_eq!(document.to_string(), "graph { a }");
+
+    let mut document = Document::parse("graph { a; b }").unwrap();
+
+    assert!(document.edit(11..11, "#").is_err());
+    assert_eq!(document.to_string(), "graph { a; b }");
+
+    assert_eq!(document.edit(10..10, " // note\n"), Ok(0..2));
+    assert_eq!(document.to_string(), "graph { a; // note\n b }");
+    assert!(document.graph().is_ok());
+}
+
+#[test]
+fn subgraphs_are_written_after_nodes() {
+    let graph = Graph::new()
+        .with_nodes(&[Node::new("a"), Node::new("b")])
+        .with_subgraphs(&[Subgraph::new("cluster_x")
+            .with_attrs(&[("label", "X")])
+            .with_nodes(&["a", "b", "a"])])
+        .with_edges(&[Edge::new("a", "b")]);
+
+    assert!(graph.subgraphs()[0].is_cluster());
+
+    assert_eq!(
+        graph.to_dot(),
+        "graph {\n    a\n    b\n    subgraph cluster_x {\n        graph [label=\"X\"]\n        a\n        b\n    }\n    a -- b\n}\n"
+    );
+}
+
+#[test]
+fn lint_finds_likely_mistakes() {
+    let graph = Graph::new()
+        .with_nodes(&[
+            Node::new("a"),
+            Node::new("b").with_attrs(&[("label", "B"), ("style", "filled,invis")]),
+            Node::new("lonely"),
+        ])
+        .with_edges(&[
+            Edge::new("a", "b"),
+            Edge::new("b", "a").with_attrs(&[("style", "invis"), ("label", "back")]),
+        ])
+        .with_subgraphs(&[
+            Subgraph::new("team").with_attrs(&[("label", "Team")]),
+            Subgraph::new("cluster_ok").with_attrs(&[("label", "Ok")]),
+            Subgraph::new("same_rank").with_attrs(&[("rank", "same")]),
+        ]);
+
+    assert_eq!(
+        graph.lint(),
+        vec![
+            Lint::UnusedNode("lonely".to_string()),
+            Lint::InvisibleNodeLabel("b".to_string()),
+            Lint::DuplicateEdge("b".to_string(), "a".to_string()),
+            Lint::InvisibleEdgeLabel("b".to_string(), "a".to_string()),
+            Lint::NotACluster("team".to_string()),
+        ]
+    );
+
+    assert!(Graph::new()
+        .with_directed(true)
+        .with_edges(&[Edge::new("a", "b"), Edge::new("b", "a")])
+        .lint()
+        .is_empty());
+}
+
+#[derive(Default)]
+struct Recorder {
+    events: Vec<String>,
+}
+
+impl GraphVisitor for Recorder {
+    fn visit_graph_attr(&mut self, key: &str, value: &str) {
+        self.events.push(format!("attr {key}={value}"));
+    }
+
+    fn visit_node(&mut self, node: &Node) {
+        self.events.push(format!("node {}", node.name()));
+    }
+
+    fn visit_edge(&mut self, edge: &Edge) {
+        self.events
+            .push(format!("edge {} {}", edge.node1(), edge.node2()));
+    }
+}
+
+#[test]
+fn visitor_sees_graph_in_output_order() {
+    let graph = Graph::new()
+        .with_attrs(&[("rankdir", "LR"), ("bgcolor", "white")])
+        .with_nodes(&[Node::new("a"), Node::new("b")])
+        .with_edges(&[Edge::new("a", "b")])
+        .with_subgraphs(&[Subgraph::new("cluster_x")]);
+
+    let mut recorder = Recorder::default();
+    graph.accept(&mut recorder);
+
+    assert_eq!(
+        recorder.events,
+        vec![
+            "attr bgcolor=white",
+            "attr rankdir=LR",
+            "node a",
+            "node b",
+            "edge a b",
+        ]
+    );
+}
+
+#[test]
+fn streaming_conversion_writes_statements_in_order() {
+    let src = "digraph { b -> a [color=red]; a [shape=box]; rankdir=LR }";
+
+    let mut writer = DotWriter::new(Vec::new());
+    parser::parse_into(src, &ParseOptions::new(), &mut writer).unwrap();
+    let out = String::from_utf8(writer.finish().unwrap()).unwrap();
+
+    assert_eq!(
+        out,
+        "digraph {\n    b -> a [color=\"red\"]\n    a [shape=\"box\"]\n    graph [rankdir=\"LR\"]\n}\n"
+    );
+
+    assert_eq!(Graph::from_dot(&out), Graph::from_dot(src));
+}
+
+#[test]
+fn emitting_a_graph_matches_to_dot() {
+    let graph = Graph::new()
+        .with_attrs(&[("rankdir", "LR")])
+        .with_nodes(&[Node::new("a").with_comment("first")])
+        .with_edges(&[Edge::new("a", "b")]);
+
+    let mut writer = DotWriter::new(Vec::new());
+    graph.emit(&mut writer);
+
+    assert_eq!(writer.finish().unwrap(), graph.to_dot().into_bytes());
+}
+
+#[test]
+fn observers_hear_about_every_change() {
+    let log = Arc::new(Mutex::new(Vec::new()));
+
+    let mut graph = Graph::new();
+    let sink = Arc::clone(&log);
+    graph.on_change(move |change| {
+        let entry = match change {
+            Change::NodeAdded(node) => format!("+node {}", node.name()),
+            Change::NodeUpdated(node) => format!("~node {}", node.name()),
+            Change::NodeRemoved(node) => format!("-node {}", node.name()),
+            Change::EdgeAdded(edge) => format!("+edge {} {}", edge.node1(), edge.node2()),
+            Change::EdgeUpdated(edge) => format!("~edge {} {}", edge.node1(), edge.node2()),
+            Change::EdgeRemoved(edge) => format!("-edge {} {}", edge.node1(), edge.node2()),
+            Change::AttrSet { key, value } => format!("attr {key}={value}"),
+            Change::AttrRemoved { key } => format!("-attr {key}"),
+        };
+        sink.lock().unwrap().push(entry);
+    });
+
+    let mut graph = graph
+        .with_nodes(&[Node::new("a"), Node::new("b"), Node::new("a")])
+        .with_edges(&[Edge::new("a", "b"), Edge::new("b", "c")])
+        .with_attrs(&[("rankdir", "LR"), ("rankdir", "TB")]);
+
+    assert_eq!(graph.remove_edge("c", "b"), Some(Edge::new("b", "c")));
+    assert_eq!(graph.remove_node("a"), Some(Node::new("a")));
+    assert_eq!(graph.remove_node("a"), None);
+
+    assert_eq!(graph.nodes(), vec![Node::new("b")]);
+    assert!(graph.edges().is_empty());
+
+    assert_eq!(
+        *log.lock().unwrap(),
+        vec![
+            "+node a",
+            "+node b",
+            "~node a",
+            "+edge a b",
+            "+edge b c",
+            "attr rankdir=LR",
+            "-edge b c",
+            "-edge a b",
+            "-node a",
+        ]
+    );
+}
+
+#[test]
+fn removing_a_hub_keeps_other_edges_and_leaves_its_clusters() {
+    let spokes = (0..20_000)
+        .map(|i| Edge::new("hub", &format!("n{i}")))
+        .collect::<Vec<_>>();
+    let mut graph = Graph::new()
+        .with_nodes(&[
+            Node::new("hub").with_attrs(&[("team", "x")]),
+            Node::new("a").with_attrs(&[("team", "x")]),
+        ])
+        .with_edges(&[Edge::new("a", "b")])
+        .with_edges(&spokes)
+        .with_edges(&[Edge::new("b", "c")])
+        .cluster_by_attr("team");
+
+    assert!(graph.remove_node("hub").is_some());
+
+    assert_eq!(graph.edges(), [Edge::new("a", "b"), Edge::new("b", "c")]);
+    assert_eq!(graph.neighbors("b").count(), 2);
+    assert_eq!(graph.subgraphs()[0].nodes(), ["a"]);
+    assert!(!graph.to_dot().contains("hub"));
+}
+
+#[test]
+fn history_graph_undo_and_redo() {
+    let mut history = HistoryGraph::new(Graph::new());
+
+    history.add_node(Node::new("a")).unwrap();
+    history.add_edge(Edge::new("a", "b")).unwrap();
+    history.apply(|graph| graph.with_attrs(&[("rankdir", "LR")]));
+
+    assert!(history.undo());
+    assert!(history.graph().attrs.is_empty());
+    assert!(history.undo());
+    assert!(history.graph().edges().is_empty());
+
+    assert!(history.redo());
+    assert_eq!(history.graph().edges(), vec![Edge::new("a", "b")]);
+
+    assert_eq!(history.remove_node("missing"), None);
+    assert!(history.can_redo());
+
+    history.remove_node("a");
+
+    assert!(!history.can_redo());
+    assert!(history.graph().nodes().is_empty());
+
+    while history.undo() {}
+
+    assert_eq!(*history.graph(), Graph::new());
+}
+
+#[test]
+fn labelled_snapshots() {
+    let mut graph = Graph::new().with_nodes(&[Node::new("a")]);
+
+    graph.snapshot("before");
+
+    let mut graph = graph.with_nodes(&[Node::new("b")]);
+
+    graph.snapshot("after");
+
+    assert_eq!(
+        graph.snapshot_labels().collect::<Vec<_>>(),
+        vec!["before", "after"]
+    );
+    assert_eq!(
+        graph.version("before").unwrap().nodes(),
+        vec![Node::new("a")]
+    );
+
+    assert!(graph.restore("before"));
+    assert_eq!(graph.nodes(), vec![Node::new("a")]);
+    assert_eq!(graph.version("after").unwrap().nodes().len(), 2);
+
+    assert!(!graph.restore("missing"));
+}
+
+#[test]
+fn patch_between_graphs_round_trips() {
+    let from = Graph::new()
+        .with_attrs(&[("rankdir", "LR"), ("bgcolor", "white")])
+        .with_nodes(&[Node::new("a"), Node::new("b"), Node::new("c")])
+        .with_edges(&[Edge::new("a", "b"), Edge::new("b", "c")]);
+    let to = Graph::new()
+        .with_attrs(&[("rankdir", "TB")])
+        .with_nodes(&[
+            Node::new("a"),
+            Node::new("b").with_attrs(&[("shape", "box")]),
+            Node::new("d"),
+        ])
+        .with_edges(&[Edge::new("a", "b"), Edge::new("a", "d")]);
+
+    let patch = GraphPatch::between(&from, &to);
+
+    assert_eq!(
+        patch.to_string(),
+        "-edge b -- c\n~node b [shape=\"box\"]\n-node c\n+node d\n+edge a -- d\n-attr bgcolor\n+attr rankdir=\"TB\"\n"
+    );
+    assert_eq!(patch.to_string().parse::<GraphPatch>(), Ok(patch.clone()));
+
+    let mut patched = from.clone();
+    patched.apply_patch(&patch).unwrap();
+
+    assert_eq!(patched, to);
+    assert!(GraphPatch::between(&to, &patched).is_empty());
+}
+
+#[test]
+fn patch_parse_errors_point_into_the_patch() {
+    let error = "+node a\n+edge a --\n".parse::<GraphPatch>().unwrap_err();
+
+    assert_eq!(error.line(), 2);
+
+    let error = "*node a".parse::<GraphPatch>().unwrap_err();
+
+    assert_eq!(error.message(), "expected a patch operation");
+
+    let patch = GraphPatch::new().with_op(PatchOp::RemoveNode("missing".to_string()));
+    let mut graph = Graph::new();
+
+    assert_eq!(graph.apply_patch(&patch), Ok(()));
+}
+
+#[test]
+fn shared_graph_serves_readers_during_updates() {
+    let shared = SharedGraph::new(Graph::new().with_nodes(&[Node::new("a")]));
+
+    let before = shared.snapshot();
+
+    let writers = (0..4)
+        .map(|i| {
+            let shared = shared.clone();
+            std::thread::spawn(move || {
+                shared.update(|graph| graph.add_node(Node::new(&format!("n{i}"))).unwrap())
+            })
+        })
+        .collect::<Vec<_>>();
+    for writer in writers {
+        writer.join().unwrap();
+    }
+
+    assert_eq!(before.nodes(), vec![Node::new("a")]);
+    assert_eq!(shared.snapshot().nodes().len(), 5);
+
+    let old = shared.replace(Graph::new());
+
+    assert_eq!(old.nodes().len(), 5);
+    assert_eq!(shared.to_dot(), "graph {\n}\n");
+}
+
+#[test]
+fn snapshots_do_not_wait_for_a_slow_update() {
+    let shared = SharedGraph::new(Graph::new().with_nodes(&[Node::new("a")]));
+    let (started, update_started) = mpsc::channel();
+    let (release, update_released) = mpsc::channel::<()>();
+
+    let writer = {
+        let shared = shared.clone();
+        std::thread::spawn(move || {
+            shared.update(|graph| {
+                started.send(()).unwrap();
+                update_released.recv().unwrap();
+                graph.add_node(Node::new("b")).unwrap();
+            })
+        })
+    };
+    update_started.recv().unwrap();
+
+    let (read, snapshot_read) = mpsc::channel();
+    let reader = {
+        let shared = shared.clone();
+        std::thread::spawn(move || read.send(shared.snapshot().nodes().len()).unwrap())
+    };
+
+    assert_eq!(snapshot_read.recv_timeout(Duration::from_secs(5)), Ok(1));
+
+    release.send(()).unwrap();
+    writer.join().unwrap();
+    reader.join().unwrap();
+
+    assert_eq!(shared.snapshot().nodes().len(), 2);
+}
+
+#[test]
+fn select_nodes_and_edges_by_attributes() {
+    let graph = Graph::new()
+        .with_nodes(&[
+            Node::new("a").with_attrs(&[("shape", "box"), ("label", "food db")]),
+            Node::new("b").with_attrs(&[("shape", "box"), ("label", "bar")]),
+            Node::new("c").with_attrs(&[("label", "foo")]),
+        ])
+        .with_edges(&[
+            Edge::new("a", "b").with_attrs(&[("label", "foo")]),
+            Edge::new("b", "c"),
+        ]);
+
+    let selection = graph.select("node[shape=box][label~=foo]").unwrap();
+
+    assert_eq!(selection.nodes(), vec![graph.node("a").unwrap()]);
+    assert!(selection.edges().is_empty());
+
+    let selection = graph.select("* [label~=\"foo\"]").unwrap();
+
+    assert_eq!(selection.nodes().len(), 2);
+    assert_eq!(selection.edges(), vec![&graph.edges()[0]]);
+
+    assert_eq!(graph.select("edge[label]").unwrap().edges().len(), 1);
+    assert_eq!(graph.select("node[shape!=box]").unwrap().nodes().len(), 1);
+
+    let query = Query::nodes().with_attr_eq("shape", "box");
+
+    assert_eq!(graph.query(&query).nodes().len(), 2);
+    assert_eq!("node[shape=box]".parse(), Ok(query));
+
+    let error = graph.select("node[shape=box").unwrap_err();
+
+    assert_eq!((error.message(), error.offset()), ("expected `]`", 14));
+}
+
+#[test]
+fn stylesheet_rules_fill_in_attributes() {
+    let stylesheet: Stylesheet = "node[kind=db] { shape: cylinder; color: blue }\n\
+                                  node { color: gray }\n\
+                                  edge[kind] { style: \"dashed\" }"
+        .parse()
+        .unwrap();
+
+    let graph = Graph::new()
+        .with_nodes(&[
+            Node::new("users").with_attrs(&[("kind", "db")]),
+            Node::new("api").with_attrs(&[("shape", "box")]),
+            Node::new("cache").with_attrs(&[("kind", "db"), ("shape", "box3d")]),
+        ])
+        .with_edges(&[
+            Edge::new("api", "users").with_attrs(&[("kind", "read")]),
+            Edge::new("api", "cache"),
+        ])
+        .with_stylesheet(&stylesheet);
+
+    assert_eq!(graph.node("users").unwrap().attr("shape"), Some("cylinder"));
+    assert_eq!(graph.node("users").unwrap().attr("color"), Some("gray"));
+    assert_eq!(graph.node("api").unwrap().attr("shape"), Some("box"));
+    assert_eq!(graph.node("cache").unwrap().attr("shape"), Some("box3d"));
+    assert_eq!(graph.edges()[0].attr("style"), Some("dashed"));
+    assert_eq!(graph.edges()[1].attr("style"), None);
+
+    let built = Stylesheet::new().with_rule(Query::nodes(), &[("color", "gray")]);
+
+    assert_eq!("node { color: gray; }".parse(), Ok(built));
+
+    let error = "node[kind { shape: box }"
+        .parse::<Stylesheet>()
+        .unwrap_err();
+
+    assert_eq!(error.message(), "expected `]`, `=`, `!=` or `~=`");
+
+    let error = "node { shape box }".parse::<Stylesheet>().unwrap_err();
+
+    assert_eq!(
+        (error.message(), error.offset()),
+        ("expected `key: value`", 6)
+    );
+
+    let quoted = r#"node[label="{"] { label: "a;b}c"; tooltip: "say \"hi\"" }"#
+        .parse::<Stylesheet>()
+        .unwrap();
+    let expected = Stylesheet::new().with_rule(
+        "node[label=\"{\"]".parse().unwrap(),
+        &[("label", "a;b}c"), ("tooltip", "say \"hi\"")],
+    );
+
+    assert_eq!(quoted, expected);
+    assert!("node { label: \"open }".parse::<Stylesheet>().is_err());
+}
+
+#[test]
+fn find_labelled_two_cycles() {
+    let graph = Graph::new().with_directed(true).with_edges(&[
+        Edge::new("x", "y").with_attrs(&[("label", "ping")]),
+        Edge::new("y", "x").with_attrs(&[("label", "ping")]),
+        Edge::new("y", "z").with_attrs(&[("label", "ping")]),
+        Edge::new("z", "y"),
+    ]);
+
+    let pattern = Graph::new().with_directed(true).with_edges(&[
+        Edge::new("a", "b").with_attrs(&[("label", "ping")]),
+        Edge::new("b", "a").with_attrs(&[("label", "ping")]),
+    ]);
+
+    let matches = graph.find_pattern(&pattern);
+
+    assert_eq!(matches.len(), 2);
+    assert_eq!(matches[0].node("a"), Some("x"));
+    assert_eq!(matches[0].node("b"), Some("y"));
+    assert_eq!(matches[0].edges(), &[&graph.edges()[0], &graph.edges()[1]]);
+    assert_eq!(matches[1].nodes(), &[("a", "y"), ("b", "x")]);
+}
+
+#[test]
+fn pattern_node_attributes_restrict_matches() {
+    let graph = Graph::new()
+        .with_nodes(&[
+            Node::new("web").with_attrs(&[("kind", "service")]),
+            Node::new("users").with_attrs(&[("kind", "db")]),
+            Node::new("jobs").with_attrs(&[("kind", "service")]),
+        ])
+        .with_edges(&[Edge::new("users", "web"), Edge::new("jobs", "web")]);
+
+    let pattern = Graph::new()
+        .with_nodes(&[Node::new("db").with_attrs(&[("kind", "db")])])
+        .with_edges(&[Edge::new("service", "db")]);
+
+    let matches: Vec<Match> = graph.find_pattern(&pattern);
+
+    assert_eq!(matches.len(), 1);
+    assert_eq!(matches[0].nodes(), &[("db", "users"), ("service", "web")]);
+
+    assert!(graph.with_directed(true).find_pattern(&pattern).is_empty());
+}
+
+#[test]
+fn effective_attrs_follow_graphviz_inheritance() {
+    let graph = Graph::new()
+        .with_node_defaults(&[("shape", "box"), ("color", "gray")])
+        .with_edge_defaults(&[("color", "gray")])
+        .with_nodes(&[
+            Node::new("a"),
+            Node::new("b").with_attrs(&[("color", "red")]),
+            Node::new("c"),
+        ])
+        .with_subgraphs(&[Subgraph::new("cluster_db")
+            .with_node_defaults(&[("shape", "cylinder")])
+            .with_nodes(&["b"])])
+        .with_edges(&[
+            Edge::new("a", "b"),
+            Edge::new("b", "c").with_attrs(&[("color", "blue")]),
+        ]);
+
+    let a = graph.node("a").unwrap().effective_attrs(&graph);
+
+    assert_eq!(a, HashMap::from([("shape", "box"), ("color", "gray")]));
+
+    let b = graph.node("b").unwrap().effective_attrs(&graph);
+
+    assert_eq!(b, HashMap::from([("shape", "cylinder"), ("color", "red")]));
+
+    assert_eq!(graph.edges()[0].effective_attrs(&graph)["color"], "gray");
+    assert_eq!(graph.edges()[1].effective_attrs(&graph)["color"], "blue");
+}
+
+#[test]
+fn defaults_are_written_and_read_back() {
+    let graph = Graph::new()
+        .with_node_defaults(&[("shape", "box")])
+        .with_edge_defaults(&[("style", "dashed")])
+        .with_nodes(&[
+            Node::new("a"),
+            Node::new("b").with_attrs(&[("color", "red")]),
+        ])
+        .with_subgraphs(&[Subgraph::new("s")
+            .with_node_defaults(&[("shape", "circle")])
+            .with_nodes(&["b"])])
+        .with_edges(&[Edge::new("a", "b")]);
+
+    assert_eq!(
+        graph.to_dot(),
+        "graph {\n    node [shape=\"box\"]\n    edge [style=\"dashed\"]\n    a\n    subgraph s {\n        node [shape=\"circle\"]\n        b [color=\"red\"]\n    }\n    a -- b\n}\n"
+    );
+
+    let parsed =
+        Graph::from_dot("graph {\n    node [shape=box]\n    edge [style=dashed]\n    a -- b\n}")
+            .unwrap();
+
+    assert_eq!(parsed.node_defaults()["shape"], "box");
+    assert_eq!(parsed.edge_defaults()["style"], "dashed");
+}
+
+#[test]
+fn themes_set_graph_attrs_and_defaults() {
+    let graph = Graph::new()
+        .with_nodes(&[
+            Node::new("a").with_attrs(&[("color", "red")]),
+            Node::new("b"),
+        ])
+        .with_theme(Theme::Dark);
+
+    assert_eq!(graph.attrs["bgcolor"], "#1e1e1e");
+    assert_eq!(graph.node_defaults()["style"], "filled");
+    assert_eq!(graph.edge_defaults()["color"], "#9cdcfe");
+
+    assert_eq!(
+        graph.node("a").unwrap().effective_attrs(&graph)["color"],
+        "red"
+    );
+    assert_eq!(
+        graph.node("b").unwrap().effective_attrs(&graph)["color"],
+        "#569cd6"
+    );
+
+    let print = Graph::new().with_theme(Theme::Print);
+
+    assert!(!print.node_defaults().contains_key("fillcolor"));
+}
+
+#[test]
+fn templates_fill_in_placeholders() {
+    let template: GraphTemplate =
+        "digraph {\n    ${service} [label=\"${service} (${team})\"]\n    ${service} -> db\n}"
+            .parse()
+            .unwrap();
+
+    assert_eq!(template.placeholders(), vec!["service", "team"]);
+
+    let values = HashMap::from([("service", "web api"), ("team", "say \"hi\"")]);
+    let graph = template.instantiate(&values).unwrap();
+
+    assert_eq!(
+        graph.node("web api").unwrap().attr("label"),
+        Some("web api (say \"hi\")")
+    );
+    assert_eq!(graph.edges(), vec![Edge::new("web api", "db")]);
+
+    let error = template
+        .instantiate(&HashMap::from([("service", "web")]))
+        .unwrap_err();
+
+    assert_eq!(
+        (error.message(), error.offset()),
+        ("no value for `team`", 45)
+    );
+}
+
+#[test]
+fn templates_are_checked_when_parsed() {
+    let error = "graph { ${a -- b }".parse::<GraphTemplate>().unwrap_err();
+
+    assert_eq!(error.message(), "invalid placeholder name");
+
+    let error = "graph { ${a} -- }".parse::<GraphTemplate>().unwrap_err();
+
+    assert_eq!(
+        (error.message(), error.offset()),
+        ("expected an identifier", 16)
+    );
+}
+
+#[test]
+fn namespaces_keep_merged_graphs_apart() {
+    let billing = Graph::new()
+        .with_nodes(&[Node::new("api").with_attrs(&[("color", "red")])])
+        .with_edges(&[Edge::new("api", "db")])
+        .with_namespace("billing");
+    let search = Graph::new()
+        .with_edges(&[Edge::new("api", "db")])
+        .with_namespace("search");
+
+    let graph = billing.with_graph(&search);
+
+    assert_eq!(graph.nodes().len(), 1);
+    assert_eq!(
+        graph.node("billing.api").unwrap().attr("color"),
+        Some("red")
+    );
+    assert_eq!(
+        graph.edges(),
+        vec![
+            Edge::new("billing.api", "billing.db"),
+            Edge::new("search.api", "search.db"),
+        ]
+    );
+    assert_eq!(graph.original_name("search.db"), "db");
+    assert_eq!(graph.original_name("elsewhere"), "elsewhere");
+
+    let nested = graph.with_namespace("org");
+
+    assert!(nested.contains_node("org.billing.api"));
+    assert_eq!(nested.original_name("org.billing.api"), "api");
+}
+
+#[test]
+fn cluster_nodes_by_attribute_value() {
+    let graph = Graph::new()
+        .with_nodes(&[
+            Node::new("api").with_attrs(&[("team", "web")]),
+            Node::new("ledger").with_attrs(&[("team", "payments & billing")]),
+            Node::new("ui").with_attrs(&[("team", "web")]),
+            Node::new("cron"),
+        ])
+        .cluster_by_attr("team");
+
+    assert_eq!(
+        graph.subgraphs(),
+        vec![
+            Subgraph::new("cluster_web")
+                .with_attrs(&[("label", "web")])
+                .with_nodes(&["api", "ui"]),
+            Subgraph::new("cluster_payments___billing")
+                .with_attrs(&[("label", "payments & billing")])
+                .with_nodes(&["ledger"]),
+        ]
+    );
+    assert!(graph.subgraphs().iter().all(Subgraph::is_cluster));
+}
+
+#[test]
+fn clusters_by_attribute_get_distinct_names_once() {
+    let graph = Graph::new()
+        .with_nodes(&[
+            Node::new("a").with_attrs(&[("team", "a-b")]),
+            Node::new("b").with_attrs(&[("team", "a b")]),
+        ])
+        .cluster_by_attr("team");
+
+    let names = graph
+        .subgraphs()
+        .iter()
+        .map(Subgraph::name)
+        .collect::<Vec<_>>();
+
+    assert_eq!(names, ["cluster_a_b", "cluster_a_b_2"]);
+    assert_eq!(graph.clone().cluster_by_attr("team"), graph);
+}
+
+#[test]
+fn edge_weights_as_numbers() {
+    let mut edge = Edge::new("a", "b").with_weight(2.5).unwrap();
+
+    assert_eq!(edge.weight(), Some(2.5));
+
+    edge.set_weight(4.0).unwrap();
+
+    assert_eq!(edge.attr("weight"), Some("4"));
+    assert!(edge.set_weight(-1.0).is_err());
+    assert_eq!(edge.weight(), Some(4.0));
+
+    let graph = Graph::new().with_edges(&[
+        edge,
+        Edge::new("b", "c"),
+        Edge::new("c", "a").with_attrs(&[("weight", "heavy")]),
+    ]);
+
+    assert_eq!(graph.edges()[2].weight(), None);
+    assert_eq!(graph.total_weight(), 6.0);
+}
+
+#[test]
+fn typed_attribute_getters() {
+    let node = Node::new("a").with_attrs(&[("peripheries", "2"), ("fixedsize", "true")]);
+
+    assert_eq!(node.attr_as::<u32>("peripheries"), Ok(Some(2)));
+    assert_eq!(node.attr_as::<bool>("fixedsize"), Ok(Some(true)));
+    assert_eq!(node.attr_as::<f64>("fontsize"), Ok(None));
+
+    let edge = Edge::new("a", "b").with_attrs(&[("weight", "lots")]);
+
+    let error = edge.attr_as::<f64>("weight").unwrap_err();
+
+    assert_eq!(error.message(), "cannot read attribute `weight`");
+
+    assert_eq!(error.token(), "lots");
+
+    assert_eq!((error.offset(), error.line(), error.column()), (0, 1, 1));
+
+    let graph = Graph::new().with_nodesep(0.5).unwrap();
+
+    assert_eq!(graph.attr_as::<f64>("nodesep"), Ok(Some(0.5)));
+}
+
+#[test]
+fn attributes_can_be_unset() {
+    let node = Node::new("a")
+        .with_attrs(&[("color", "red"), ("shape", "box")])
+        .without_attr("color")
+        .without_attr("missing");
+
+    assert_eq!(node, Node::new("a").with_attrs(&[("shape", "box")]));
+
+    let edge = Edge::new("a", "b")
+        .with_attr("label", "x")
+        .without_attr("label");
+
+    assert_eq!(edge, Edge::new("a", "b"));
+
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let mut graph = Graph::new().with_attrs(&[("rankdir", "LR"), ("bgcolor", "white")]);
+    let sink = Arc::clone(&log);
+    graph.on_change(move |change| {
+        if let Change::AttrRemoved { key } = change {
+            sink.lock().unwrap().push(key.to_string());
+        }
+    });
+
+    assert_eq!(graph.remove_attr("rankdir"), Some("LR".to_string()));
+    assert_eq!(graph.remove_attr("rankdir"), None);
+
+    let graph = graph.without_attr("bgcolor");
+
+    assert!(graph.attrs.is_empty());
+    assert_eq!(*log.lock().unwrap(), vec!["rankdir", "bgcolor"]);
+}
+
+#[test]
+fn attributes_can_be_replaced_wholesale() {
+    let node = Node::new("a")
+        .with_attrs(&[("color", "red"), ("shape", "box")])
+        .replace_attrs(&[("color", "blue")]);
+
+    assert_eq!(node, Node::new("a").with_attrs(&[("color", "blue")]));
+
+    let edge = Edge::new("a", "b").with_attr("label", "x").clear_attrs();
+
+    assert_eq!(edge.attrs().count(), 0);
+
+    let graph = Graph::new()
+        .with_attrs(&[("rankdir", "LR"), ("bgcolor", "white")])
+        .replace_attrs(&[("rankdir", "TB")]);
+
+    assert_eq!(
+        graph.attrs,
+        HashMap::from([("rankdir".to_string(), "TB".to_string())])
+    );
+    assert!(graph.clear_attrs().attrs.is_empty());
+}
+
+#[test]
+fn attribute_diffs_between_entities() {
+    let before = Node::new("a").with_attrs(&[("color", "red"), ("shape", "box"), ("width", "1")]);
+    let after = Node::new("a").with_attrs(&[("color", "blue"), ("shape", "box"), ("label", "A")]);
+
+    let diff: AttrDiff = before.attr_diff(&after);
+
+    assert_eq!(diff.added(), &[("label", "A")]);
+    assert_eq!(diff.removed(), &[("width", "1")]);
+    assert_eq!(diff.changed(), &[("color", "red", "blue")]);
+    assert_eq!(
+        diff.to_string(),
+        "+label=A\n-width=1\n~color: red -> blue\n"
+    );
+
+    let edge = Edge::new("a", "b").with_attr("style", "dashed");
+
+    assert!(edge.attr_diff(&edge.clone()).is_empty());
+}
+
+#[test]
+fn cascade_controls_subgraph_inheritance() {
+    let graph = Graph::new()
+        .with_attrs(&[("label", "Services")])
+        .with_nodes(&[Node::new("a")])
+        .with_subgraphs(&[Subgraph::new("cluster_x").with_nodes(&["a"])]);
+
+    let cluster = &graph.subgraphs()[0];
+
+    assert_eq!(cluster.effective_attrs(&graph)["label"], "Services");
+
+    let graph = graph.with_cascade(Cascade::new().with_subgraph_inheritance(false));
+
+    assert!(graph.subgraphs()[0].effective_attrs(&graph).is_empty());
+    assert_eq!(
+        graph.to_dot(),
+        "graph {\n    a\n    subgraph cluster_x {\n        a\n    }\n    graph [label=\"Services\"]\n}\n"
+    );
+}
+
+#[test]
+fn cascade_can_combine_styles() {
+    let graph = Graph::new()
+        .with_cascade(Cascade::new().with_combined_lists(true))
+        .with_attrs(&[("style", "filled")])
+        .with_attrs(&[("style", "rounded,filled")])
+        .with_nodes(&[
+            Node::new("a").with_attrs(&[("style", "dashed"), ("color", "red")]),
+            Node::new("a").with_attrs(&[("style", "bold"), ("color", "blue")]),
+        ]);
+
+    assert_eq!(graph.attrs["style"], "filled,rounded");
+    assert_eq!(graph.node("a").unwrap().attr("style"), Some("dashed,bold"));
+    assert_eq!(graph.node("a").unwrap().attr("color"), Some("blue"));
+
+    let graph = Graph::new().with_nodes(&[
+        Node::new("a").with_attrs(&[("style", "dashed")]),
+        Node::new("a").with_attrs(&[("style", "bold")]),
+    ]);
+
+    assert_eq!(graph.node("a").unwrap().attr("style"), Some("bold"));
+}
+
+#[test]
+fn stats_summarize_the_graph() {
+    let graph = Graph::new()
+        .with_directed(true)
+        .with_nodes(&[Node::new("lonely")])
+        .with_edges(&[
+            Edge::new("a", "b"),
+            Edge::new("a", "c"),
+            Edge::new("b", "c"),
+            Edge::new("d", "e"),
+        ]);
+
+    assert_eq!(
+        graph.stats(),
+        GraphStats {
+            node_count: 6,
+            edge_count: 4,
+            self_loops: 0,
+            isolated_nodes: 1,
+            components: 3,
+            max_degree: 2,
+            is_dag: true,
+        }
+    );
+
+    let cyclic = graph.with_edges(&[Edge::new("c", "a"), Edge::new("e", "e")]);
+    let stats = cyclic.stats();
+
+    assert!(!stats.is_dag);
+    assert_eq!((stats.self_loops, stats.max_degree), (1, 3));
+    assert!(!generators::path(3).stats().is_dag);
+}
+
+#[test]
+fn canonical_dot_quotes_and_declares_everything() {
+    let graph = Graph::new()
+        .with_directed(true)
+        .with_comment("generated")
+        .with_attrs(&[("rankdir", "LR")])
+        .with_nodes(&[Node::new("a").with_attrs(&[("shape", "box"), ("color", "red")])])
+        .with_edges(&[Edge::new("a", "b").with_attrs(&[("weight", "2")])]);
+
+    assert_eq!(
+        graph.to_canonical_dot(),
+        "digraph {\n\
+         \tgraph [\"rankdir\"=\"LR\"];\n\
+         \t\"a\" [\"color\"=\"red\", \"shape\"=\"box\"];\n\
+         \t\"b\";\n\
+         \t\"a\" -> \"b\" [\"weight\"=\"2\"];\n\
+         }\n"
+    );
+
+    let reparsed = Graph::from_dot(&graph.to_canonical_dot()).unwrap();
+
+    assert_eq!(reparsed.to_canonical_dot(), graph.to_canonical_dot());
+}
+
+#[test]
+fn parse_limits_stop_oversized_input() {
+    let src = "digraph {\n    a -> b -> c\n    d [label=\"a long label\"]\n}";
+
+    let error = parser::parse_with(src, &ParseOptions::new().with_max_nodes(2)).unwrap_err();
+
+    assert_eq!(error.limit_exceeded(), Some(LimitExceeded::Nodes(2)));
+    assert_eq!((error.message(), error.line()), ("more than 2 nodes", 2));
+
+    let options = ParseOptions::new().with_max_edges(1).with_lenient(true);
+    let error = parser::parse_with(src, &options).unwrap_err();
+
+    assert_eq!(error.limit_exceeded(), Some(LimitExceeded::Edges(1)));
+
+    let error = parser::parse_with(src, &ParseOptions::new().with_max_attr_len(5)).unwrap_err();
+
+    assert_eq!(error.limit_exceeded(), Some(LimitExceeded::AttrLength(5)));
+    assert_eq!(error.token(), "\"a long label\"");
+
+    let error = parser::parse_with(src, &ParseOptions::new().with_max_depth(1)).unwrap_err();
+
+    assert_eq!(error.limit_exceeded(), Some(LimitExceeded::Depth(1)));
+
+    let options = ParseOptions::new()
+        .with_max_nodes(4)
+        .with_max_edges(2)
+        .with_max_depth(2)
+        .with_max_attr_len(12);
+
+    assert!(parser::parse_with(src, &options).is_ok());
+    assert_eq!(
+        Graph::from_dot("graph {").unwrap_err().limit_exceeded(),
+        None
+    );
+}
+
+#[test]
+fn parse_from_a_reader() {
+    let src = "// exported\ndigraph {\n    rankdir=LR; a [label=\"{ ; }\"]\n    a -> b\n      -> c\n    c\n      [color=red] /* note\n    } */\n    d [label=\"two\n lines\"]\n    d ->\n      e\n}\n";
+
+    let graph = Graph::from_dot_reader(src.as_bytes()).unwrap();
+
+    assert_eq!(graph, Graph::from_dot(src).unwrap());
+    assert_eq!(graph.edges().len(), 3);
+
+    let src = "graph {\n# preprocessor\n    a /* x */\n}\n# trailing\n";
+
+    assert_eq!(
+        Graph::from_dot_reader(src.as_bytes()).ok(),
+        Graph::from_dot(src).ok()
+    );
+    assert!(Graph::from_dot(src).is_ok());
+    assert_eq!(graph.node("c").unwrap().attr("color"), Some("red"));
+}
+
+#[test]
+fn reader_errors_point_into_the_whole_input() {
+    for src in [
+        "graph {\n    a -- b\n    c -- [x=1]\n}",
+        "graph {\n    a --\n}",
+        "graph {\n    a -- b;  c -> d\n}",
+        "graph {\n    a -- b\n",
+        "graph {\n    a\n} x",
+        "grap {}",
+        ";",
+        "x;",
+        "}",
+        "\\-1}",
+        "graph {}#",
+        "graph { a; }#x",
+        "graph {\n    a # b\n}",
+        "strict x digraph {\n    a\n}",
+    ] {
+        let expected = Graph::from_dot(src).unwrap_err();
+
+        match Graph::from_dot_reader(src.as_bytes()) {
+            Err(parser::ReadError::Parse(error)) => assert_eq!(error, expected, "{src:?}"),
+            other => panic!("{src:?} gave {other:?}"),
+        }
+    }
+}
+
+#[test]
+#[cfg(feature = "ffi")]
+fn ffi_builds_and_renders_a_graph() {
+    use dot_dsl::graph::ffi::*;
+    use std::ffi::{CStr, CString};
+
+    let c = |s: &str| CString::new(s).unwrap();
+
+    unsafe {
+        let graph = dot_graph_new(false);
+
+        assert_eq!(dot_graph_add_node(graph, c("a").as_ptr()), DOT_OK);
+        assert_eq!(
+            dot_graph_add_edge(graph, c("a").as_ptr(), c("b").as_ptr()),
+            DOT_OK
+        );
+        assert_eq!(
+            dot_graph_set_attr(graph, c("rankdir").as_ptr(), c("LR").as_ptr()),
+            DOT_OK
+        );
+        assert_eq!(
+            dot_graph_set_node_attr(
+                graph,
+                c("a").as_ptr(),
+                c("color").as_ptr(),
+                c("red").as_ptr()
+            ),
+            DOT_OK
+        );
+        assert_eq!(
+            dot_graph_set_edge_attr(
+                graph,
+                c("b").as_ptr(),
+                c("a").as_ptr(),
+                c("label").as_ptr(),
+                c("x").as_ptr(),
+            ),
+            DOT_OK
+        );
+
+        let dot = dot_graph_to_dot(graph);
+        let expected = Graph::new()
+            .with_nodes(&[Node::new("a").with_attrs(&[("color", "red")])])
+            .with_edges(&[Edge::new("a", "b").with_attrs(&[("label", "x")])])
+            .with_attrs(&[("rankdir", "LR")]);
+
+        assert_eq!(CStr::from_ptr(dot).to_str().unwrap(), expected.to_dot());
+
+        dot_string_free(dot);
+        dot_graph_free(graph);
+    }
+}
+
+#[test]
+#[cfg(feature = "ffi")]
+fn ffi_reports_bad_arguments() {
+    use dot_dsl::graph::ffi::*;
+    use std::ffi::CString;
+    use std::ptr;
+
+    let name = CString::new("a").unwrap();
+
+    unsafe {
+        let graph = dot_graph_from_dot(CString::new("graph { a }").unwrap().as_ptr());
+
+        assert!(!graph.is_null());
+        assert!(dot_graph_from_dot(CString::new("graph {").unwrap().as_ptr()).is_null());
+        assert_eq!(dot_graph_add_node(graph, ptr::null()), DOT_EINVAL);
+        assert_eq!(
+            dot_graph_add_node(ptr::null_mut(), name.as_ptr()),
+            DOT_EINVAL
+        );
+        assert_eq!(
+            dot_graph_add_node(graph, CString::new("\n").unwrap().as_ptr()),
+            DOT_EGRAPH
+        );
+        assert_eq!(
+            dot_graph_set_node_attr(
+                graph,
+                CString::new("z").unwrap().as_ptr(),
+                name.as_ptr(),
+                name.as_ptr()
+            ),
+            DOT_ENOTFOUND
+        );
+
+        dot_graph_free(graph);
+    }
+}
+
+#[test]
+#[cfg(feature = "ffi")]
+fn ffi_header_declares_every_function() {
+    let header = include_str!("../include/dot_dsl.h");
+    let source = include_str!("../src/lib.rs");
+    let exported = source
+        .split("extern \"C\" fn ")
+        .skip(1)
+        .map(|rest| &rest[..rest.find('(').unwrap()]);
+
+    for name in exported {
+        assert!(
+            header.contains(&format!("{name}(")),
+            "{name} missing from header"
+        );
+    }
+}
+
+#[test]
+#[cfg(feature = "cli")]
+fn cli_converts_an_edge_list_to_dot() {
+    use std::io::Write;
+    use std::process::{Command, Stdio};
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_dot-dsl"))
+        .args(["convert", "-", "--from", "edge-list"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(b"a,b\nb c\n")
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+
+    let expected = Graph::new().with_edges(&[Edge::new("a", "b"), Edge::new("b", "c")]);
+
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), expected.to_dot());
+}
+
+#[test]
+#[cfg(feature = "cli")]
+fn cli_edge_lists_read_back_as_written() {
+    use std::io::Write;
+    use std::process::{Command, Stdio};
+
+    let run = |args: &[&str], input: &str| {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_dot-dsl"))
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        child
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(input.as_bytes())
+            .unwrap();
+        let output = child.wait_with_output().unwrap();
+        assert!(output.status.success());
+        String::from_utf8(output.stdout).unwrap()
+    };
+    let graph = Graph::new()
+        .with_directed(true)
+        .with_nodes(&[Node::new("#lonely")])
+        .with_edges(&[Edge::new("a,b", "c d"), Edge::new("say \"hi\"", "C:\\temp")]);
+
+    let list = run(&["convert", "-", "--to", "edge-list"], &graph.to_dot());
+
+    assert_eq!(
+        list,
+        "\"a,b\",\"c d\"\n\"say \\\"hi\\\"\",C:\\temp\n\"#lonely\"\n"
+    );
+
+    let back = run(
+        &["convert", "-", "--from", "edge-list", "--directed"],
+        &list,
+    );
+
+    assert_eq!(Graph::from_dot(&back), Ok(graph));
+}
+
+#[test]
+fn binary_round_trip() {
+    let graph = Graph::new()
+        .with_directed(true)
+        .with_comment("generated")
+        .with_attrs(&[("rankdir", "LR")])
+        .with_node_defaults(&[("shape", "box")])
+        .with_edge_defaults(&[("color", "gray")])
+        .with_nodes(&[
+            Node::new("a").with_attrs(&[("label", "A \"quoted\""), ("color", "red")]),
+            Node::new("b").with_comment("second"),
+        ])
+        .with_edges(&[
+            Edge::new("a", "b").with_attrs(&[("weight", "2")]),
+            Edge::new("a", "b"),
+            Edge::new("b", "c"),
+        ])
+        .with_subgraphs(&[Subgraph::new("cluster_x")
+            .with_attrs(&[("label", "X")])
+            .with_nodes(&["a", "b"])]);
+
+    let bytes = graph.to_bytes();
+
+    assert!(bytes.starts_with(b"DOTG"));
+    assert_eq!(Graph::from_bytes(&bytes), Ok(graph.clone()));
+    assert_eq!(Graph::from_bytes(&bytes).unwrap().to_dot(), graph.to_dot());
+}
+
+#[test]
+fn binary_rejects_bad_input() {
+    let bytes = Graph::new().with_edges(&[Edge::new("a", "b")]).to_bytes();
+
+    assert_eq!(Graph::from_bytes(b"graph {}"), Err(DecodeError::BadMagic));
+    assert_eq!(
+        Graph::from_bytes(b"DOTG\x09\x00"),
+        Err(DecodeError::UnsupportedVersion(9))
+    );
+    assert_eq!(
+        Graph::from_bytes(&bytes[..bytes.len() - 1]),
+        Err(DecodeError::Truncated)
+    );
+    assert_eq!(
+        Graph::from_bytes(&[&bytes[..], b"x"].concat()),
+        Err(DecodeError::TrailingBytes)
+    );
+}
+
+#[test]
+fn read_an_edge_list() {
+    let src = "# exported\na,b\nb\tc\n\nd\r\nc , a\n";
+
+    let graph = Graph::from_edge_list(src.as_bytes()).unwrap();
+
+    let expected = Graph::new().with_nodes(&[Node::new("d")]).with_edges(&[
+        Edge::new("a", "b"),
+        Edge::new("b", "c"),
+        Edge::new("c", "a"),
+    ]);
+
+    assert_eq!(graph, expected);
+
+    let edges = graph.edges();
+
+    assert_eq!(edges[0].node2().as_ptr(), edges[1].node1().as_ptr());
+    assert_eq!(edges[0].node1().as_ptr(), edges[2].node2().as_ptr());
+}
+
+#[test]
+fn edge_list_names_can_be_quoted() {
+    let src = "\"a b\",\"#c\"\n\"d\\\"e\" f\n\"open\n";
+
+    match Graph::from_edge_list(src.as_bytes()) {
+        Err(parser::ReadError::Parse(error)) => {
+            assert_eq!(error.line(), 3);
+            assert!(error.to_string().contains("unterminated"));
+        }
+        other => panic!("{other:?}"),
+    }
+
+    let graph = Graph::from_edge_list(&src.as_bytes()[..src.len() - 6]).unwrap();
+
+    let expected = Graph::new().with_edges(&[Edge::new("a b", "#c"), Edge::new("d\"e", "f")]);
+
+    assert_eq!(graph, expected);
+}
+
+#[test]
+fn edge_list_errors_point_at_the_extra_name() {
+    let src = "a,b\nb c  d\n";
+
+    match Graph::from_edge_list(src.as_bytes()) {
+        Err(parser::ReadError::Parse(error)) => {
+            assert_eq!(error.line(), 2);
+            assert_eq!(error.column(), 6);
+            assert_eq!(error.offset(), 9);
+            assert_eq!(error.token(), "d");
+        }
+        other => panic!("{other:?}"),
+    }
+}
+
+#[test]
+fn pull_statements_from_a_reader() {
+    let src = "digraph {\n    rankdir=LR\n    node [shape=box]\n    a [color=red]\n    a -> b\n}\n";
+
+    let events = parser::DotReader::new(src.as_bytes())
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+
+    assert_eq!(
+        events,
+        vec![
+            Event::BeginGraph { directed: true },
+            Event::GraphAttrs(vec![("rankdir".to_string(), "LR".to_string())]),
+            Event::NodeDefaults(vec![("shape".to_string(), "box".to_string())]),
+            Event::Node(Node::new("a").with_attrs(&[("color", "red")])),
+            Event::Edge(Edge::new("a", "b")),
+            Event::EndGraph,
+        ]
+    );
+}
+
+#[test]
+fn dot_reader_stops_after_an_error() {
+    let src = "graph {\n    a -- b\n    c -- [x=1]\n    d\n}";
+
+    let mut reader = parser::DotReader::new(src.as_bytes());
+
+    assert_eq!(
+        reader.next().unwrap().unwrap(),
+        Event::BeginGraph { directed: false }
+    );
+    assert_eq!(
+        reader.next().unwrap().unwrap(),
+        Event::Edge(Edge::new("a", "b"))
+    );
+    match reader.next() {
+        Some(Err(parser::ReadError::Parse(error))) => {
+            assert_eq!(error, Graph::from_dot(src).unwrap_err())
+        }
+        other => panic!("{other:?}"),
+    }
+    assert!(reader.next().is_none());
+
+    let src = "graph { a; b -- ; }";
+    let events = parser::DotReader::new(src.as_bytes()).collect::<Vec<_>>();
+
+    assert_eq!(events.len(), 3);
+    assert_eq!(
+        events[0].as_ref().unwrap(),
+        &Event::BeginGraph { directed: false }
+    );
+    assert_eq!(events[1].as_ref().unwrap(), &Event::Node(Node::new("a")));
+    assert!(events[2].is_err());
+}
+
+#[test]
+fn nodes_carry_typed_data() {
+    #[derive(Debug, PartialEq)]
+    struct Service {
+        port: u16,
+    }
+
+    let graph = Graph::new().with_nodes(&[
+        Node::new("api").with_data(Service { port: 8080 }),
+        Node::new("db"),
+    ]);
+
+    let api = graph.node("api").unwrap();
+
+    assert_eq!(api.data::<Service>(), Some(&Service { port: 8080 }));
+    assert_eq!(api.data::<String>(), None);
+    assert_eq!(graph.node("db").unwrap().data::<Service>(), None);
+    assert_eq!(api, &Node::new("api"));
+    assert_eq!(graph.to_dot(), "graph {\n    api\n    db\n}\n");
+}
+
+#[test]
+fn edges_by_id() {
+    let mut graph = Graph::new()
+        .with_edges(&[
+            Edge::new("a", "b"),
+            Edge::new("a", "b").with_id("e0"),
+            Edge::new("b", "c"),
+        ])
+        .with_edge_ids();
+
+    let ids = graph
+        .edges()
+        .iter()
+        .map(|e| e.id().unwrap())
+        .collect::<Vec<_>>();
+
+    assert_eq!(ids, ["e1", "e0", "e2"]);
+    assert_eq!(
+        graph.edge_by_id("e2"),
+        Some(&Edge::new("b", "c").with_id("e2"))
+    );
+    assert_eq!(
+        graph.remove_edge_by_id("e0"),
+        Some(Edge::new("a", "b").with_id("e0"))
+    );
+    assert_eq!(graph.edge_by_id("e0"), None);
+    assert_eq!(graph.edges().len(), 2);
+}
+
+#[test]
+fn patches_track_edges_by_id() {
+    let from = Graph::new().with_edges(&[
+        Edge::new("a", "b").with_id("x"),
+        Edge::new("a", "b").with_id("y"),
+    ]);
+    let to = Graph::new().with_edges(&[
+        Edge::new("a", "b").with_id("x"),
+        Edge::new("a", "c")
+            .with_id("y")
+            .with_attrs(&[("weight", "3")]),
+    ]);
+
+    let patch = GraphPatch::between(&from, &to);
+
+    assert_eq!(
+        patch.ops(),
+        [PatchOp::ChangeEdge(
+            Edge::new("a", "c").with_attrs(&[("id", "y"), ("weight", "3")])
+        )]
+    );
+    assert_eq!(patch.to_string().parse::<GraphPatch>(), Ok(patch.clone()));
+
+    let mut patched = from.clone();
+
+    assert_eq!(patched.neighbors("a").collect::<Vec<_>>(), ["b"]);
+
+    patched.apply_patch(&patch).unwrap();
+
+    assert_eq!(patched, to);
+    assert_eq!(patched.neighbors("a").collect::<Vec<_>>(), ["b", "c"]);
+}
+
+#[test]
+fn counting_nodes_and_edges() {
+    let empty = Graph::new().with_attrs(&[("rankdir", "LR")]);
+    let graph = Graph::new()
+        .with_nodes(&[Node::new("a")])
+        .with_edges(&[Edge::new("a", "b"), Edge::new("b", "c")]);
+
+    assert!(empty.is_empty());
+    assert_eq!(empty.attr("rankdir"), Some("LR"));
+    assert!(!graph.is_empty());
+    assert_eq!(graph.node_count(), 1);
+    assert_eq!(graph.edge_count(), 2);
+    assert_eq!(graph.stats().node_count, 3);
+}
+
+#[test]
+fn membership_checks() {
+    let graph = Graph::new()
+        .with_nodes(&[Node::new("a")])
+        .with_edges(&[Edge::new("a", "b")]);
+    let digraph = graph.clone().with_directed(true);
+
+    assert!(graph.contains_node("a"));
+    assert!(!graph.contains_node("b"));
+    assert!(graph.contains_edge("a", "b"));
+    assert!(graph.contains_edge("b", "a"));
+    assert!(!graph.contains_edge("a", "c"));
+    assert!(digraph.contains_edge("a", "b"));
+    assert!(!digraph.contains_edge("b", "a"));
+}
+
+#[test]
+fn edges_between_includes_parallel_edges() {
+    let edges = [
+        Edge::new("a", "b").with_attrs(&[("label", "1")]),
+        Edge::new("b", "c"),
+        Edge::new("b", "a").with_attrs(&[("label", "2")]),
+        Edge::new("a", "a"),
+    ];
+    let graph = Graph::new().with_edges(&edges);
+    let digraph = graph.clone().with_directed(true);
+
+    assert_eq!(
+        graph.edges_between("a", "b").collect::<Vec<_>>(),
+        [&edges[0], &edges[2]]
+    );
+    assert_eq!(
+        digraph.edges_between("b", "a").collect::<Vec<_>>(),
+        [&edges[2]]
+    );
+    assert_eq!(
+        graph.edges_between("a", "a").collect::<Vec<_>>(),
+        [&edges[3]]
+    );
+    assert_eq!(graph.edges_between("a", "c").count(), 0);
+}
+
+#[test]
+fn edges_of_a_node() {
+    let edges = [
+        Edge::new("c", "a"),
+        Edge::new("a", "b"),
+        Edge::new("b", "c"),
+        Edge::new("a", "a"),
+        Edge::new("d", "a"),
+    ];
+    let graph = Graph::new().with_directed(true).with_edges(&edges);
+
+    assert_eq!(
+        graph.edges_of("a").collect::<Vec<_>>(),
+        [&edges[0], &edges[1], &edges[3], &edges[4]]
+    );
+    assert_eq!(
+        graph.edges_of("a").incoming().collect::<Vec<_>>(),
+        [&edges[0], &edges[3], &edges[4]]
+    );
+    assert_eq!(
+        graph.edges_of("a").outgoing().collect::<Vec<_>>(),
+        [&edges[1], &edges[3]]
+    );
+    assert_eq!(graph.edges_of("z").count(), 0);
+}
+
+#[test]
+fn successors_and_predecessors() {
+    let edges = [
+        Edge::new("app", "lib"),
+        Edge::new("app", "log"),
+        Edge::new("lib", "log"),
+        Edge::new("app", "lib"),
+    ];
+    let digraph = Graph::new().with_directed(true).with_edges(&edges);
+    let graph = Graph::new().with_edges(&edges);
+
+    assert_eq!(
+        digraph.successors("app").collect::<Vec<_>>(),
+        ["lib", "log"]
+    );
+    assert_eq!(
+        digraph.predecessors("log").collect::<Vec<_>>(),
+        ["app", "lib"]
+    );
+    assert_eq!(digraph.predecessors("app").count(), 0);
+    assert_eq!(
+        graph.predecessors("lib").collect::<Vec<_>>(),
+        graph.neighbors("lib").collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn roots_and_leaves() {
+    let graph = Graph::new()
+        .with_directed(true)
+        .with_nodes(&[Node::new("lonely"), Node::new("test")])
+        .with_edges(&[
+            Edge::new("build", "compile"),
+            Edge::new("compile", "link"),
+            Edge::new("test", "compile"),
+            Edge::new("loop", "loop"),
+        ]);
+
+    assert_eq!(graph.roots(), ["lonely", "test", "build"]);
+    assert_eq!(graph.leaves(), ["lonely", "link"]);
+    assert_eq!(graph.clone().with_directed(false).roots(), ["lonely"]);
+}
+
+#[test]
+fn has_path_follows_direction() {
+    let mut graph = Graph::new()
+        .with_directed(true)
+        .with_nodes(&[Node::new("alone")])
+        .with_edges(&[
+            Edge::new("a", "b"),
+            Edge::new("b", "c"),
+            Edge::new("c", "a"),
+            Edge::new("c", "d"),
+            Edge::new("e", "d"),
+        ]);
+
+    assert!(graph.has_path("a", "d"));
+    assert!(graph.has_path("c", "b"));
+    assert!(!graph.has_path("d", "a"));
+    assert!(!graph.has_path("a", "e"));
+    assert!(graph.has_path("alone", "alone"));
+    assert!(!graph.has_path("alone", "a"));
+    assert!(!graph.has_path("nowhere", "a"));
+
+    graph.add_edge(Edge::new("d", "e")).unwrap();
+
+    assert!(graph.has_path("a", "e"));
+
+    graph.remove_edge("c", "d");
+
+    assert!(!graph.has_path("a", "e"));
+    assert!(!graph.clone().with_directed(false).has_path("d", "a"));
+    assert!(graph.with_directed(false).has_path("e", "d"));
+}
+
+#[test]
+fn all_simple_paths_up_to_a_length() {
+    let graph = Graph::new().with_directed(true).with_edges(&[
+        Edge::new("req", "gateway"),
+        Edge::new("gateway", "auth"),
+        Edge::new("gateway", "svc"),
+        Edge::new("auth", "svc"),
+        Edge::new("auth", "gateway"),
+        Edge::new("req", "svc"),
+    ]);
+
+    assert_eq!(
+        graph.all_simple_paths("req", "svc", 3).collect::<Vec<_>>(),
+        [
+            vec!["req", "gateway", "auth", "svc"],
+            vec!["req", "gateway", "svc"],
+            vec!["req", "svc"],
+        ]
+    );
+    assert_eq!(graph.all_simple_paths("req", "svc", 2).count(), 2);
+    assert_eq!(graph.all_simple_paths("svc", "req", 5).count(), 0);
+    assert_eq!(graph.all_simple_paths("req", "req", 5).count(), 0);
+}
+
+#[test]
+fn k_shortest_paths_by_weight() {
+    let edge = |a, b, weight| Edge::new(a, b).with_attrs(&[("weight", weight)]);
+    let graph = Graph::new().with_directed(true).with_edges(&[
+        edge("c", "d", "3"),
+        edge("c", "e", "2"),
+        edge("d", "f", "4"),
+        edge("e", "d", "1"),
+        edge("e", "f", "2"),
+        edge("e", "g", "3"),
+        edge("f", "g", "2"),
+        edge("f", "h", "1"),
+        edge("g", "h", "2"),
+    ]);
+
+    assert_eq!(
+        graph.k_shortest_paths("c", "h", 3),
+        [
+            (vec!["c", "e", "f", "h"], 5.0),
+            (vec!["c", "e", "g", "h"], 7.0),
+            (vec!["c", "d", "f", "h"], 8.0),
+        ]
+    );
+    assert_eq!(graph.k_shortest_paths("c", "h", 20).len(), 7);
+    assert_eq!(graph.k_shortest_paths("h", "c", 3), []);
+    assert_eq!(graph.k_shortest_paths("c", "h", 0), []);
+}
+
+#[test]
+fn max_flow_with_min_cut() {
+    let edge = |a, b, capacity| Edge::new(a, b).with_capacity(capacity).unwrap();
+    let graph = Graph::new().with_directed(true).with_edges(&[
+        edge("s", "v1", 16.0),
+        edge("s", "v2", 13.0),
+        edge("v2", "v1", 4.0),
+        edge("v1", "v3", 12.0),
+        edge("v3", "v2", 9.0),
+        edge("v2", "v4", 14.0),
+        edge("v4", "v3", 7.0),
+        edge("v3", "t", 20.0),
+        edge("v4", "t", 4.0),
+    ]);
+
+    let (flow, cut) = graph.max_flow("s", "t");
+
+    assert_eq!(flow, 23.0);
+    assert_eq!(
+        cut,
+        [
+            &edge("v1", "v3", 12.0),
+            &edge("v4", "v3", 7.0),
+            &edge("v4", "t", 4.0),
+        ]
+    );
+    assert_eq!(graph.max_flow("t", "s"), (0.0, vec![]));
+}
+
+#[test]
+fn max_flow_through_undirected_edges_defaults_to_unit_capacity() {
+    let graph = Graph::new().with_edges(&[
+        Edge::new("b", "a"),
+        Edge::new("a", "c"),
+        Edge::new("c", "b"),
+    ]);
+
+    assert_eq!(
+        graph.max_flow("a", "b"),
+        (2.0, vec![&Edge::new("b", "a"), &Edge::new("a", "c")])
+    );
+    assert_eq!(graph.max_flow("a", "a"), (0.0, vec![]));
+}
+
+#[test]
+fn maximum_matching_assigns_workers_to_jobs() {
+    let graph = Graph::new().with_directed(true).with_edges(&[
+        Edge::new("a", "x"),
+        Edge::new("a", "y"),
+        Edge::new("b", "x"),
+        Edge::new("c", "y"),
+        Edge::new("c", "z"),
+    ]);
+
+    assert_eq!(
+        graph.maximum_matching(),
+        [
+            &Edge::new("a", "y"),
+            &Edge::new("b", "x"),
+            &Edge::new("c", "z")
+        ]
+    );
+}
+
+#[test]
+fn maximum_matching_through_odd_cycles() {
+    // Two triangles joined by a bridge, each with a tail: a perfect matching has to
+    // look past the odd cycles.
+    let pairs = [
+        ("1", "2"),
+        ("2", "3"),
+        ("3", "1"),
+        ("3", "4"),
+        ("4", "5"),
+        ("5", "6"),
+        ("6", "4"),
+        ("1", "7"),
+        ("6", "8"),
+    ];
+    let edges = pairs
+        .iter()
+        .map(|(a, b)| Edge::new(a, b))
+        .collect::<Vec<_>>();
+    let graph = Graph::new()
+        .with_edges(&edges)
+        .with_edges(&[Edge::new("9", "9")]);
+
+    let matching = graph.maximum_matching();
+
+    assert_eq!(matching.len(), 4);
+    let mut covered = matching
+        .iter()
+        .flat_map(|edge| [edge.node1(), edge.node2()])
+        .collect::<Vec<_>>();
+    covered.sort_unstable();
+    assert_eq!(covered, ["1", "2", "3", "4", "5", "6", "7", "8"]);
+}
+
+#[test]
+fn greedy_coloring_keeps_neighbors_apart() {
+    let graph = Graph::new().with_nodes(&[Node::new("d")]).with_edges(&[
+        Edge::new("a", "b"),
+        Edge::new("b", "c"),
+        Edge::new("c", "a"),
+        Edge::new("c", "d"),
+        Edge::new("d", "d"),
+    ]);
+
+    assert_eq!(
+        graph.greedy_coloring(),
+        [("d", 0), ("a", 0), ("b", 1), ("c", 2)]
+    );
+}
+
+#[test]
+fn coloring_written_back_as_fillcolor() {
+    let graph = Graph::new()
+        .with_nodes(&[Node::new("a").with_styles(&[Style::Bold])])
+        .with_edges(&[Edge::new("a", "b"), Edge::new("b", "c")])
+        .with_coloring(&["red", "blue"]);
+
+    assert_eq!(
+        graph.nodes(),
+        [
+            Node::new("a").with_attrs(&[("style", "bold,filled"), ("fillcolor", "red")]),
+            Node::new("b").with_attrs(&[("style", "filled"), ("fillcolor", "blue")]),
+            Node::new("c").with_attrs(&[("style", "filled"), ("fillcolor", "red")]),
+        ]
+    );
+    assert_eq!(
+        Graph::new()
+            .with_edges(&[Edge::new("a", "b")])
+            .with_coloring(&[])
+            .nodes(),
+        []
+    );
+}
+
+#[test]
+fn articulation_points_and_bridges() {
+    // A triangle a-b-c hanging off d by one link, and e joined to d twice.
+    let graph = Graph::new().with_directed(true).with_edges(&[
+        Edge::new("a", "b"),
+        Edge::new("b", "c"),
+        Edge::new("c", "a"),
+        Edge::new("c", "d"),
+        Edge::new("d", "e"),
+        Edge::new("e", "d"),
+        Edge::new("e", "f"),
+        Edge::new("f", "f"),
+    ]);
+
+    assert_eq!(graph.articulation_points(), ["c", "d", "e"]);
+    assert_eq!(
+        graph.bridges(),
+        [&Edge::new("c", "d"), &Edge::new("e", "f")]
+    );
+    assert!(Graph::new()
+        .with_edges(&[
+            Edge::new("a", "b"),
+            Edge::new("b", "c"),
+            Edge::new("c", "a")
+        ])
+        .bridges()
+        .is_empty());
+}
+
+#[test]
+fn condensation_collapses_cycles() {
+    let graph = Graph::new()
+        .with_directed(true)
+        .with_nodes(&[Node::new("e").with_attrs(&[("shape", "box")])])
+        .with_edges(&[
+            Edge::new("a", "b"),
+            Edge::new("b", "a"),
+            Edge::new("b", "c"),
+            Edge::new("c", "d"),
+            Edge::new("d", "c"),
+            Edge::new("a", "d"),
+            Edge::new("d", "e"),
+        ]);
+
+    let expected = Graph::new()
+        .with_directed(true)
+        .with_nodes(&[
+            Node::new("e").with_attrs(&[("shape", "box")]),
+            Node::new("a").with_attrs(&[("label", "a, b")]),
+            Node::new("c").with_attrs(&[("label", "c, d")]),
+        ])
+        .with_edges(&[Edge::new("a", "c"), Edge::new("c", "e")]);
+
+    assert_eq!(graph.condensation(), expected);
+}
+
+#[test]
+fn condensation_with_custom_merge() {
+    let graph = Graph::new().with_directed(true).with_edges(&[
+        Edge::new("a", "b"),
+        Edge::new("b", "a"),
+        Edge::new("b", "c"),
+    ]);
+
+    let mut count = 0;
+    let condensed = graph.condensation_with(|members| {
+        count += 1;
+        Node::new(&format!("scc{count}")).with_attrs(&[("size", &members.len().to_string())])
+    });
+
+    assert_eq!(
+        condensed.to_dot(),
+        "digraph {\n    scc1 [size=\"2\"]\n    scc2 [size=\"1\"]\n    scc1 -> scc2\n}\n"
+    );
+}
+
+#[test]
+fn longest_path_layers_of_a_dag() {
+    let graph = Graph::new().with_directed(true).with_edges(&[
+        Edge::new("a", "b"),
+        Edge::new("b", "c"),
+        Edge::new("a", "c"),
+        Edge::new("d", "c"),
+    ]);
+
+    assert_eq!(
+        graph.longest_path_layers(),
+        Some(vec![("a", 0), ("b", 1), ("c", 2), ("d", 0)])
+    );
+    assert_eq!(
+        graph
+            .with_edges(&[Edge::new("c", "a")])
+            .longest_path_layers(),
+        None
+    );
+    assert_eq!(
+        Graph::new()
+            .with_edges(&[Edge::new("a", "b")])
+            .longest_path_layers(),
+        None
+    );
+}
+
+#[test]
+fn layer_ranks_written_as_subgraphs() {
+    let graph = Graph::new()
+        .with_directed(true)
+        .with_edges(&[Edge::new("a", "b"), Edge::new("c", "b")])
+        .with_layer_ranks();
+
+    assert_eq!(
+        graph.subgraphs(),
+        [
+            Subgraph::new("layer0")
+                .with_attrs(&[("rank", "same")])
+                .with_nodes(&["a", "c"]),
+            Subgraph::new("layer1")
+                .with_attrs(&[("rank", "same")])
+                .with_nodes(&["b"]),
+        ]
+    );
+}
+
+#[test]
+fn topological_generations_in_waves() {
+    let graph = Graph::new()
+        .with_directed(true)
+        .with_nodes(&[Node::new("docs")])
+        .with_edges(&[
+            Edge::new("core", "net"),
+            Edge::new("core", "ui"),
+            Edge::new("net", "app"),
+            Edge::new("ui", "app"),
+            Edge::new("core", "app"),
+        ]);
+
+    assert_eq!(
+        graph.topological_generations(),
+        Some(vec![vec!["docs", "core"], vec!["net", "ui"], vec!["app"]])
+    );
+    assert_eq!(
+        graph
+            .with_edges(&[Edge::new("app", "app")])
+            .topological_generations(),
+        None
+    );
+}
+
+#[test]
+fn is_dag_needs_direction_and_no_cycles() {
+    let mut graph = Graph::new().with_directed(true).with_edges(&[
+        Edge::new("a", "b"),
+        Edge::new("b", "c"),
+        Edge::new("a", "c"),
+    ]);
+
+    assert!(graph.is_dag());
+
+    graph.add_edge(Edge::new("c", "a")).unwrap();
+
+    assert!(!graph.is_dag());
+    assert!(!Graph::new()
+        .with_directed(true)
+        .with_edges(&[Edge::new("a", "a")])
+        .is_dag());
+    assert!(!generators::path(3).is_dag());
+    assert!(Graph::new().with_directed(true).is_dag());
+}
+
+#[test]
+fn spanning_forest_keeps_one_tree_per_component() {
+    let graph = Graph::new()
+        .with_nodes(&[Node::new("z").with_attrs(&[("shape", "box")])])
+        .with_edges(&[
+            Edge::new("a", "b"),
+            Edge::new("b", "c"),
+            Edge::new("c", "a"),
+            Edge::new("a", "b"),
+            Edge::new("c", "c"),
+            Edge::new("d", "e"),
+        ]);
+
+    let forest = graph.spanning_forest();
+
+    assert_eq!(forest.nodes(), graph.nodes());
+    assert_eq!(
+        forest.edges(),
+        [
+            Edge::new("a", "b"),
+            Edge::new("b", "c"),
+            Edge::new("d", "e")
+        ]
+    );
+    assert_eq!(forest.stats().components, graph.stats().components);
+}
+
+#[test]
+fn eulerian_path_takes_every_edge_once() {
+    // The house of Nikolaus: a square with both diagonals and a roof on top. Only the
+    // bottom corners a and b have odd degree.
+    let graph = Graph::new().with_edges(&[
+        Edge::new("a", "b"),
+        Edge::new("a", "c"),
+        Edge::new("a", "d"),
+        Edge::new("b", "c"),
+        Edge::new("b", "d"),
+        Edge::new("c", "d"),
+        Edge::new("c", "e"),
+        Edge::new("d", "e"),
+    ]);
+
+    let path = graph.eulerian_path().unwrap();
+
+    assert_eq!(path.len(), 8);
+    let mut at = "a";
+    for edge in path {
+        at = if edge.node1() == at {
+            edge.node2()
+        } else {
+            assert_eq!(edge.node2(), at);
+            edge.node1()
+        };
+    }
+    assert_eq!(at, "b");
+    assert_eq!(generators::star(4).eulerian_path(), None);
+}
+
+#[test]
+fn eulerian_circuit_follows_direction() {
+    let graph = Graph::new().with_directed(true).with_edges(&[
+        Edge::new("a", "b"),
+        Edge::new("b", "c"),
+        Edge::new("c", "a"),
+        Edge::new("a", "a"),
+    ]);
+
+    assert_eq!(
+        graph.eulerian_path(),
+        Some(vec![
+            &Edge::new("a", "b"),
+            &Edge::new("b", "c"),
+            &Edge::new("c", "a"),
+            &Edge::new("a", "a"),
+        ])
+    );
+    assert_eq!(
+        graph.with_edges(&[Edge::new("d", "e")]).eulerian_path(),
+        None
+    );
+    assert_eq!(Graph::new().eulerian_path(), Some(vec![]));
+}
+
+#[test]
+fn closeness_centrality() {
+    assert_eq!(
+        centrality::closeness(&generators::star(4)),
+        [("0", 1.0), ("1", 0.6), ("2", 0.6), ("3", 0.6)]
+    );
+
+    let graph = Graph::new()
+        .with_directed(true)
+        .with_nodes(&[Node::new("c")])
+        .with_edges(&[Edge::new("a", "b")]);
+
+    assert_eq!(
+        centrality::closeness(&graph),
+        [("c", 0.0), ("a", 0.5), ("b", 0.0)]
+    );
+}
+
+#[test]
+fn diameter_radius_and_eccentricity() {
+    let graph = generators::path(5);
+
+    assert_eq!(graph.eccentricity("0"), Some(4));
+    assert_eq!(graph.eccentricity("2"), Some(2));
+    assert_eq!(graph.eccentricity("9"), None);
+    assert_eq!(graph.diameter(), Some(4));
+    assert_eq!(graph.radius(), Some(2));
+}
+
+#[test]
+fn distance_metrics_need_every_node_reachable() {
+    let graph = Graph::new()
+        .with_directed(true)
+        .with_edges(&[Edge::new("a", "b"), Edge::new("b", "c")]);
+
+    assert_eq!(graph.eccentricity("a"), Some(2));
+    assert_eq!(graph.eccentricity("c"), None);
+    assert_eq!(graph.diameter(), None);
+    assert_eq!(graph.radius(), None);
+    assert_eq!(Graph::new().diameter(), None);
+}
+
+#[test]
+fn degree_assortativity() {
+    assert_eq!(generators::star(5).assortativity(), Some(-1.0));
+
+    let path = generators::path(4).assortativity().unwrap();
+
+    assert!((path + 0.5).abs() < 1e-12);
+    assert_eq!(generators::cycle(5).assortativity(), None);
+    assert_eq!(Graph::new().assortativity(), None);
+}
+
+#[test]
+fn label_propagation_finds_dense_groups() {
+    // Two four-cliques joined by a single edge.
+    let mut edges = Vec::new();
+    for group in [["a", "b", "c", "d"], ["w", "x", "y", "z"]] {
+        for (i, node1) in group.iter().enumerate() {
+            for node2 in &group[i + 1..] {
+                edges.push(Edge::new(node1, node2));
+            }
+        }
+    }
+    edges.push(Edge::new("d", "w"));
+    let graph = Graph::new().with_edges(&edges);
+
+    for seed in 0..10 {
+        let communities = graph.label_propagation(seed);
+
+        assert_eq!(
+            communities,
+            [
+                ("a", 0),
+                ("b", 0),
+                ("c", 0),
+                ("d", 0),
+                ("w", 1),
+                ("x", 1),
+                ("y", 1),
+                ("z", 1),
+            ]
+        );
+    }
+    assert_eq!(graph.label_propagation(7), graph.label_propagation(7));
+}
+
+#[test]
+fn communities_written_as_clusters() {
+    let graph = Graph::new()
+        .with_nodes(&[Node::new("lonely")])
+        .with_edges(&[Edge::new("a", "b")])
+        .cluster_by_community(1);
+
+    assert_eq!(
+        graph.subgraphs(),
+        [
+            Subgraph::new("cluster_0").with_nodes(&["lonely"]),
+            Subgraph::new("cluster_1").with_nodes(&["a", "b"]),
+        ]
+    );
+}
+
+#[test]
+fn sampled_nodes_keep_the_edges_between_them() {
+    let graph = generators::complete(10)
+        .with_subgraphs(&[Subgraph::new("cluster_low").with_nodes(&["0", "1", "2", "3", "4"])]);
+
+    let sample = graph.sample_nodes(4, 42);
+
+    assert_eq!(sample.node_count(), 4);
+    assert_eq!(sample.edge_count(), 6);
+    assert_eq!(sample, graph.sample_nodes(4, 42));
+    assert!(sample.subgraphs().iter().all(|subgraph| subgraph
+        .nodes()
+        .iter()
+        .all(|name| sample.contains_node(name))));
+    assert_eq!(graph.sample_nodes(50, 1).node_count(), 10);
+}
+
+#[test]
+fn sampled_edges_bring_their_endpoints() {
+    let graph = generators::path(10);
+
+    let sample = graph.sample_edges(3, 7);
+
+    assert_eq!(sample.edge_count(), 3);
+    assert!(sample
+        .edges()
+        .iter()
+        .all(|edge| sample.contains_node(edge.node1()) && sample.contains_node(edge.node2())));
+    assert_eq!(sample, graph.sample_edges(3, 7));
+}
+
+#[test]
+fn snowball_sample_spreads_from_the_start() {
+    let graph = generators::path(6);
+
+    let sample = graph.snowball_sample("2", 3);
+
+    assert_eq!(
+        sample.nodes(),
+        [Node::new("1"), Node::new("2"), Node::new("3")]
+    );
+    assert_eq!(sample.edges(), [Edge::new("1", "2"), Edge::new("2", "3")]);
+    assert!(graph.snowball_sample("9", 3).is_empty());
+}
+
+#[test]
+fn partition_into_balanced_parts() {
+    assert_eq!(
+        generators::path(9)
+            .partition(3)
+            .into_iter()
+            .map(|(_, part)| part)
+            .collect::<Vec<_>>(),
+        [0, 0, 0, 1, 1, 1, 2, 2, 2]
+    );
+
+    let graph = generators::complete(4)
+        .with_graph(&generators::complete(4).with_namespace("b"))
+        .with_edges(&[Edge::new("3", "b.0")]);
+    let parts = graph.partition(2);
+
+    assert_eq!(parts.iter().filter(|(_, part)| *part == 0).count(), 4);
+    assert!(parts
+        .iter()
+        .all(|(name, part)| (*part == 1) == name.starts_with('b')));
+    assert_eq!(generators::path(2).partition(5), [("0", 0), ("1", 1)]);
+}
+
+#[test]
+fn partition_written_back_as_attr() {
+    let graph = Graph::new()
+        .with_nodes(&[Node::new("a").with_attrs(&[("shape", "box")])])
+        .with_edges(&[Edge::new("a", "b"), Edge::new("c", "d")])
+        .with_partition(2)
+        .cluster_by_attr("partition");
+
+    assert_eq!(
+        graph.nodes(),
+        [
+            Node::new("a").with_attrs(&[("shape", "box"), ("partition", "0")]),
+            Node::new("b").with_attrs(&[("partition", "0")]),
+            Node::new("c").with_attrs(&[("partition", "1")]),
+            Node::new("d").with_attrs(&[("partition", "1")]),
+        ]
+    );
+    assert_eq!(graph.subgraphs().len(), 2);
+}
+
+#[test]
+fn with_attr_sets_a_single_attribute() {
+    let graph = Graph::new()
+        .with_attr("rankdir", "LR")
+        .with_attr("label", format!("{} nodes", 2))
+        .with_nodes(&[Node::new("a").with_attr("shape", "box")])
+        .with_edges(&[Edge::new("a", "b").with_attr("color", "red")]);
+
+    assert_eq!(graph.attr("rankdir"), Some("LR"));
+    assert_eq!(graph.attr("label"), Some("2 nodes"));
+    assert_eq!(graph.node("a").unwrap().attr("shape"), Some("box"));
+    assert_eq!(graph.edges()[0].attr("color"), Some("red"));
+}
+
+#[cfg(feature = "derive")]
+#[test]
+fn derived_attrs_from_struct_fields() {
+    use dot_dsl::graph::IntoAttrs;
+
+    #[derive(IntoAttrs)]
+    struct Service {
+        #[attr(rename = "label")]
+        name: String,
+        #[attr(format = "{:.1}")]
+        penwidth: f64,
+        fillcolor: Option<&'static str>,
+        tooltip: Option<String>,
+        #[attr(skip)]
+        #[allow(dead_code)]
+        owner: std::collections::HashMap<String, Vec<u8>>,
+        pub r#shape: &'static str,
+    }
+
+    let service = Service {
+        name: "auth".to_string(),
+        penwidth: 2.0,
+        fillcolor: Some("gold"),
+        tooltip: None,
+        owner: HashMap::new(),
+        shape: "box",
+    };
+
+    assert_eq!(
+        service.to_attrs(),
+        [
+            ("label", "auth".to_string()),
+            ("penwidth", "2.0".to_string()),
+            ("fillcolor", "gold".to_string()),
+            ("shape", "box".to_string()),
+        ]
+    );
+    assert_eq!(
+        Node::new("auth").with_attrs_from(&service),
+        Node::new("auth").with_attrs(&[
+            ("label", "auth"),
+            ("penwidth", "2.0"),
+            ("fillcolor", "gold"),
+            ("shape", "box"),
+        ])
+    );
+}
+
+#[test]
+fn nodes_and_edges_from_plain_tuples() {
+    let graph = Graph::new()
+        .with_nodes(&["a", "b"])
+        .with_edges(&[("a", "b"), ("b", "c")])
+        .with_edges(&[("c", "a", &[("color", "red")][..])]);
+
+    let expected = Graph::new()
+        .with_nodes(&[Node::new("a"), Node::new("b")])
+        .with_edges(&[
+            Edge::new("a", "b"),
+            Edge::new("b", "c"),
+            Edge::new("c", "a").with_attrs(&[("color", "red")]),
+        ]);
+
+    assert_eq!(graph, expected);
+
+    let edge: Edge = ("x", "y").into();
+
+    assert_eq!(edge, Edge::new("x", "y"));
+    assert_eq!(Node::from("x"), Node::new("x"));
+}
+
+#[test]
+fn ensure_nodes_declares_edge_endpoints() {
+    let graph = Graph::new()
+        .with_nodes(&[Node::new("b").with_attrs(&[("shape", "box")])])
+        .with_edges(&[Edge::new("a", "b"), Edge::new("b", "c")]);
+
+    assert_eq!(graph.node("a"), None);
+
+    let graph = graph.ensure_nodes();
+
+    assert_eq!(
+        graph.nodes(),
+        [
+            Node::new("b").with_attrs(&[("shape", "box")]),
+            Node::new("a"),
+            Node::new("c"),
+        ]
+    );
+    assert_eq!(graph.node("a"), Some(&Node::new("a")));
+    assert_eq!(graph.clone().ensure_nodes(), graph);
+}
+
+#[test]
+fn implicit_nodes_can_be_omitted_or_declared() {
+    let graph = Graph::new()
+        .with_nodes(&[
+            Node::new("a"),
+            Node::new("b").with_attrs(&[("shape", "box")]),
+            Node::new("lonely"),
+        ])
+        .with_edges(&[Edge::new("a", "b"), Edge::new("b", "c")]);
+
+    assert_eq!(graph.to_dot_with(DotOptions::new()), graph.to_dot());
+
+    let omitted = DotOptions::new().with_implicit_nodes(ImplicitNodes::Omit);
+
+    assert_eq!(
+        graph.to_dot_with(omitted),
+        concat!(
+            "graph {\n",
+            "    b [shape=\"box\"]\n",
+            "    lonely\n",
+            "    a -- b\n",
+            "    b -- c\n",
+            "}\n",
+        )
+    );
+
+    let declared = DotOptions::new().with_implicit_nodes(ImplicitNodes::Declare);
+
+    assert_eq!(
+        graph.to_dot_with(declared),
+        concat!(
+            "graph {\n",
+            "    a\n",
+            "    b [shape=\"box\"]\n",
+            "    lonely\n",
+            "    c\n",
+            "    a -- b\n",
+            "    b -- c\n",
+            "}\n",
+        )
+    );
+}
+
+#[test]
+fn compact_dot_chains_edges_on_one_line() {
+    let graph = Graph::new()
+        .with_directed(true)
+        .with_nodes(&[
+            Node::new("a").with_attrs(&[("color", "red")]),
+            Node::new("b c").with_comment("spaced"),
+        ])
+        .with_edges(&[
+            Edge::new("a", "b c"),
+            Edge::new("b c", "d"),
+            Edge::new("d", "e").with_attrs(&[("style", "dashed")]),
+            Edge::new("e", "f").with_attrs(&[("style", "dashed")]),
+        ])
+        .with_attrs(&[("rankdir", "LR")]);
+
+    let dot = graph.to_dot_with(DotOptions::new().with_compact(true));
+
+    assert_eq!(
+        dot,
+        "digraph{graph[rankdir=LR];a[color=red];\"b c\";a->\"b c\"->d;d->e->f[style=dashed]}"
+    );
+
+    let parsed = Graph::from_dot(&dot).expect("compact DOT parses");
+
+    assert_eq!(parsed.node("a"), graph.node("a"));
+    assert_eq!(parsed.edges().len(), 4);
+}
+
+#[test]
+fn output_keeps_insertion_order_until_sorted() {
+    let names = ["m", "c", "x", "a", "q", "b", "z", "k"];
+    let nodes = names.map(Node::new);
+    let edges = names
+        .windows(2)
+        .map(|w| Edge::new(w[1], w[0]))
+        .collect::<Vec<_>>();
+    let graph = Graph::new().with_nodes(&nodes).with_edges(&edges);
+
+    let statements = |graph: &Graph| {
+        graph
+            .to_dot()
+            .lines()
+            .skip(1)
+            .take(names.len())
+            .map(str::trim)
+            .map(String::from)
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(statements(&graph), names);
+    assert_eq!(graph.neighbors("a").count(), 2);
+
+    let sorted = graph
+        .clone()
+        .sort_nodes_by(|a, b| a.name().cmp(b.name()))
+        .sort_edges_by(|a, b| a.node1().cmp(b.node1()));
+
+    assert_eq!(
+        statements(&sorted),
+        ["a", "b", "c", "k", "m", "q", "x", "z"]
+    );
+    assert_eq!(sorted.edges()[0], Edge::new("a", "x"));
+    assert_eq!(sorted.node("q"), Some(&Node::new("q")));
+
+    let mut neighbors = sorted.neighbors("a").collect::<Vec<_>>();
+    neighbors.sort();
+
+    assert_eq!(neighbors, ["q", "x"]);
+}
+
+#[test]
+fn graph_attrs_as_a_list_or_assignments() {
+    let graph = Graph::new().with_attrs(&[("rankdir", "LR"), ("bgcolor", "white")]);
+
+    let assignments = DotOptions::new().with_graph_attrs(GraphAttrStyle::Assignments);
+    let dot = graph.to_dot_with(assignments);
+
+    assert_eq!(
+        dot,
+        "graph {\n    bgcolor=\"white\"\n    rankdir=\"LR\"\n}\n"
+    );
+    assert_eq!(
+        graph.to_dot_with(assignments.with_compact(true)),
+        "graph{bgcolor=white;rankdir=LR}"
+    );
+    assert_eq!(Graph::from_dot(&dot), Ok(graph.clone()));
+    assert_eq!(Graph::from_dot(&graph.to_dot()), Ok(graph));
+}
+
+#[test]
+fn esc_string_escapes_text_but_not_escapes() {
+    let label = EscString::new()
+        .node_name()
+        .end_line(Justify::Left)
+        .text("C:\\temp \"x\"\nnext")
+        .end_line(Justify::Right);
+
+    assert_eq!(label.as_str(), r#"\N\lC:\\temp "x"\nnext\r"#);
+
+    let graph = Graph::new().with_nodes(&[Node::new("a").with_attr("label", label)]);
+    let dot = graph.to_dot();
+
+    assert_eq!(
+        dot,
+        "graph {\n    a [label=\"\\N\\lC:\\\\temp \\\"x\\\"\\nnext\\r\"]\n}\n"
+    );
+    assert_eq!(Graph::from_dot(&dot), Ok(graph));
+    assert_eq!(
+        EscString::raw(r"\G").to_string(),
+        EscString::new().graph_name().to_string()
+    );
+}
+
+#[test]
+fn multi_line_labels_are_joined_and_escaped() {
+    let label = Label::lines(&["fn main()", "  \"hi\\n\""]);
+
+    assert_eq!(label.to_string(), r#"fn main()\n  "hi\\n""#);
+    assert_eq!(
+        label.clone().left_justified().to_string(),
+        r#"fn main()\l  "hi\\n"\l"#
+    );
+    assert_eq!(
+        Label::lines(&["a\nb", "c"]).right_justified().to_string(),
+        r"a\rb\rc\r"
+    );
+
+    let spaced = Label::lines(&["a", "", "b\r\nc"]);
+
+    assert_eq!(spaced.to_string(), r"a\n\nb\nc");
+    assert_eq!(spaced.left_justified().to_string(), r"a\l\lb\lc\l");
+
+    let node = Node::new("code").with_attr("label", label.left_justified());
+
+    assert_eq!(node.attr("label"), Some(r#"fn main()\l  "hi\\n"\l"#));
+}